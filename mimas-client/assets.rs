@@ -239,6 +239,9 @@ pub struct UiColors {
 	pub block_selection_color :TextureId,
 	pub block_selection_color_digging :TextureId,
 	pub crosshair_color :TextureId,
+	pub wear_bar_color :TextureId,
+	pub wear_bar_background_color :TextureId,
+	pub hp_bar_color :TextureId,
 	// Player colors
 	pub color_body :TextureId,
 	pub color_head :TextureId,
@@ -255,6 +258,9 @@ impl UiColors {
 			block_selection_color : assets.add_color([0.0, 0.0, 0.3, 0.5]),
 			block_selection_color_digging : assets.add_color([0.0, 0.0, 0.6, 0.7]),
 			crosshair_color : assets.add_color([0.8, 0.8, 0.8, 0.85]),
+			wear_bar_color : assets.add_color([0.2, 0.8, 0.2, 0.9]),
+			wear_bar_background_color : assets.add_color([0.1, 0.1, 0.1, 0.85]),
+			hp_bar_color : assets.add_color([0.8, 0.1, 0.1, 0.9]),
 			color_body : assets.add_color([0.3, 0.3, 0.5, 1.0]),
 			color_head : assets.add_color([0.94, 0.76, 0.49, 1.0]),
 			color_eyes : assets.add_color([0.2, 0.2, 0.2, 1.0]),
@@ -1,6 +1,9 @@
 use mimas_common::map::{Map, MapBackend, ClientMap,
-	CHUNKSIZE, MapBlock, MetadataEntry};
-use mimas_common::player::PlayerMode;
+	CHUNKSIZE, MapBlock, MetadataEntry, FurnaceState, ItemEntity,
+	SIGN_TEXT_MAX_LEN};
+use mimas_common::player::{PlayerMode, MAX_HP};
+use mimas_common::facedir::Facedir;
+use mimas_common::protocol::ChatKind;
 use glium::{glutin, Surface, VertexBuffer};
 use glium::texture::SrgbTexture2dArray;
 use glium::uniforms::{MagnifySamplerFilter, SamplerWrapFunction};
@@ -14,7 +17,7 @@ use nalgebra::{Vector3, Matrix4, Point3, Translation3, Rotation3};
 use num_traits::identities::Zero;
 use glium_glyph::GlyphBrush;
 use glium_glyph::glyph_brush::{
-	rusttype::Font, Section,
+	rusttype::Font, SectionText, VariedSection,
 };
 use std::collections::{HashMap, VecDeque};
 use std::time::{Instant, Duration};
@@ -22,19 +25,20 @@ use std::thread;
 use std::sync::mpsc::{channel, Receiver};
 use std::sync::Arc;
 use frustum_query::frustum::Frustum;
-use collide::collide;
 use srp::client::SrpClient;
 use srp::groups::G_4096;
 use sha2::Sha256;
 use rand::RngCore;
 
-use mimas_common::{btchn, ServerToClientMsg, ClientToServerMsg};
+use mimas_common::{btchn, time_of_day, ServerToClientMsg, ClientToServerMsg};
 use mimas_common::generic_net::NetworkClientConn;
+use mimas_common::collide::{collide, player_collision_pos};
 use mimas_common::local_auth::{PlayerPwHash, HashParams};
 use mimas_common::config::Config;
 use mimas_common::map_storage::{PlayerPosition, PlayerIdPair};
 use mimas_common::inventory::{SelectableInventory, InventoryPos, InventoryLocation};
-use mimas_common::game_params::{GameParamsHdl, DigGroup, ToolGroup};
+use mimas_common::game_params::GameParamsHdl;
+use mimas_common::interaction::{self, dig_time, player_overlaps_block};
 
 use mimas_meshgen::{Vertex, mesh_for_chunk, push_block,
 	BlockTextureIds, TextureIdCache, ChunkMesh};
@@ -43,9 +47,9 @@ use assets::{Assets, UiColors};
 
 use ui::{render_menu, square_mesh, ChatWindow, ChatWindowEvent,
 	ChestMenu, InventoryMenu, IDENTITY, render_inventory_hud,
+	render_health_bar,
 	SwapOrCraftCommand, SwapCommand};
 
-use voxel_walk::VoxelWalker;
 
 type MeshResReceiver = Receiver<(Vector3<isize>, ChunkMesh)>;
 
@@ -63,6 +67,9 @@ const FRAGMENT_SHADER_SRC :&str = include_str!("fragment-shader.glsl");
 
 const KENPIXEL :&[u8] = include_bytes!("../assets/kenney-pixel.ttf");
 
+/// The number of chat messages that are kept
+const CHAT_MSGS_LIMIT :usize = 10;
+
 enum AuthState {
 	WaitingForBpub(String, SrpClient<'static, Sha256>),
 	Authenticated,
@@ -88,6 +95,9 @@ pub struct Game<C :NetworkClientConn> {
 	selected_pos :Option<(Vector3<isize>, Vector3<isize>)>,
 	sel_inventory :SelectableInventory,
 	craft_inv :SelectableInventory,
+	hp :u16,
+	time_of_day :f32,
+	time_speed :f32,
 
 	last_pos :Option<PhysicalPosition<f64>>,
 
@@ -99,8 +109,10 @@ pub struct Game<C :NetworkClientConn> {
 	grab_cursor :bool,
 	grabbing_cursor :bool,
 	has_focus :bool,
-	chat_msgs :VecDeque<String>,
+	chat_msgs :VecDeque<(ChatKind, String)>,
 	chat_window :Option<ChatWindow>,
+	/// Position of the sign whose text is being edited in the chat window
+	sign_pos :Option<Vector3<isize>>,
 	inventory_menu :Option<InventoryMenu>,
 	chest_menu :Option<ChestMenu>,
 	menu_enabled :bool,
@@ -118,6 +130,9 @@ macro_rules! maybe_inventory_change {
 		if let Some(SwapOrCraftCommand::Craft) = $command {
 			let msg = ClientToServerMsg::Craft;
 			let _ = $this.srv_conn.send(msg);
+		} else if let Some(SwapOrCraftCommand::CraftMax) = $command {
+			let msg = ClientToServerMsg::CraftMax;
+			let _ = $this.srv_conn.send(msg);
 		} else if let Some(SwapOrCraftCommand::Swap(cmd)) = $command {
 			fn ind_to_loc(ind :usize) -> InventoryLocation {
 				match ind {
@@ -183,11 +198,8 @@ macro_rules! maybe_chest_inventory_change {
 		}
 
 		let mut chest_meta = $this.map.get_blk_meta_mut($m.chest_pos()).unwrap();
-		if Some($m.chest_inv()) != chest_meta.get().map(|v| {
-			let MetadataEntry::Inventory(inv) = v;
-			inv
-		}) {
-			chest_meta.set(MetadataEntry::Inventory($m.chest_inv().clone()));
+		if Some($m.chest_inv()) != chest_meta.get().and_then(|v| v.inventory()) {
+			chest_meta.set($m.chest_meta());
 
 			// TODO maybe do some checks to ensure that $command is Some?
 		}
@@ -275,6 +287,9 @@ impl<C :NetworkClientConn> Game<C> {
 			selected_pos : None,
 			sel_inventory : SelectableInventory::new(),
 			craft_inv : SelectableInventory::crafting_inv(),
+			hp : MAX_HP,
+			time_of_day : 0.5,
+			time_speed : 0.0,
 
 			last_pos : None,
 			last_frame_time : Instant::now(),
@@ -287,6 +302,7 @@ impl<C :NetworkClientConn> Game<C> {
 			has_focus : false,
 			chat_msgs : VecDeque::new(),
 			chat_window : None,
+			sign_pos : None,
 			inventory_menu : None,
 			chest_menu : None,
 			menu_enabled : false,
@@ -346,6 +362,8 @@ impl<C :NetworkClientConn> Game<C> {
 				self.camera.pos.map(|v| v as isize), 4, 2);
 			self.render(&mut glyph_brush);
 			let float_delta = self.update_fps();
+			self.time_of_day = time_of_day::advance(self.time_of_day,
+				float_delta, self.time_speed);
 			let close = self.handle_events(event_loop);
 			self.handle_mouse_buttons(float_delta);
 			if !self.in_background() {
@@ -392,6 +410,10 @@ impl<C :NetworkClientConn> Game<C> {
 						println!("Log-In failed. Reason: {}", reason);
 						break 'game_main_loop;
 					},
+					ServerToClientMsg::Disconnect(reason) => {
+						println!("Disconnected by the server. Reason: {}", reason);
+						break 'game_main_loop;
+					},
 					ServerToClientMsg::GameParams(params) => {
 						let params_arc = Arc::new(params);
 
@@ -431,6 +453,13 @@ impl<C :NetworkClientConn> Game<C> {
 					ServerToClientMsg::SetCraftInventory(inv) => {
 						self.craft_inv = inv;
 					},
+					ServerToClientMsg::SetHp(hp) => {
+						self.hp = hp;
+					},
+					ServerToClientMsg::SetTimeOfDay(time, speed) => {
+						self.time_of_day = time;
+						self.time_speed = speed;
+					},
 					ServerToClientMsg::SetModes(modes) => {
 						self.camera.fly_mode = modes.contains(&PlayerMode::Fly);
 						self.camera.noclip_mode = modes.contains(&PlayerMode::Noclip);
@@ -439,13 +468,51 @@ impl<C :NetworkClientConn> Game<C> {
 					ServerToClientMsg::ChunkUpdated(p, c) => {
 						self.map.set_chunk(p, c);
 					},
-					ServerToClientMsg::Chat(s) => {
-						self.chat_msgs.push_back(s);
-						const CHAT_MSGS_LIMIT :usize = 10;
-						while self.chat_msgs.len() > CHAT_MSGS_LIMIT {
-							self.chat_msgs.pop_front();
+					ServerToClientMsg::ItemEntitiesUpdated(p, entities) => {
+						self.map.set_item_entities(p, entities);
+					},
+					ServerToClientMsg::SetChestMeta(p, meta) => {
+						let is_open = self.chest_menu.as_ref()
+							.map(|m| m.chest_pos() == p)
+							.unwrap_or(false);
+						match (is_open, meta) {
+							(true, Some(meta)) => {
+								self.chest_menu.as_mut().unwrap().update_chest_meta(&meta);
+							},
+							(true, None) => {
+								self.chest_menu = None;
+								self.check_grab_change();
+							},
+							(false, _) => (),
 						}
 					},
+					ServerToClientMsg::CommandCompletions(text, completions) => {
+						let cw = match &mut self.chat_window {
+							Some(cw) if cw.text() == text => cw,
+							// The text has changed in the meantime
+							_ => continue,
+						};
+						match completions.as_slice() {
+							[] => (),
+							[completion] => cw.set_text(completion.clone() + " "),
+							_ => {
+								let first = &completions[0];
+								let common_len = completions.iter()
+									.map(|c| first.chars()
+										.zip(c.chars())
+										.take_while(|(a, b)| a == b)
+										.map(|(a, _)| a.len_utf8())
+										.sum::<usize>())
+									.min()
+									.unwrap_or(0);
+								cw.set_text(first[..common_len].to_owned());
+								self.add_chat_msg(ChatKind::System, completions.join("  "));
+							},
+						}
+					},
+					ServerToClientMsg::Chat(kind, s) => {
+						self.add_chat_msg(kind, s);
+					},
 				}
 			}
 
@@ -485,7 +552,7 @@ impl<C :NetworkClientConn> Game<C> {
 				}
 			}
 		}
-		let player_pos = self.camera.pos - Vector3::new(0.35, 0.35, 1.4);
+		let player_pos = player_collision_pos(self.camera.pos);
 		let mut touches_ground = false;
 		for pos in cubes.into_iter() {
 			// X coord
@@ -591,9 +658,6 @@ impl<C :NetworkClientConn> Game<C> {
 		}
 		self.camera.pos += delta_pos;
 	}
-	fn chat_string(&self) -> String {
-		self.chat_msgs.iter().fold(String::new(), |v, w| v + "\n" + w)
-	}
 	fn render<'a, 'b>(&mut self, glyph_brush :&mut GlyphBrush<'a, 'b>) {
 		self.recv_vbuffs();
 		let pmatrix = self.camera.get_perspective();
@@ -610,12 +674,19 @@ impl<C :NetworkClientConn> Game<C> {
 		let texture_arr = texture_array.sampled()
 			.wrap_function(SamplerWrapFunction::Repeat)
 			.magnify_filter(MagnifySamplerFilter::Nearest);
+		// Sky and fog get darker at night, but never entirely black
+		let daylight = time_of_day::daylight(self.time_of_day);
+		let sky_brightness = 0.15 + 0.85 * daylight;
+		let sky_color = Vector3::new(0.05, 0.01, 0.6) * sky_brightness;
+		let fog_color = Vector3::new(0.5, 0.5, 0.5) * sky_brightness;
 		// building the uniforms
 		let uniforms = uniform! {
 			vmatrix : vmatrix,
 			pmatrix : pmatrix,
 			texture_arr : texture_arr,
-			fog_near_far : [self.config.fog_near, self.config.fog_far]
+			fog_near_far : [self.config.fog_near, self.config.fog_far],
+			fog_color : [fog_color.x, fog_color.y, fog_color.z, 1.0f32],
+			darkness : 0.7 * (1.0 - daylight)
 		};
 		self.selected_pos = self.params.as_ref().and_then(|params| self.camera.get_selected_pos(&self.map, params));
 		let mut sel_text = "sel = None".to_string();
@@ -631,6 +702,11 @@ impl<C :NetworkClientConn> Game<C> {
 			};
 			sel_text = format!("sel = ({}, {}, {}), {}",
 				selected_pos.x, selected_pos.y, selected_pos.z, blk_name);
+			if let Some(Some(MetadataEntry::Text(text))) = self.map.get_blk_meta(selected_pos) {
+				if !text.is_empty() {
+					sel_text += &format!("\n\"{}\"", text);
+				}
+			}
 
 			// TODO: only update if the position actually changed from the prior one
 			// this spares us needless chatter with the GPU
@@ -650,6 +726,18 @@ impl<C :NetworkClientConn> Game<C> {
 				pl_buf.push(vbuff);
 			}
 		}
+		let mut ent_buf = Vec::new();
+		if let Some(tid_cache) = &self.texture_id_cache {
+			let viewing_range = self.config.viewing_range;
+			let vertices = self.map.item_entities()
+				.filter(|ent| (ent.pos - self.camera.pos).norm() <= viewing_range)
+				.flat_map(|ent| item_entity_mesh(ent, tid_cache))
+				.collect::<Vec<_>>();
+			if !vertices.is_empty() {
+				let vbuff = VertexBuffer::new(&self.display, &vertices).unwrap();
+				ent_buf.push(vbuff);
+			}
+		}
 		let screen_dims = self.display.get_framebuffer_dimensions();
 
 		let polygon_mode = if !self.config.draw_poly_lines {
@@ -672,7 +760,7 @@ impl<C :NetworkClientConn> Game<C> {
 
 		// drawing a frame
 		let mut target = self.display.draw();
-		target.clear_color_and_depth((0.05, 0.01, 0.6, 0.0), 1.0);
+		target.clear_color_and_depth((sky_color.x, sky_color.y, sky_color.z, 0.0), 1.0);
 
 		let player_pos = self.camera.pos;
 		let mut drawn_chunks_count = 0;
@@ -699,6 +787,7 @@ impl<C :NetworkClientConn> Game<C> {
 			.chain(vbuffs_to_draw.iter().filter_map(|m| m.1.as_ref()));
 		for buff in vbuffs_to_draw_iter
 				.chain(pl_buf.iter())
+				.chain(ent_buf.iter())
 				.chain(selbuff.iter()) {
 			drawn_chunks_count += 1;
 			target.draw(buff,
@@ -712,12 +801,31 @@ impl<C :NetworkClientConn> Game<C> {
 				self.camera.pos.x, self.camera.pos.y, self.camera.pos.z,
 				self.camera.pitch, self.camera.yaw,
 				sel_text, self.last_fps as u16,
-				self.vbuffs.len(), drawn_chunks_count) + "\n" + &self.chat_string();
-		glyph_brush.queue(Section {
-			text :&text,
-			bounds : (screen_dims.0 as f32, screen_dims.1 as f32),
+				self.vbuffs.len(), drawn_chunks_count);
+		let chat_lines = self.chat_msgs.iter()
+			.map(|(kind, msg)| (*kind, "\n".to_owned() + msg))
+			.collect::<Vec<_>>();
+		let mut section_texts = vec![SectionText {
+			text : &text,
 			color : [0.9, 0.9, 0.9, 1.0],
-			.. Section::default()
+			.. SectionText::default()
+		}];
+		section_texts.extend(chat_lines.iter().map(|(kind, line)| {
+			let color = match kind {
+				ChatKind::Public => [0.9, 0.9, 0.9, 1.0],
+				ChatKind::Private => [0.9, 0.6, 0.9, 1.0],
+				ChatKind::System => [1.0, 0.85, 0.4, 1.0],
+			};
+			SectionText {
+				text : line,
+				color,
+				.. SectionText::default()
+			}
+		}));
+		glyph_brush.queue(VariedSection {
+			text : section_texts,
+			bounds : (screen_dims.0 as f32, screen_dims.1 as f32),
+			.. VariedSection::default()
 		});
 
 		glyph_brush.draw_queued(&self.display, &mut target);
@@ -761,6 +869,8 @@ impl<C :NetworkClientConn> Game<C> {
 				&mut self.display,
 				&self.program, glyph_brush,
 				params, &mut target);
+			render_health_bar(self.hp, ui_colors,
+				&mut self.display, &self.program, &mut target);
 		}
 		if self.in_background() {
 			if let (true, Some(ui_colors)) = (self.menu_enabled, &self.ui_colors) {
@@ -773,7 +883,7 @@ impl<C :NetworkClientConn> Game<C> {
 					tid_cache,
 					&mut self.display,
 					&self.program, glyph_brush, &mut target);
-				let command = m.check_event();
+				let command = m.check_event(self.modifiers.shift());
 				maybe_inventory_change!(m, self, command);
 			} else if let (Some(m), Some(ui_colors), Some(tid_cache)) = (&mut self.chest_menu, &self.ui_colors, &self.texture_id_cache) {
 				m.render(
@@ -821,6 +931,12 @@ impl<C :NetworkClientConn> Game<C> {
 		}
 	}
 
+	fn add_chat_msg(&mut self, kind :ChatKind, msg :String) {
+		self.chat_msgs.push_back((kind, msg));
+		while self.chat_msgs.len() > CHAT_MSGS_LIMIT {
+			self.chat_msgs.pop_front();
+		}
+	}
 	fn check_grab_change(&mut self) {
 		let grabbing_cursor = self.has_focus &&
 			!self.in_background() && self.grab_cursor;
@@ -835,17 +951,29 @@ impl<C :NetworkClientConn> Game<C> {
 		match ev {
 			ChatWindowEvent::CloseChatWindow => {
 				self.chat_window = None;
+				self.sign_pos = None;
 				self.check_grab_change();
 			},
 			ChatWindowEvent::SendChat => {
 				{
-					let text = &self.chat_window.as_ref().unwrap().text();
-					let msg = ClientToServerMsg::Chat(text.to_string());
+					let text = self.chat_window.as_ref().unwrap().text().to_string();
+					let msg = if let Some(pos) = self.sign_pos.take() {
+						ClientToServerMsg::SetSignText(pos, text)
+					} else {
+						ClientToServerMsg::Chat(text)
+					};
 					let _ = self.srv_conn.send(msg);
 				}
 				self.chat_window = None;
 				self.check_grab_change();
 			},
+			ChatWindowEvent::Complete => {
+				let text = self.chat_window.as_ref().unwrap().text();
+				if self.sign_pos.is_none() && text.starts_with('/') {
+					let msg = ClientToServerMsg::CompleteCommand(text.to_owned());
+					let _ = self.srv_conn.send(msg);
+				}
+			},
 			ChatWindowEvent::None => (),
 		}
 	}
@@ -869,6 +997,8 @@ impl<C :NetworkClientConn> Game<C> {
 				return false;
 			} else if let Some(m) = self.chest_menu.take() {
 				maybe_chest_inventory_change!(m, self, None);
+				let msg = ClientToServerMsg::CloseChest(m.chest_pos());
+				let _ = self.srv_conn.send(msg);
 
 				self.check_grab_change();
 				return false;
@@ -897,6 +1027,12 @@ impl<C :NetworkClientConn> Game<C> {
 					self.check_grab_change();
 				}
 			},
+			Some(VirtualKeyCode::Q) => {
+				if input.state == ElementState::Pressed {
+					let msg = ClientToServerMsg::DropSelected;
+					let _ = self.srv_conn.send(msg);
+				}
+			},
 			Some(VirtualKeyCode::K) => {
 				if input.state == ElementState::Pressed {
 					self.camera.fly_mode = !self.camera.fly_mode;
@@ -954,34 +1090,15 @@ impl<C :NetworkClientConn> Game<C> {
 					// Start new digging interaction
 					v @ None => {
 						let blk = self.map.get_blk(selected_pos).unwrap();
-						let dig_group = params.get_block_params(blk).unwrap().dig_group;
 						let sel = self.sel_inventory.get_selected();
-						let try_tool_groups = |tool_groups :&[ToolGroup]| {
-							let tgs = tool_groups.iter()
-								.filter(|g| g.group == dig_group.0 || g.group == DigGroup::any());
-							for tg in tgs {
-								// Only start digging if hardness is below or at the tool theshold
-								if dig_group.1 <= tg.hardness {
-									return Some((0.01 + 1.0/tg.speed) as f32);
-								}
-							}
-							None
-						};
-						// Set block specific cooldown:
-						// 1. Try if the currently selected tool supports the group
-						let tool_cooldown = if let Some(sel) = sel {
-							let tool_groups = &params.get_block_params(sel).unwrap().tool_groups;
-							try_tool_groups(tool_groups)
-						} else {
-							None
-						};
-						// 2. Try the groups of the bare hand
-						let cooldown = tool_cooldown.or_else(|| {
-							try_tool_groups(&params.hand_tool_groups)
-						});
+						// Set block and tool specific cooldown
+						let cooldown = dig_time(params, blk, sel);
 						// If any of the groups matched:
 						if let Some(cooldown) = cooldown {
 							*v = Some((selected_pos, cooldown));
+							// Tell the server so that it can verify the dig time
+							let msg = ClientToServerMsg::StartDig(selected_pos);
+							let _ = self.srv_conn.send(msg);
 						}
 					},
 				}
@@ -989,21 +1106,52 @@ impl<C :NetworkClientConn> Game<C> {
 			if self.camera.mouse_right_down
 					&& self.camera.mouse_right_cooldown <= 0.0 {
 				let blk_sel = self.map.get_blk(selected_pos).unwrap();
-				let has_inv = params.get_block_params(blk_sel).unwrap().inventory;
-
-				if let (Some(stack_num), false) = (has_inv, self.camera.down_pressed) {
-					// open chest inventory
-					let chest_inv = self.map.get_blk_meta(selected_pos).unwrap()
-						.map(|v| {
-							let MetadataEntry::Inventory(inv) = v.clone();
-							inv
-						})
-						.unwrap_or_else(|| SelectableInventory::empty_with_size(stack_num as usize));
+				let bp_sel = params.get_block_params(blk_sel).unwrap();
+				let chest_meta = if bp_sel.furnace {
+					Some(MetadataEntry::Furnace(FurnaceState::default()))
+				} else {
+					bp_sel.inventory.map(|stack_num| {
+						let inv = SelectableInventory::empty_with_size(stack_num as usize);
+						MetadataEntry::Inventory(inv)
+					})
+				};
+
+				if bp_sel.sign && !self.camera.down_pressed {
+					// open the sign text editor
+					let text = match self.map.get_blk_meta(selected_pos) {
+						Some(Some(MetadataEntry::Text(text))) => text.clone(),
+						_ => String::new(),
+					};
+					let prompt = format!("Sign text (max {} characters)", SIGN_TEXT_MAX_LEN);
+					self.chat_window = Some(ChatWindow::with_prompt(prompt, text));
+					self.sign_pos = Some(selected_pos);
+					self.camera.mouse_right_cooldown = RIGHT_BUTTON_COOLDOWN;
+					self.camera.mouse_right_down = false;
+					self.check_grab_change();
+					return;
+				}
+
+				if let (Some(default_meta), false) = (chest_meta, self.camera.down_pressed) {
+					// open chest or furnace inventory
+					let chest_meta = self.map.get_blk_meta(selected_pos).unwrap()
+						.cloned()
+						.unwrap_or(default_meta);
+					let own_id = self.player_positions.as_ref().map(|(id, _)| *id);
+					if let Some(own_id) = own_id {
+						if !chest_meta.may_access(own_id) {
+							self.add_chat_msg(ChatKind::System, "This chest is locked".to_owned());
+							self.camera.mouse_right_cooldown = RIGHT_BUTTON_COOLDOWN;
+							self.camera.mouse_right_down = false;
+							return;
+						}
+					}
 					self.chest_menu = Some(ChestMenu::new(
 						self.params.as_ref().unwrap().clone(),
 						self.sel_inventory.clone(),
-						chest_inv,
+						chest_meta,
 						selected_pos));
+					let msg = ClientToServerMsg::OpenChest(selected_pos);
+					let _ = self.srv_conn.send(msg);
 					self.camera.mouse_right_cooldown = RIGHT_BUTTON_COOLDOWN;
 					self.camera.mouse_right_down = false;
 					self.check_grab_change();
@@ -1013,16 +1161,33 @@ impl<C :NetworkClientConn> Game<C> {
 				let sel = self.sel_inventory.get_sel_idx_and_content();
 				if let Some((sel_idx, sel)) = sel {
 					let bp = params.get_block_params(sel).unwrap();
-					if bp.placeable {
+					let is_buildable_to = |blk| blk == params.block_roles.air ||
+						params.get_block_params(blk).map(|p| p.buildable_to).unwrap_or(false);
+					// Blocks like grass get replaced instead of placed onto
+					let place_pos = if is_buildable_to(blk_sel) {
+						selected_pos
+					} else {
+						before_selected
+					};
+					let can_place = self.map.get_blk(place_pos)
+						.map(is_buildable_to)
+						.unwrap_or(false)
+						&& !(bp.solid && player_overlaps_block(self.camera.pos, place_pos));
+					if bp.placeable && can_place {
 						let taken = self.sel_inventory.take_selected();
 						assert_eq!(taken, Some(sel));
 						if bp.on_place_plants_tree {
-							let msg = ClientToServerMsg::PlaceTree(before_selected, sel_idx, sel);
+							let msg = ClientToServerMsg::PlaceTree(place_pos, sel_idx, sel);
 							let _ = self.srv_conn.send(msg);
 						} else {
-							let mut blk = self.map.get_blk_mut(before_selected).unwrap();
-							blk.set(sel);
-							let msg = ClientToServerMsg::PlaceBlock(before_selected, sel_idx, sel);
+							let facedir = if bp.facedir {
+								Facedir::from_look_dir(self.camera.direction().coords)
+							} else {
+								Facedir::default()
+							};
+							let mut blk = self.map.get_blk_mut(place_pos).unwrap();
+							blk.set_with_facedir(sel, facedir);
+							let msg = ClientToServerMsg::PlaceBlock(place_pos, sel_idx, sel, facedir);
 							let _ = self.srv_conn.send(msg);
 						}
 						self.camera.mouse_right_cooldown = RIGHT_BUTTON_COOLDOWN;
@@ -1227,6 +1392,29 @@ fn hand_mesh(pos :Vector3<f32>, blk :MapBlock,
 	vertices
 }
 
+fn item_entity_mesh(ent :&ItemEntity,
+		texture_id_cache :&TextureIdCache) -> Vec<Vertex> {
+	const SIZE :f32 = 0.3;
+	let mut vertices = Vec::new();
+	let item = if let Some((item, _count)) = ent.stack.content() {
+		item
+	} else {
+		return vec![];
+	};
+	let texture_ids = if let Some(id) = texture_id_cache.get_inv_texture_id(&item) {
+		BlockTextureIds::uniform(id)
+	} else if let Some(ids) = texture_id_cache.get_bl_tex_ids(&item) {
+		ids
+	} else {
+		return vec![];
+	};
+
+	push_block(&mut vertices,
+		[ent.pos.x - SIZE / 2.0, ent.pos.y - SIZE / 2.0, ent.pos.z],
+		texture_ids, SIZE, |_| false);
+	vertices
+}
+
 fn clamp(a :f32, min :f32, max :f32) -> f32 {
 	if a > min {
 		if a < max {
@@ -1372,6 +1560,9 @@ impl Camera {
 		self.yaw += factor * delta.x as f32;
 		self.yaw = (self.yaw + 180.0).rem_euclid(360.0) - 180.0;
 	}
+	/// Whether to move at the fast speed. The client doesn't know
+	/// the privileges of the player, so the fast key works without
+	/// the fast privilege, and the server moves the player back.
 	fn fast_speed(&self) -> bool {
 		self.fast_mode || self.fast_pressed
 	}
@@ -1380,9 +1571,7 @@ impl Camera {
 	}
 
 	fn direction(&self) -> Point3<f32> {
-		let pitch = dtr(-self.pitch);
-		let yaw = dtr(-self.yaw);
-		Point3::new(pitch.cos() * yaw.cos(), pitch.cos() * yaw.sin(), pitch.sin())
+		Point3::from(interaction::look_direction(self.pitch, self.yaw))
 	}
 
 	fn get_matrix(&self) -> [[f32; 4]; 4] {
@@ -1400,16 +1589,6 @@ impl Camera {
 	}
 
 	pub fn get_selected_pos<B :MapBackend>(&self, map :&Map<B>, params :&GameParamsHdl) -> Option<(Vector3<isize>, Vector3<isize>)> {
-		for (vs, ve) in VoxelWalker::new(self.pos,
-				self.direction().coords) {
-			let vs = vs.map(|v| v.floor() as isize);
-			let ve = ve.map(|v| v.floor() as isize);
-			if let Some(blk) = map.get_blk(ve) {
-				if params.get_pointability_for_blk(&blk) {
-					return Some((ve, vs));
-				}
-			}
-		}
-		None
+		interaction::pointed_block(map, params, self.pos, self.direction().coords)
 	}
 }
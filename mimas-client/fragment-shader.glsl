@@ -8,8 +8,9 @@ out vec4 fcolor;
 
 uniform sampler2DArray texture_arr;
 uniform vec2 fog_near_far;
-
-const vec4 fog = vec4(0.5, 0.5, 0.5, 1.0);
+uniform vec4 fog_color;
+// How much darker than at daylight blocks are
+uniform float darkness;
 
 void main() {
 	vec4 tcolor = texture(texture_arr, vec3(vtex_pos, vtex_ind));
@@ -17,8 +18,8 @@ void main() {
 		discard;
 	}
 
-	vec4 color_lamb = vlamb * tcolor;
+	vec4 color_lamb = vlamb * (1.0 - darkness) * tcolor;
 	color_lamb.a = tcolor.a;
 	float fog_factor = clamp((length(vposition) - fog_near_far.y) / fog_near_far.x, 0.0, 1.0);
-	fcolor = mix(color_lamb, fog, fog_factor);
+	fcolor = mix(color_lamb, fog_color, fog_factor);
 }
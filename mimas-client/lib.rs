@@ -19,8 +19,6 @@ extern crate mimas_meshgen;
 
 mod assets;
 pub mod client;
-mod collide;
 mod ui;
-mod voxel_walk;
 
 use glium::glutin;
@@ -10,8 +10,10 @@ use glium::glutin::dpi::PhysicalPosition;
 use glium_glyph::glyph_brush::GlyphCruncher;
 use mimas_common::inventory::{self, SelectableInventory, Stack,
 	HUD_SLOT_COUNT};
-use mimas_common::crafting::get_matching_recipe;
+use mimas_common::crafting;
 use mimas_common::game_params::GameParamsHdl;
+use mimas_common::player::MAX_HP;
+use mimas_common::map::{MetadataEntry, FurnaceState, FURNACE_OUTPUT_SLOT};
 
 use mimas_meshgen::{Vertex, TextureId, TextureIdCache};
 
@@ -57,12 +59,15 @@ pub fn render_menu<'a, 'b>(ui_colors :&UiColors, display :&glium::Display, progr
 }
 
 pub struct ChatWindow {
+	prompt : String,
 	text : String,
 }
 
 pub enum ChatWindowEvent {
 	CloseChatWindow,
 	SendChat,
+	/// Tab has been pressed to complete the text
+	Complete,
 	None,
 }
 
@@ -71,22 +76,33 @@ impl ChatWindow {
 		Self::with_text("".to_owned())
 	}
 	pub fn with_text(text :String) -> Self {
+		Self::with_prompt("Type to chat".to_owned(), text)
+	}
+	/// Creates a window for entering other text than chat messages
+	pub fn with_prompt(prompt :String, text :String) -> Self {
 		ChatWindow {
+			prompt,
 			text,
 		}
 	}
 	pub fn text(&self) -> &str {
 		&self.text
 	}
+	pub fn set_text(&mut self, text :String) {
+		self.text = text;
+	}
 	pub fn render<'a, 'b>(&self, ui_colors :&UiColors, display :&glium::Display,
 			program :&glium::Program, glyph_brush :&mut GlyphBrush<'a, 'b>, target :&mut glium::Frame) {
-		let text = "Type to chat\n".to_owned() + &self.text;
+		let text = self.prompt.clone() + "\n" + &self.text;
 		render_text(&text, ui_colors, display, program, glyph_brush, target);
 	}
 	pub fn handle_character(&mut self, input :char) -> ChatWindowEvent {
 		if input == '\n' {
 			return ChatWindowEvent::SendChat;
 		}
+		if input == '\t' {
+			return ChatWindowEvent::Complete;
+		}
 		if input == '\x08' {
 			// Backspace. Remove last character.
 			self.text.pop();
@@ -383,9 +399,9 @@ impl InventoryMenu {
 		self.mouse_input_ev = Some((state, button));
 	}
 	fn update_craft_output_inv(&mut self) {
-		let recipe = get_matching_recipe(&self.invs[CRAFTING_ID], &self.params);
-		let stack = recipe
-			.map(|r| r.output)
+		// Craft on a copy to preview the output
+		let mut craft_inv = self.invs[CRAFTING_ID].clone();
+		let stack = crafting::craft(&mut craft_inv, &self.params)
 			.unwrap_or(Stack::Empty);
 		let stacks = vec![stack].into_boxed_slice();
 		self.invs[CRAFTING_OUTPUT_ID] = SelectableInventory::from_stacks(stacks);
@@ -439,7 +455,11 @@ impl InventoryMenu {
 		self.hover_idx = hover_idx;
 
 	}
-	pub fn check_event(&mut self) -> Option<SwapOrCraftCommand> {
+	/// Handles the last mouse input.
+	///
+	/// If shift is pressed, clicking on the crafting output
+	/// crafts as many times as possible.
+	pub fn check_event(&mut self, shift :bool) -> Option<SwapOrCraftCommand> {
 		let mut res = None;
 		let mut swap_command = None;
 
@@ -457,12 +477,13 @@ impl InventoryMenu {
 						// add the output to the inventory immediately.
 						// Only do something if there is something to craft
 						if self.invs[CRAFTING_OUTPUT_ID].stacks()[0] != Stack::Empty {
-							// TODO figure out something for the remainder stack
-							self.invs[NORMAL_INV_ID].put(self.invs[CRAFTING_OUTPUT_ID].stacks()[0]);
-							// Reduce inputs.
-							for st in self.invs[CRAFTING_ID].stacks_mut().iter_mut() {
-								st.take_n(1);
-							}
+							let [craft_inv, _output_inv, inv] = &mut self.invs;
+							// The server drops the remainder stack
+							crafting::craft_into(craft_inv, inv, &self.params, shift);
+						}
+						self.update_craft_output_inv();
+						if shift {
+							return Some(SwapOrCraftCommand::CraftMax);
 						}
 						return Some(SwapOrCraftCommand::Craft);
 					} else {
@@ -511,12 +532,20 @@ pub struct SwapCommand {
 #[derive(PartialEq, Eq)]
 pub enum SwapOrCraftCommand {
 	Craft,
+	/// Craft as many times as possible
+	CraftMax,
 	Swap(SwapCommand),
 }
 
 pub struct ChestMenu {
 	params :GameParamsHdl,
 	invs :[SelectableInventory; 2],
+	/// The metadata entry of the chest or furnace.
+	/// Its inventory is kept in invs instead.
+	meta :MetadataEntry,
+	/// An update from the server that arrived while
+	/// the player was moving stacks
+	pending_meta :Option<MetadataEntry>,
 	chest_pos :Vector3<isize>,
 	last_mouse_pos :Option<PhysicalPosition<f64>>,
 	mouse_input_ev :Option<(ElementState, MouseButton)>,
@@ -527,12 +556,16 @@ pub struct ChestMenu {
 impl ChestMenu {
 	pub fn new(params :GameParamsHdl,
 			inv :SelectableInventory,
-			chest_inv :SelectableInventory,
+			chest_meta :MetadataEntry,
 			chest_pos :Vector3<isize>) -> Self {
+		let chest_inv = chest_meta.inventory().cloned()
+			.unwrap_or_else(|| SelectableInventory::empty_with_size(0));
 		let invs = [chest_inv, inv];
 		Self {
 			params,
 			invs,
+			meta : chest_meta,
+			pending_meta : None,
 			chest_pos,
 			last_mouse_pos : None,
 			mouse_input_ev : None,
@@ -549,6 +582,36 @@ impl ChestMenu {
 	pub fn chest_pos(&self) -> Vector3<isize> {
 		self.chest_pos
 	}
+	/// The metadata entry of the chest as shown by the menu
+	pub fn chest_meta(&self) -> MetadataEntry {
+		let mut meta = self.meta.clone();
+		if let Some(inv) = meta.inventory_mut() {
+			*inv = self.invs[CRAFTING_ID].clone();
+		}
+		meta
+	}
+	/// The furnace state, if the menu belongs to a furnace
+	fn furnace(&self) -> Option<&FurnaceState> {
+		if let MetadataEntry::Furnace(state) = &self.meta {
+			Some(state)
+		} else {
+			None
+		}
+	}
+	/// Updates the menu with changes made to the chest
+	/// on the server, e.g. by a cooking furnace or another player
+	pub fn update_chest_meta(&mut self, chest_meta :&MetadataEntry) {
+		if self.from_pos.is_some() {
+			// Don't change the contents while the player moves stacks
+			self.pending_meta = Some(chest_meta.clone());
+			return;
+		}
+		self.pending_meta = None;
+		if let Some(inv) = chest_meta.inventory() {
+			self.invs[CRAFTING_ID] = inv.clone();
+		}
+		self.meta = chest_meta.clone();
+	}
 	pub fn handle_mouse_moved(&mut self, pos :PhysicalPosition<f64>)  {
 		self.last_mouse_pos = Some(pos);
 	}
@@ -566,8 +629,13 @@ impl ChestMenu {
 
 		const SLOT_COUNT_X :usize = 8;
 
+		let chest_slot_count_x = if self.furnace().is_some() {
+			self.invs[CRAFTING_ID].stacks().len()
+		} else {
+			SLOT_COUNT_X
+		};
 		let slot_counts_x :&[usize] = &[
-			SLOT_COUNT_X,
+			chest_slot_count_x,
 			SLOT_COUNT_X,
 		];
 
@@ -593,9 +661,39 @@ impl ChestMenu {
 
 		// TODO this is hacky, we change state in RENDERING code!!
 		self.hover_idx = hover_idx;
+
+		if let Some(state) = self.furnace() {
+			// Show the progress right of the furnace slots
+			let percent = |v :f32, total :f32| if total > 0.0 {
+				(v / total * 100.0) as u32
+			} else {
+				0
+			};
+			let text = format!("Cooking: {}%\nFuel: {}%",
+				percent(state.cook_time, state.cook_time_total),
+				percent(state.burn_time, state.burn_time_total));
+			let width = layout.state.dimension_x.unwrap() + 0.1 * unit;
+			let height = layout.state.dimension_y.unwrap() + 0.1 * unit;
+			let text_x = (screen_dims.0 as f32 - width / 2.0
+				+ unit * 1.1 * chest_slot_count_x as f32 + unit * 0.1) * 0.5;
+			let text_y = (screen_dims.1 as f32 - height / 2.0 + unit * 0.1) * 0.5;
+			glyph_brush.queue(Section {
+				text : &text,
+				bounds : (unit * 2.0, unit),
+				screen_position : (text_x, text_y),
+				color : [0.9, 0.9, 0.9, 1.0],
+				.. Section::default()
+			});
+			glyph_brush.draw_queued(display, target);
+		}
 	}
 
 	pub fn check_movement(&mut self) -> Option<SwapCommand> {
+		if self.from_pos.is_none() {
+			if let Some(meta) = self.pending_meta.take() {
+				self.update_chest_meta(&meta);
+			}
+		}
 		let mut swap_command = None;
 		let input_ev = self.mouse_input_ev.take();
 		if let (Some((state, button)), Some(hv)) = (input_ev, self.hover_idx.take()) {
@@ -612,6 +710,19 @@ impl ChestMenu {
 		}
 
 		if let Some((from_pos, to_pos, button)) = swap_command {
+			let output_pos = (CRAFTING_ID, FURNACE_OUTPUT_SLOT);
+			if self.furnace().is_some() && to_pos == output_pos {
+				// Putting into the furnace output is not possible
+				return None;
+			}
+			if self.furnace().is_some() && from_pos == output_pos {
+				// Taking from the furnace output may not swap something into it
+				let from = self.invs[from_pos.0].stacks()[from_pos.1];
+				let to = self.invs[to_pos.0].stacks()[to_pos.1];
+				if !inventory::moves_without_swap(from, to) {
+					return None;
+				}
+			}
 			let maybe_only_move = match button {
 				MouseButton::Left => Some(false),
 				MouseButton::Right => Some(true),
@@ -621,6 +732,9 @@ impl ChestMenu {
 				inventory::merge_or_move(
 					&mut self.invs,
 					from_pos, to_pos, only_move);
+				// The server sends the chest again once it
+				// has processed the move
+				self.pending_meta = None;
 				return Some(SwapCommand {
 					from_pos,
 					to_pos,
@@ -712,6 +826,7 @@ fn render_inventories<'a, 'b>(
 					+ unit * 1.1 * line as f32 + unit * 0.1) * 0.5 + offs.1 * 0.5
 			},
 			glyph_brush,
+			ui_colors,
 			tid_cache,
 			params,
 		));
@@ -738,6 +853,7 @@ fn inventory_slots_mesh<'a, 'b>(inv :&SelectableInventory,
 		mesh_y_fn :impl Fn(usize) -> i32,
 		text_y_fn :impl Fn(usize) -> f32,
 		glyph_brush :&mut GlyphBrush<'a, 'b>,
+		ui_colors :&UiColors,
 		tid_cache :&TextureIdCache,
 		params :&GameParamsHdl) -> Vec<Vertex> {
 	let mut vertices = Vec::new();
@@ -770,7 +886,22 @@ fn inventory_slots_mesh<'a, 'b>(inv :&SelectableInventory,
 		let content = inv.stacks()
 			.get(i)
 			.unwrap_or(&Stack::Empty);
-		let text = if let Stack::Content { item, count } = content {
+		// Wear bar at the bottom of the slot
+		let max_wear = content.content()
+			.and_then(|(item, _count)| params.get_block_params(item))
+			.and_then(|bp| bp.max_wear);
+		if let (Some(max_wear), true) = (max_wear, content.wear() > 0) {
+			let remaining = 1.0 - content.wear() as f32 / max_wear as f32;
+			let bar_x = mesh_x + (unit * 0.1) as i32;
+			let bar_y = mesh_y + (unit * 0.05) as i32;
+			let bar_dims = ((unit * 0.8) as i32, (unit * 0.08) as i32);
+			vertices.extend_from_slice(&square_mesh_xy(bar_x, bar_y,
+				bar_dims, screen_dims, ui_colors.wear_bar_background_color));
+			let filled_dims = ((bar_dims.0 as f32 * remaining) as i32, bar_dims.1);
+			vertices.extend_from_slice(&square_mesh_xy(bar_x, bar_y,
+				filled_dims, screen_dims, ui_colors.wear_bar_color));
+		}
+		let text = if let Stack::Content { item, count, .. } = content {
 			if icon_found {
 				format!("{}", count)
 			} else {
@@ -844,6 +975,7 @@ pub fn render_inventory_hud<'a, 'b>(inv :&SelectableInventory,
 			screen_dims.1 as f32 - hud_height * 0.5
 		},
 		glyph_brush,
+		ui_colors,
 		tid_cache,
 		&gm_params,
 	));
@@ -852,6 +984,35 @@ pub fn render_inventory_hud<'a, 'b>(inv :&SelectableInventory,
 	glyph_brush.draw_queued(display, target);
 }
 
+/// Renders the health of the player as a bar above the HUD
+pub fn render_health_bar(hp :u16, ui_colors :&UiColors,
+		display :&glium::Display, program :&glium::Program,
+		target :&mut glium::Frame) {
+
+	let screen_dims = display.get_framebuffer_dimensions();
+
+	let unit = unit_from_screen_dims(screen_dims.0);
+
+	const SLOT_COUNT_F32 :f32 = HUD_SLOT_COUNT as f32;
+
+	let hud_width = SLOT_COUNT_F32 * unit * 1.10 + 0.1 * unit;
+	let hud_height = unit * 1.10;
+
+	let mut vertices = Vec::new();
+
+	let dims = (hud_width as i32, (unit * 0.15) as i32);
+	let mesh_x = -(hud_width / 2.0) as i32;
+	let mesh_y = -(screen_dims.1 as i32) + (hud_height * 1.25) as i32;
+	vertices.extend_from_slice(&square_mesh_xy(mesh_x, mesh_y,
+		dims, screen_dims, ui_colors.wear_bar_background_color));
+	let filled = hp.min(MAX_HP) as f32 / MAX_HP as f32;
+	let filled_dims = ((dims.0 as f32 * filled) as i32, dims.1);
+	vertices.extend_from_slice(&square_mesh_xy(mesh_x, mesh_y,
+		filled_dims, screen_dims, ui_colors.hp_bar_color));
+
+	draw_ui_vertices(&vertices, display, program, target);
+}
+
 fn draw_ui_vertices<'a, 'b>(vertices :&[Vertex],
 		display :&glium::Display, program :&glium::Program,
 		target :&mut glium::Frame) {
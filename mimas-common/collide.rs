@@ -1,5 +1,11 @@
+//! Collision of players with blocks, shared by the client
+//! moving the player and the server checking the movement.
+
 use nalgebra::Vector3;
 
+/// Half the size of the box of the player
+const PLAYER_EXTENT :[f32; 3] = [0.35, 0.35, 0.9];
+
 fn fmin(a :f32, b :f32) -> f32 {
 	if a < b {
 		a
@@ -28,11 +34,17 @@ fn overlap_fn<T, F :Fn(T) -> f32>(f :F, a_min :T, a_max :T, b_min :T, b_max :T)
 	overlap(f(a_min), f(a_max), f(b_min), f(b_max))
 }
 
+/// Returns the position of the player passed to the collision
+/// functions, given the position of the player's camera.
+pub fn player_collision_pos(camera_pos :Vector3<f32>) -> Vector3<f32> {
+	camera_pos - Vector3::new(0.35, 0.35, 1.4)
+}
+
 /// Returns whether the player collides with the box at position pos
 /// and if yes, returns the normal.
 pub fn collide(player_pos :Vector3<f32>, pos :Vector3<isize>) -> Option<Vector3<f32>> {
 	let pos = pos.map(|v| v as f32);
-	let player_colb_extent = Vector3::new(0.35, 0.35, 0.9);
+	let player_colb_extent = Vector3::from(PLAYER_EXTENT);
 	let pmin = player_pos - player_colb_extent;
 	let pmax = player_pos + player_colb_extent;
 	let cube_extent = Vector3::new(0.5, 0.5, 0.5);
@@ -54,7 +66,7 @@ pub fn collide(player_pos :Vector3<f32>, pos :Vector3<isize>) -> Option<Vector3<
 			}
 		}
 		const LIMIT :f32 = 0.04;
-		let num_smaller = [ox, oy, oz].iter().filter(|v| *v <= &&LIMIT).count();
+		let num_smaller = [ox, oy, oz].iter().filter(|v| **v <= LIMIT).count();
 		let xnormal = Vector3::new(f(player_pos.x, pos.x), 0.0, 0.0);
 		let ynormal = Vector3::new(0.0, f(player_pos.y, pos.y), 0.0);
 		let znormal = Vector3::new(0.0, 0.0, f(player_pos.z, pos.z));
@@ -86,3 +98,32 @@ pub fn collide(player_pos :Vector3<f32>, pos :Vector3<isize>) -> Option<Vector3<
 		None
 	}
 }
+
+/// Returns whether the player overlaps with any of the blocks
+/// for which `solid` returns true.
+///
+/// The box of the player is shrunk by the margin on each side,
+/// so that merely touching a block doesn't count.
+pub fn overlaps_blocks(player_pos :Vector3<f32>, margin :f32,
+		solid :impl Fn(Vector3<isize>) -> bool) -> bool {
+	let extent = Vector3::from(PLAYER_EXTENT).map(|v| v - margin);
+	let pmin = player_pos - extent;
+	let pmax = player_pos + extent;
+	// The blocks are centered on their positions
+	let bmin = pmin.map(|v| (v - 0.5).floor() as isize);
+	let bmax = pmax.map(|v| (v + 0.5).ceil() as isize);
+	for x in bmin.x ..= bmax.x {
+		for y in bmin.y ..= bmax.y {
+			for z in bmin.z ..= bmax.z {
+				let pos = Vector3::new(x, y, z);
+				let cmin = pos.map(|v| v as f32 - 0.5);
+				let cmax = pos.map(|v| v as f32 + 0.5);
+				let overlaps = (0 .. 3).all(|i| overlap(pmin[i], pmax[i], cmin[i], cmax[i]).is_some());
+				if overlaps && solid(pos) {
+					return true;
+				}
+			}
+		}
+	}
+	false
+}
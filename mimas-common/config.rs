@@ -18,6 +18,20 @@ pub struct Config {
 	pub sent_chunks_radius_z :isize,
 	#[serde(default)]
 	pub map_storage_path :Option<String>,
+	#[serde(default = "default_privs_default")]
+	pub default_privs :Vec<String>,
+	#[serde(default)]
+	pub admin_nick :Option<String>,
+	#[serde(default = "item_entity_lifetime_default")]
+	pub item_entity_lifetime :u64,
+	#[serde(default = "time_speed_default")]
+	pub time_speed :f32,
+	#[serde(default)]
+	pub chat_log_path :Option<String>,
+	#[serde(default)]
+	pub spawn_pos :Option<[f32; 3]>,
+	#[serde(default = "tps_default")]
+	pub tps :u32,
 
 	// Client settings
 
@@ -39,6 +53,10 @@ fn mapgen_radius_xy_default() -> isize { 5 }
 fn mapgen_radius_z_default() -> isize { 2 }
 fn sent_chunks_radius_xy_default() -> isize { 6 }
 fn sent_chunks_radius_z_default() -> isize { 3 }
+fn default_privs_default() -> Vec<String> { vec!["fast".to_owned(), "sign".to_owned(), "home".to_owned()] }
+fn item_entity_lifetime_default() -> u64 { 300 }
+fn time_speed_default() -> f32 { 72.0 }
+fn tps_default() -> u32 { 60 }
 fn viewing_range_default() -> f32 { 128.0 }
 fn fog_near_default() -> f32 { 40.0 }
 fn fog_far_default() -> f32 { 60.0 }
@@ -52,6 +70,13 @@ impl Default for Config {
 			sent_chunks_radius_xy : 6,
 			sent_chunks_radius_z : 3,
 			map_storage_path : None,
+			default_privs : default_privs_default(),
+			admin_nick : None,
+			item_entity_lifetime : item_entity_lifetime_default(),
+			time_speed : time_speed_default(),
+			chat_log_path : None,
+			spawn_pos : None,
+			tps : tps_default(),
 
 			draw_poly_lines : false,
 			viewing_range : 128.0,
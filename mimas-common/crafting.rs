@@ -2,83 +2,187 @@ use crate::inventory::{SelectableInventory, Stack};
 use crate::game_params::GameParams;
 use crate::map::MapBlock;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct Recipe {
-	pub inputs :Vec<Option<MapBlock>>,
-	pub output :Stack,
+/// The number of slots per line of the crafting inventory
+pub const CRAFTING_WIDTH :usize = 3;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum RecipeInput {
+	Empty,
+	/// The given number of items of the block
+	Item(MapBlock, u16),
+	/// The given number of items of any block in the group
+	Group(String, u16),
 }
 
-const fn build_sqrt_table<const N :usize>() -> [u8; N] {
-	let mut res = [0u8; N];
-	let mut v = 1;
-	let mut w;
-	loop {
-		w = (v as usize) * (v as usize);
-		if w >= N {
-			break;
+impl RecipeInput {
+	/// The number of items that crafting consumes
+	pub fn count(&self) -> u16 {
+		match self {
+			RecipeInput::Empty => 0,
+			RecipeInput::Item(_item, count) => *count,
+			RecipeInput::Group(_group, count) => *count,
 		}
-		res[w] = v;
-		v += 1;
 	}
-	res
+	pub fn is_empty(&self) -> bool {
+		*self == RecipeInput::Empty
+	}
+	/// Whether the stack can be used for the input
+	fn matches(&self, params :&GameParams, stack :&Stack) -> bool {
+		let (st_item, st_count) = match stack.content() {
+			Some(c) => c,
+			None => return self.is_empty(),
+		};
+		let item_matches = match self {
+			RecipeInput::Empty => false,
+			RecipeInput::Item(item, _count) => *item == st_item,
+			RecipeInput::Group(group, _count) => params.in_group(st_item, group),
+		};
+		item_matches && self.count() <= st_count
+	}
 }
 
-fn isqrt(v :usize) -> usize {
-	// Use a lookup table for common values for v.
-	// We know that the if and else branches are returning
-	// different values if v is not a square number.
-	// This is okay however, as non-square inputs are not
-	// supposed to be passed to this function.
-	static SQRT_TABLE :[u8; 128] = build_sqrt_table();
-	if v < SQRT_TABLE.len() {
-		SQRT_TABLE[v] as usize
-	} else {
-		(v as f32).sqrt() as usize
-	}
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Recipe {
+	/// The inputs, line by line
+	pub inputs :Vec<RecipeInput>,
+	/// The number of inputs per line
+	pub width :usize,
+	/// Whether the inputs may be placed in any arrangement
+	pub shapeless :bool,
+	pub output :Stack,
 }
 
 impl Recipe {
-	fn matches(&self,
-			inv :&SelectableInventory) -> bool {
-		if inv.stacks().len() < self.inputs.len() {
-			return false;
+	/// Returns the number of items that crafting the recipe
+	/// once consumes from each stack of the inventory,
+	/// or None if the recipe doesn't match the inventory.
+	pub fn consumption(&self, inv :&SelectableInventory,
+			params :&GameParams) -> Option<Vec<u16>> {
+		if self.shapeless {
+			self.consumption_shapeless(inv, params)
+		} else {
+			self.consumption_shaped(inv, params)
+		}
+	}
+	fn consumption_shaped(&self, inv :&SelectableInventory,
+			params :&GameParams) -> Option<Vec<u16>> {
+		let stacks = inv.stacks();
+		let inv_width = CRAFTING_WIDTH.min(stacks.len());
+		let inv_height = (stacks.len() + inv_width - 1) / inv_width;
+		let width = self.width.max(1);
+		let height = (self.inputs.len() + width - 1) / width;
+		if width > inv_width || height > inv_height {
+			return None;
 		}
-		let inv_size_sqrt = isqrt(inv.stacks().len());
-		let recipe_size_sqrt = isqrt(self.inputs.len());
-		let size_sqrt_diff = inv_size_sqrt - recipe_size_sqrt;
 		// Try all possible offsets
-		for offs_line in 0 ..= size_sqrt_diff {
-			for offs_col in 0 ..= size_sqrt_diff {
-				let matches = inv.stacks().iter()
+		for offs_line in 0 ..= inv_height - height {
+			for offs_col in 0 ..= inv_width - width {
+				let mut consumption = vec![0; stacks.len()];
+				let matches = stacks.iter()
 					.enumerate()
 					.all(|(i, stack)| {
-						let stc = stack.content().map(|(m, _c)| m);
-						let line = i / inv_size_sqrt;
-						let col = i % inv_size_sqrt;
-						let line_recipe = line.checked_sub(offs_line);
-						let col_recipe = col.checked_sub(offs_col);
-						if let (Some(line_recipe), Some(col_recipe)) = (line_recipe, col_recipe) {
-							if (line_recipe < recipe_size_sqrt) && (col_recipe < recipe_size_sqrt) {
-								let recipe_idx = line_recipe * recipe_size_sqrt + col_recipe;
-								return stc == self.inputs[recipe_idx];
-							}
-						}
-						// If we are outside the recipe, the inventory needs to be empty
-						stc == None
+						let line = i / inv_width;
+						let col = i % inv_width;
+						let in_recipe = line >= offs_line && line < offs_line + height
+							&& col >= offs_col && col < offs_col + width;
+						let input = if in_recipe {
+							let recipe_idx = (line - offs_line) * width + col - offs_col;
+							// The last line may be shorter than the others
+							self.inputs.get(recipe_idx)
+								.unwrap_or(&RecipeInput::Empty)
+						} else {
+							// If we are outside the recipe, the inventory needs to be empty
+							&RecipeInput::Empty
+						};
+						consumption[i] = input.count();
+						input.matches(params, stack)
 					});
 				// If there is a match for this offset,
 				// return a match for the recipe
 				if matches {
-					return true;
+					return Some(consumption);
 				}
 			}
 		}
 		// No offset found at which there was a match
-		return false;
+		None
+	}
+	fn consumption_shapeless(&self, inv :&SelectableInventory,
+			params :&GameParams) -> Option<Vec<u16>> {
+		let stacks = inv.stacks();
+		let inputs = self.inputs.iter()
+			.filter(|input| !input.is_empty())
+			.collect::<Vec<_>>();
+		// Each input needs its own stack, and stacks
+		// that the recipe doesn't use need to be empty
+		let used_stacks = stacks.iter().filter(|st| !st.is_empty()).count();
+		if used_stacks != inputs.len() {
+			return None;
+		}
+		let mut consumption = vec![0; stacks.len()];
+		if assign_inputs(&inputs, stacks, params, &mut consumption) {
+			Some(consumption)
+		} else {
+			None
+		}
+	}
+}
+
+/// Assigns each input to a different stack that matches it,
+/// trying other assignments if an input is left without a stack
+fn assign_inputs(inputs :&[&RecipeInput], stacks :&[Stack],
+		params :&GameParams, consumption :&mut [u16]) -> bool {
+	let (input, rest) = if let Some(split) = inputs.split_first() {
+		split
+	} else {
+		return true;
+	};
+	for (i, stack) in stacks.iter().enumerate() {
+		if consumption[i] != 0 || !input.matches(params, stack) {
+			continue;
+		}
+		consumption[i] = input.count();
+		if assign_inputs(rest, stacks, params, consumption) {
+			return true;
+		}
+		consumption[i] = 0;
 	}
+	false
 }
 
 pub fn get_matching_recipe<'p>(inv :&SelectableInventory, params :&'p GameParams)
 		-> Option<&'p Recipe> {
-	params.recipes.iter().find(|r| r.matches(inv))
+	params.recipes.iter().find(|r| r.consumption(inv, params).is_some())
+}
+
+/// Crafts the recipe matching the craft inventory once,
+/// taking the inputs from the craft inventory.
+///
+/// Returns the output, or None if no recipe matches.
+pub fn craft(craft_inv :&mut SelectableInventory, params :&GameParams) -> Option<Stack> {
+	let (recipe, consumption) = params.recipes.iter()
+		.find_map(|r| r.consumption(craft_inv, params).map(|c| (r, c)))?;
+	if consumption.iter().all(|c| *c == 0) {
+		// Recipes without inputs could be crafted forever
+		return None;
+	}
+	for (st, count) in craft_inv.stacks_mut().iter_mut().zip(consumption) {
+		st.take_n(count);
+	}
+	Some(recipe.output)
+}
+
+/// Crafts from the craft inventory into the inventory,
+/// either once or as many times as possible.
+///
+/// Returns the part of the output that didn't fit into the inventory.
+pub fn craft_into(craft_inv :&mut SelectableInventory, inv :&mut SelectableInventory,
+		params :&GameParams, max :bool) -> Stack {
+	while let Some(output) = craft(craft_inv, params) {
+		let leftover = inv.put(output);
+		if !leftover.is_empty() || !max {
+			return leftover;
+		}
+	}
+	Stack::Empty
 }
@@ -0,0 +1,58 @@
+//! Block orientations.
+//!
+//! Blocks with `paramtype = "facedir"` store the direction
+//! their top face points to. By default, it points up.
+//!
+//! Blocks only have top, bottom and side textures, so there is
+//! no rotation around the top face's axis. The orientation is
+//! meant for blocks like logs, whose top differs from their sides.
+
+use nalgebra::Vector3;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Hash, Default)]
+pub struct Facedir(u8);
+
+/// The directions the top face can point to, indexed by the facedir
+const TOP_DIRS :[[isize; 3]; 6] = [
+	[0, 0, 1],
+	[0, 0, -1],
+	[1, 0, 0],
+	[-1, 0, 0],
+	[0, 1, 0],
+	[0, -1, 0],
+];
+
+impl Facedir {
+	pub fn from_u8(v :u8) -> Option<Self> {
+		if (v as usize) < TOP_DIRS.len() {
+			Some(Facedir(v))
+		} else {
+			None
+		}
+	}
+	pub fn to_u8(self) -> u8 {
+		self.0
+	}
+	/// Computes the facedir of a block placed while looking
+	/// into the given direction.
+	///
+	/// When looking mostly up or down, the block is upright.
+	/// Otherwise, its top points towards the player,
+	/// like a log lying along the looking direction.
+	pub fn from_look_dir(dir :Vector3<f32>) -> Self {
+		let (x, y, z) = (dir.x.abs(), dir.y.abs(), dir.z.abs());
+		if z >= x && z >= y {
+			Facedir(0)
+		} else if x >= y {
+			if dir.x < 0.0 { Facedir(2) } else { Facedir(3) }
+		} else if dir.y < 0.0 {
+			Facedir(4)
+		} else {
+			Facedir(5)
+		}
+	}
+	/// The direction the top face points to
+	pub fn top_dir(self) -> [isize; 3] {
+		TOP_DIRS[self.0 as usize]
+	}
+}
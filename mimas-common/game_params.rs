@@ -1,5 +1,5 @@
 use anyhow::{anyhow, bail, Result};
-use crate::crafting::Recipe;
+use crate::crafting::{Recipe, RecipeInput, CRAFTING_WIDTH};
 use std::sync::Arc;
 use toml::from_str;
 use toml::value::{Value, Array, Table};
@@ -7,13 +7,15 @@ use std::fs::{read_to_string, File};
 use std::path::{Path, PathBuf};
 use crate::map::MapBlock;
 use crate::toml_util::TomlReadExt;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
 use std::num::NonZeroU16;
+use std::convert::TryFrom;
 use std::str::FromStr;
 use std::io::Read;
 use crate::inventory::Stack;
 use crate::schematic::{Schematic, self};
+use crate::time_of_day::parse_hhmm;
 use sha2::{Sha256, Digest};
 
 pub type GameParamsHdl = Arc<GameParams>;
@@ -44,11 +46,33 @@ pub struct BlockParams {
 	pub on_place_plants_tree :bool,
 	pub solid :bool,
 	pub climbable :bool,
+	/// Whether placing a block onto this block replaces it
+	pub buildable_to :bool,
+	/// Whether players respawn at this block after placing it
+	pub bed :bool,
+	/// Whether the block falls down if there is nothing below it
+	pub falls :bool,
+	/// Whether the block is a furnace that cooks items
+	pub furnace :bool,
+	/// Whether the block is a sign that players can write text onto
+	pub sign :bool,
+	/// Whether the block can be placed in different orientations
+	pub facedir :bool,
+	/// Whether only the placer and players they share it with
+	/// can access the inventory of the block
+	pub locked :bool,
+	/// The item groups of the block, usable in recipes
+	pub groups :Vec<String>,
+	/// Damage that players inside this block take per second
+	pub damage_per_second :u16,
 	pub inventory :Option<u8>,
 	pub display_name :String,
 	pub drops :Stack,
 	pub dig_group :(DigGroup, u16),
 	pub tool_groups :Vec<ToolGroup>,
+	/// The wear the item can take before it breaks,
+	/// or None if it doesn't wear
+	pub max_wear :Option<u16>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Hash)]
@@ -153,12 +177,96 @@ pub struct MapgenParams {
 	pub plants :Vec<Plant>,
 }
 
+pub struct Liquid {
+	pub source :MapBlock,
+	/// The flowing variants, from the highest level to the lowest
+	pub flowing :Vec<MapBlock>,
+	/// Whether flowing liquid between two sources becomes a source
+	pub renewable :bool,
+}
+
+impl Liquid {
+	/// The level of the source block
+	pub fn source_level(&self) -> u8 {
+		self.flowing.len() as u8 + 1
+	}
+	/// Returns the level of the block if it belongs to the liquid
+	pub fn level(&self, blk :MapBlock) -> Option<u8> {
+		if blk == self.source {
+			return Some(self.source_level());
+		}
+		self.flowing.iter()
+			.position(|fl| *fl == blk)
+			.map(|idx| (self.flowing.len() - idx) as u8)
+	}
+	/// Returns the flowing block for the given level
+	pub fn flowing_with_level(&self, level :u8) -> Option<MapBlock> {
+		let idx = self.flowing.len().checked_sub(level as usize)?;
+		self.flowing.get(idx).copied()
+	}
+}
+
+pub enum AbmAction {
+	/// Replace the block with the given block
+	Replace(MapBlock),
+	/// Spawn the schematic at the block's position
+	Schematic(Schematic),
+}
+
+/// An active block modifier: an action that is run
+/// on blocks near players at a given interval
+pub struct Abm {
+	/// The blocks the action is run on
+	pub blocks :Vec<MapBlock>,
+	/// If not empty, one of the 26 surrounding blocks
+	/// must be one of these blocks
+	pub neighbours :Vec<MapBlock>,
+	/// Whether the block above needs to be air
+	pub air_above :bool,
+	/// The interval in seconds
+	pub interval :f64,
+	/// The action is run with a chance of 1 / chance
+	pub chance :u32,
+	/// If present, the time of day range in which the action is run
+	pub time_range :Option<(f32, f32)>,
+	pub action :AbmAction,
+}
+
+/// A recipe for cooking an item in a furnace
+pub struct CookingRecipe {
+	pub input :MapBlock,
+	pub output :Stack,
+	/// The cooking time in seconds
+	pub time :f32,
+}
+
 pub struct ServerGameParams {
 	pub p :GameParams,
 	pub mapgen_params :MapgenParams,
+	pub liquids :Vec<Liquid>,
+	pub abms :Vec<Abm>,
+	pub cooking_recipes :Vec<CookingRecipe>,
+	/// Burn times of fuels in seconds
+	pub fuels :HashMap<MapBlock, f32>,
 	pub textures :HashMap<Vec<u8>, Vec<u8>>,
 }
 
+impl ServerGameParams {
+	/// Returns the liquid the block belongs to, if any
+	pub fn get_liquid(&self, blk :MapBlock) -> Option<&Liquid> {
+		self.liquids.iter()
+			.find(|l| l.level(blk).is_some())
+	}
+	pub fn get_cooking_recipe(&self, input :MapBlock) -> Option<&CookingRecipe> {
+		self.cooking_recipes.iter()
+			.find(|r| r.input == input)
+	}
+	/// Returns the burn time of the fuel, if the block is a fuel
+	pub fn get_burn_time(&self, blk :MapBlock) -> Option<f32> {
+		self.fuels.get(&blk).copied()
+	}
+}
+
 fn default_hand_tool_groups(dig_group_ids :&mut NameIdMap<DigGroup>) -> Vec<ToolGroup> {
 	vec![
 		ToolGroup {
@@ -185,11 +293,21 @@ impl Default for BlockParams {
 			on_place_plants_tree : false,
 			solid : true,
 			climbable : false,
+			buildable_to : false,
+			bed : false,
+			falls : false,
+			furnace : false,
+			sign : false,
+			facedir : false,
+			locked : false,
+			groups : Vec::new(),
+			damage_per_second : 0,
 			display_name : String::new(),
 			inventory : None,
 			drops : Stack::Empty,
 			dig_group : (DigGroup::default(), 1),
 			tool_groups : Vec::new(),
+			max_wear : None,
 		}
 	}
 }
@@ -233,6 +351,13 @@ impl Schematics {
 			cactus_schematic : schematic::cactus_schematic(roles),
 		}
 	}
+	pub fn get(&self, name :&str) -> Option<&Schematic> {
+		match name {
+			"tree" => Some(&self.tree_schematic),
+			"cactus" => Some(&self.cactus_schematic),
+			_ => None,
+		}
+	}
 }
 
 impl NameIdMap {
@@ -317,6 +442,12 @@ impl GameParams {
 	pub fn get_block_params(&self, blk :MapBlock) -> Option<&BlockParams> {
 		self.block_params.get(blk.id() as usize)
 	}
+	/// Returns whether the block is in the given item group
+	pub fn in_group(&self, blk :MapBlock, group :&str) -> bool {
+		self.get_block_params(blk)
+			.map(|p| p.groups.iter().any(|g| g == group))
+			.unwrap_or(false)
+	}
 	pub fn get_pointability_for_blk(&self, blk :&MapBlock) -> bool {
 		self.block_params.get(blk.id() as usize)
 			.map(|p| p.pointable)
@@ -377,6 +508,35 @@ pub fn resolve_dig_group_specifier(nm :&mut NameIdMap<DigGroup>, sp :&str)
 	}
 }
 
+/// Resolves recipe inputs in the format `name [count]`
+/// where the name is either an item name or `group:<group name>`.
+///
+/// `groups` are the groups that blocks are in.
+pub fn resolve_recipe_input(nm :&NameIdMap, groups :&HashSet<&str>, sp :&str)
+		-> Result<RecipeInput> {
+	if sp.is_empty() {
+		return Ok(RecipeInput::Empty);
+	}
+	let mut nit = sp.split(' ');
+	let (name, count) = match (nit.next(), nit.next(), nit.next()) {
+		(Some(name), None, None) => (name, 1),
+		(Some(name), Some(count), None) => (name, u16::from_str(count)?),
+		_ => bail!("Invalid recipe input '{}'. Must be in format 'modname:name [count]'.", sp),
+	};
+	if count == 0 {
+		bail!("Count may not be 0. Use \"\" instead.");
+	}
+	if let Some(group) = name.strip_prefix("group:") {
+		if !groups.contains(group) {
+			bail!("Can't find any item in group '{}'.", group);
+		}
+		return Ok(RecipeInput::Group(group.to_owned(), count));
+	}
+	let item = nm.get_id(name)
+		.ok_or_else(|| anyhow!("Can't find any item named '{}'.", name))?;
+	Ok(RecipeInput::Item(item, count))
+}
+
 pub fn resolve_stack_specifier(nm :&NameIdMap, sp :&str)
 		-> Result<Stack> {
 	if sp.is_empty() {
@@ -389,7 +549,7 @@ pub fn resolve_stack_specifier(nm :&NameIdMap, sp :&str)
 		let count = u16::from_str(count)?;
 		let count = NonZeroU16::new(count)
 			.ok_or_else(|| anyhow!("Count may not be 0. Use \"\" instead."))?;
-		Ok(Stack::Content { item, count })
+		Ok(Stack::Content { item, count, wear : 0 })
 	} else {
 		bail!("Invalid stack specifier '{}'. Must be in format 'modname:name count'.", sp);
 	}
@@ -467,6 +627,10 @@ fn from_val(val :Value, nm_from_db :NameIdMap, default_game_params_str :&'static
 		ServerGameParams {
 			p,
 			mapgen_params,
+			liquids : Vec::new(),
+			abms : Vec::new(),
+			cooking_recipes : Vec::new(),
+			fuels : HashMap::new(),
 			textures :HashMap::new(),
 		}
 	};
@@ -574,6 +738,49 @@ fn from_val(val :Value, nm_from_db :NameIdMap, default_game_params_str :&'static
 		let climbable = block.get("climbable")
 			.unwrap_or(&Value::Boolean(false));
 		let climbable = *climbable.convert::<bool>()?;
+		let buildable_to = block.get("buildable_to")
+			.unwrap_or(&Value::Boolean(false));
+		let buildable_to = *buildable_to.convert::<bool>()?;
+		let bed = block.get("bed")
+			.unwrap_or(&Value::Boolean(false));
+		let bed = *bed.convert::<bool>()?;
+		let falls = block.get("falls")
+			.unwrap_or(&Value::Boolean(false));
+		let falls = *falls.convert::<bool>()?;
+		let furnace = block.get("furnace")
+			.unwrap_or(&Value::Boolean(false));
+		let furnace = *furnace.convert::<bool>()?;
+		let sign = block.get("sign")
+			.unwrap_or(&Value::Boolean(false));
+		let sign = *sign.convert::<bool>()?;
+		let facedir = match block.get("paramtype") {
+			Some(v) => match v.convert::<str>()? {
+				"facedir" => true,
+				"none" => false,
+				pt => bail!("Unsupported paramtype '{}'", pt),
+			},
+			None => false,
+		};
+		let locked = block.get("locked")
+			.unwrap_or(&Value::Boolean(false));
+		let locked = *locked.convert::<bool>()?;
+		let groups = if let Some(groups) = block.get("groups") {
+			groups.convert::<Array>()?
+				.iter()
+				.map(|g| Ok(g.convert::<str>()?.to_owned()))
+				.collect::<Result<Vec<String>>>()?
+		} else {
+			Vec::new()
+		};
+		let damage_per_second = if let Some(v) = block.get("damage_per_second") {
+			let damage = *v.convert::<i64>()?;
+			match u16::try_from(damage) {
+				Ok(damage) => damage,
+				_ => bail!("invalid damage_per_second {}, must be between 0 and {}", damage, u16::MAX),
+			}
+		} else {
+			0
+		};
 		let inventory = if let Some(v) = block.get("inventory") {
 			Some(v.convert::<i64>()?.to_owned() as u8)
 		} else {
@@ -592,6 +799,15 @@ fn from_val(val :Value, nm_from_db :NameIdMap, default_game_params_str :&'static
 			(DigGroup::default(), 1)
 		};
 		let tool_groups = parse_tool_groups(&mut params.p.dig_group_ids, block.get("tool_groups"))?;
+		let max_wear = if let Some(v) = block.get("max_wear") {
+			let max_wear = *v.convert::<i64>()?;
+			match u16::try_from(max_wear) {
+				Ok(max_wear) if max_wear > 0 => Some(max_wear),
+				_ => bail!("invalid max_wear {}, must be between 1 and {}", max_wear, u16::MAX),
+			}
+		} else {
+			None
+		};
 
 		let block_params = BlockParams {
 			draw_style,
@@ -601,11 +817,21 @@ fn from_val(val :Value, nm_from_db :NameIdMap, default_game_params_str :&'static
 			on_place_plants_tree,
 			solid,
 			climbable,
+			buildable_to,
+			bed,
+			falls,
+			furnace,
+			sign,
+			facedir,
+			locked,
+			groups,
+			damage_per_second,
 			display_name,
 			inventory,
 			drops,
 			dig_group,
 			tool_groups,
+			max_wear,
 		};
 		params.p.block_params[id.id() as usize] = block_params;
 	}
@@ -621,30 +847,176 @@ fn from_val(val :Value, nm_from_db :NameIdMap, default_game_params_str :&'static
 
 	if let Some(recipes_list) = val.get("recipe") {
 		let recipes_list = recipes_list.convert::<Array>()?;
+		let groups = params.p.block_params.iter()
+			.flat_map(|bp| bp.groups.iter().map(|g| g.as_str()))
+			.collect::<HashSet<_>>();
 		for recipe in recipes_list.iter() {
 			let inputs = recipe.read::<Array>("inputs")?;
 			let inputs = inputs.iter()
 				.map(|input| {
-					let name = input.convert::<str>()?;
-					if name == "" {
-						Ok(None)
-					} else {
-						let mb = name_id_map.get_id(name)
-							.ok_or_else(|| anyhow!("invalid name"))?;
-						Ok(Some(mb))
-					}
+					let sp = input.convert::<str>()?;
+					resolve_recipe_input(name_id_map, &groups, sp)
 				})
-				.collect::<Result<Vec<Option<MapBlock>>>>()?;
+				.collect::<Result<Vec<RecipeInput>>>()?;
+			if inputs.iter().all(RecipeInput::is_empty) {
+				bail!("recipe without inputs");
+			}
+			let shapeless = match recipe.get("type") {
+				Some(ty) => match ty.convert::<str>()? {
+					"shaped" => false,
+					"shapeless" => true,
+					ty => bail!("invalid recipe type {}", ty),
+				},
+				None => false,
+			};
+			// Recipes with a square number of inputs are square by default,
+			// others are in lines of the crafting grid's width
+			let width = if let Some(width) = recipe.get("width") {
+				*width.convert::<i64>()? as usize
+			} else {
+				(1 ..= CRAFTING_WIDTH)
+					.find(|w| w * w == inputs.len())
+					.unwrap_or_else(|| CRAFTING_WIDTH.min(inputs.len()))
+			};
+			if width == 0 || width > CRAFTING_WIDTH {
+				bail!("invalid recipe width {}", width);
+			}
 			let output_sp = recipe.read::<str>("output")?;
 			let output = resolve_stack_specifier(&name_id_map, output_sp)?;
 
 			params.p.recipes.push(Recipe {
 				inputs,
+				width,
+				shapeless,
 				output,
 			});
 		}
 	}
 
+	if let Some(cooking_list) = val.get("cooking") {
+		let cooking_list = cooking_list.convert::<Array>()?;
+		for recipe in cooking_list.iter() {
+			let input_name = recipe.read::<str>("input")?;
+			let input = name_id_map.get_id(input_name)
+				.ok_or_else(|| anyhow!("invalid name {}", input_name))?;
+			let output_sp = recipe.read::<str>("output")?;
+			let output = resolve_stack_specifier(name_id_map, output_sp)?;
+			let time = *recipe.read::<f64>("time")? as f32;
+			if !(time.is_finite() && time > 0.0) {
+				bail!("invalid cooking time {}, must be a positive number of seconds", time);
+			}
+			params.cooking_recipes.push(CookingRecipe {
+				input,
+				output,
+				time,
+			});
+		}
+	}
+
+	if let Some(fuel_list) = val.get("fuel") {
+		let fuel_list = fuel_list.convert::<Array>()?;
+		for fuel in fuel_list.iter() {
+			let name = fuel.read::<str>("name")?;
+			let id = name_id_map.get_id(name)
+				.ok_or_else(|| anyhow!("invalid name {}", name))?;
+			let burn_time = *fuel.read::<f64>("burn_time")? as f32;
+			if !(burn_time.is_finite() && burn_time > 0.0) {
+				bail!("invalid burn_time {}, must be a positive number of seconds", burn_time);
+			}
+			params.fuels.insert(id, burn_time);
+		}
+	}
+
+	if let Some(liquids_list) = val.get("liquid") {
+		let liquids_list = liquids_list.convert::<Array>()?;
+		for liquid in liquids_list.iter() {
+			let get_id = |name :&str| name_id_map.get_id(name)
+				.ok_or_else(|| anyhow!("invalid name {}", name));
+			let source = get_id(liquid.read::<str>("source")?)?;
+			let flowing = liquid.read::<Array>("flowing")?
+				.iter()
+				.map(|name| get_id(name.convert::<str>()?))
+				.collect::<Result<Vec<MapBlock>>>()?;
+			let renewable = liquid.get("renewable")
+				.unwrap_or(&Value::Boolean(false));
+			let renewable = *renewable.convert::<bool>()?;
+			params.liquids.push(Liquid {
+				source,
+				flowing,
+				renewable,
+			});
+		}
+	}
+
+	if let Some(abms_list) = val.get("abm") {
+		let abms_list = abms_list.convert::<Array>()?;
+		for abm in abms_list.iter() {
+			let get_ids = |key :&str| -> Result<Vec<MapBlock>> {
+				let names = if let Some(names) = abm.get(key) {
+					names.convert::<Array>()?
+				} else {
+					return Ok(Vec::new());
+				};
+				names.iter()
+					.map(|name| {
+						let name = name.convert::<str>()?;
+						name_id_map.get_id(name)
+							.ok_or_else(|| anyhow!("invalid name {}", name))
+					})
+					.collect()
+			};
+			let blocks = get_ids("blocks")?;
+			let neighbours = get_ids("neighbours")?;
+			let air_above = abm.get("air_above")
+				.unwrap_or(&Value::Boolean(false));
+			let air_above = *air_above.convert::<bool>()?;
+			let interval = *abm.read::<f64>("interval")?;
+			if !(interval.is_finite() && interval > 0.0) {
+				bail!("invalid abm interval {}, must be a positive number of seconds", interval);
+			}
+			let chance = *abm.read::<i64>("chance")?;
+			let chance = match u32::try_from(chance) {
+				Ok(chance) if chance > 0 => chance,
+				_ => bail!("invalid abm chance {}, must be between 1 and {}", chance, u32::MAX),
+			};
+			let time_range = if let Some(range) = abm.get("time") {
+				let range = range.convert::<Array>()?;
+				let parse = |v :Option<&Value>| -> Result<f32> {
+					let s = v.ok_or_else(|| anyhow!("time range needs a start and an end"))?
+						.convert::<str>()?;
+					parse_hhmm(s).ok_or_else(|| anyhow!("invalid time {}", s))
+				};
+				Some((parse(range.first())?, parse(range.get(1))?))
+			} else {
+				None
+			};
+			let action = match (abm.get("replace"), abm.get("schematic")) {
+				(Some(name), None) => {
+					let name = name.convert::<str>()?;
+					let mb = name_id_map.get_id(name)
+						.ok_or_else(|| anyhow!("invalid name {}", name))?;
+					AbmAction::Replace(mb)
+				},
+				(None, Some(name)) => {
+					let name = name.convert::<str>()?;
+					let schematic = params.p.schematics.get(name)
+						.ok_or_else(|| anyhow!("invalid schematic {}", name))?;
+					AbmAction::Schematic(schematic.clone())
+				},
+				_ => bail!("abm needs exactly one of replace and schematic"),
+			};
+			params.abms.push(Abm {
+				blocks,
+				neighbours,
+				air_above,
+				interval,
+				chance,
+				time_range,
+				action,
+			});
+		}
+	}
+
 	if let Some(mapgen) = val.get("mapgen") {
 		let mapgen = mapgen.convert::<Table>()?;
 		if let Some(ores) = mapgen.get("ore") {
@@ -1,5 +1,8 @@
 use anyhow::Result;
 use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, Condvar};
+use std::time::Duration;
+use std::thread;
 use std::net::{TcpStream, TcpListener, SocketAddr, ToSocketAddrs};
 use std::io::{Read, Write, Error as IoError, ErrorKind};
 use std::mem::replace;
@@ -9,6 +12,36 @@ use bincode::{serialize, deserialize};
 pub trait NetworkServerSocket {
 	type Conn :NetworkServerConn + 'static;
 	fn try_open_conn(&mut self) -> Option<Self::Conn>;
+	/// Blocks until a message or connection might have
+	/// arrived, or until the timeout has passed.
+	///
+	/// Sockets that can't tell when messages arrive just sleep.
+	fn wait_for_msgs(&self, timeout :Duration) {
+		thread::sleep(timeout);
+	}
+}
+
+/// Wakes up a thread waiting for messages
+#[derive(Clone, Default)]
+pub struct Wakeup {
+	woken :Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Wakeup {
+	/// Wakes up the waiting thread, or if none
+	/// is waiting, makes the next wait return immediately
+	pub fn wake(&self) {
+		let (woken, cvar) = &*self.woken;
+		*woken.lock().unwrap() = true;
+		cvar.notify_all();
+	}
+	/// Waits until woken up or until the timeout has passed
+	pub fn wait_timeout(&self, timeout :Duration) {
+		let (woken, cvar) = &*self.woken;
+		let guard = woken.lock().unwrap();
+		let (mut woken, _) = cvar.wait_timeout_while(guard, timeout, |woken| !*woken).unwrap();
+		*woken = false;
+	}
 }
 
 pub trait NetworkServerConn {
@@ -32,6 +65,7 @@ impl NetworkClientConn for Box<dyn NetworkClientConn> {
 
 pub struct MpscServerSocket {
 	srv_conn :Option<MpscServerConn>,
+	wakeup :Wakeup,
 }
 
 pub struct MpscServerConn {
@@ -42,6 +76,7 @@ pub struct MpscServerConn {
 pub struct MpscClientConn {
 	stc_r :Receiver<ServerToClientMsg>,
 	cts_s :Sender<ClientToServerMsg>,
+	wakeup :Wakeup,
 }
 
 impl NetworkServerSocket for MpscServerSocket {
@@ -49,6 +84,9 @@ impl NetworkServerSocket for MpscServerSocket {
 	fn try_open_conn(&mut self) -> Option<Self::Conn> {
 		self.srv_conn.take()
 	}
+	fn wait_for_msgs(&self, timeout :Duration) {
+		self.wakeup.wait_timeout(timeout);
+	}
 }
 
 impl NetworkServerConn for MpscServerConn {
@@ -67,22 +105,26 @@ impl NetworkClientConn for MpscClientConn {
 	}
 	fn send(&self, msg :ClientToServerMsg) -> Result<(), NetErr> {
 		let _ = self.cts_s.send(msg);
+		self.wakeup.wake();
 		Ok(())
 	}
 }
 
 impl MpscServerSocket {
 	pub fn new() -> (Self, MpscClientConn) {
-		let (srv_conn, client_conn) = MpscServerConn::new();
+		let wakeup = Wakeup::default();
+		let (srv_conn, client_conn) = MpscServerConn::new(wakeup.clone());
 		let res = MpscServerSocket {
 			srv_conn : Some(srv_conn),
+			wakeup,
 		};
 		(res, client_conn)
 	}
 }
 
 impl MpscServerConn {
-	pub fn new() -> (Self, MpscClientConn) {
+	/// Creates a connection whose client wakes up the wakeup when sending
+	pub fn new(wakeup :Wakeup) -> (Self, MpscClientConn) {
 		let (stc_s, stc_r) = channel();
 		let (cts_s, cts_r) = channel();
 		let mpsc_socket = MpscServerConn {
@@ -92,6 +134,7 @@ impl MpscServerConn {
 		let srv_conn = MpscClientConn {
 			stc_r,
 			cts_s,
+			wakeup,
 		};
 		(mpsc_socket, srv_conn)
 	}
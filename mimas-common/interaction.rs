@@ -0,0 +1,95 @@
+use nalgebra::Vector3;
+use crate::map::{Map, MapBackend, MapBlock};
+use crate::game_params::{GameParams, DigGroup, ToolGroup};
+use crate::voxel_walk::VoxelWalker;
+
+/// The maximum distance from the player's eye position
+/// at which blocks can be pointed at
+pub const SELECTION_RANGE :f32 = 10.0;
+
+/// How much further away than the selection range the server
+/// still accepts interactions, to account for the player's
+/// position on the server lagging behind the client.
+const REACH_TOLERANCE :f32 = 2.0;
+
+/// Returns whether a player at the given eye position
+/// can reach the block at the given position
+pub fn in_reach(player_pos :Vector3<f32>, pos :Vector3<isize>) -> bool {
+	let block_center = pos.map(|v| v as f32 + 0.5);
+	let max_dist = SELECTION_RANGE + 3.0f32.sqrt() / 2.0 + REACH_TOLERANCE;
+	(block_center - player_pos).norm() <= max_dist
+}
+
+/// Returns the direction a player with the given pitch
+/// and yaw (in degrees) looks into
+pub fn look_direction(pitch :f32, yaw :f32) -> Vector3<f32> {
+	let pitch = (-pitch).to_radians();
+	let yaw = (-yaw).to_radians();
+	Vector3::new(pitch.cos() * yaw.cos(), pitch.cos() * yaw.sin(), pitch.sin())
+}
+
+/// Returns the first pointable block in the given direction
+/// from the given eye position, together with the position
+/// in front of it.
+pub fn pointed_block<B :MapBackend>(map :&Map<B>, params :&GameParams,
+		pos :Vector3<f32>, dir :Vector3<f32>) -> Option<(Vector3<isize>, Vector3<isize>)> {
+	for (vs, ve) in VoxelWalker::new(pos, dir) {
+		let vs = vs.map(|v| v.floor() as isize);
+		let ve = ve.map(|v| v.floor() as isize);
+		if let Some(blk) = map.get_blk(ve) {
+			if params.get_pointability_for_blk(&blk) {
+				return Some((ve, vs));
+			}
+		}
+	}
+	None
+}
+
+/// Returns whether a player at the given eye position
+/// overlaps with the block at the given position
+pub fn player_overlaps_block(player_pos :Vector3<f32>, pos :Vector3<isize>) -> bool {
+	let player_min = player_pos - Vector3::new(0.35, 0.35, 1.8);
+	let player_max = player_pos + Vector3::new(0.35, 0.35, 0.1);
+	let block_min = pos.map(|v| v as f32);
+	let block_max = block_min + Vector3::new(1.0, 1.0, 1.0);
+	(0 .. 3).all(|i| player_min[i] < block_max[i] && block_min[i] < player_max[i])
+}
+
+fn matching_tool_group(tool_groups :&[ToolGroup], dig_group :(DigGroup, u16)) -> Option<&ToolGroup> {
+	tool_groups.iter()
+		.filter(|g| g.group == dig_group.0 || g.group == DigGroup::any())
+		// Only allow digging if hardness is below or at the tool theshold
+		.find(|tg| dig_group.1 <= tg.hardness)
+}
+
+/// Returns the time in seconds it takes to dig the given block
+/// while wielding the given tool.
+///
+/// Returns None if the block can't be dug with the tool.
+pub fn dig_time(params :&GameParams, blk :MapBlock, tool :Option<MapBlock>) -> Option<f32> {
+	let dig_group = params.get_block_params(blk)?.dig_group;
+	let try_tool_groups = |tool_groups :&[ToolGroup]| {
+		matching_tool_group(tool_groups, dig_group)
+			.map(|tg| (0.01 + 1.0/tg.speed) as f32)
+	};
+	// 1. Try if the tool supports the group
+	let tool_time = tool
+		.and_then(|tool| params.get_block_params(tool))
+		.and_then(|bp| try_tool_groups(&bp.tool_groups));
+	// 2. Try the groups of the bare hand
+	tool_time.or_else(|| try_tool_groups(&params.hand_tool_groups))
+}
+
+/// Returns the wear that digging the given block adds to the given tool,
+/// and the maximum wear of the tool.
+///
+/// The wear depends on the hardness of the block's dig group.
+/// Returns None if the tool doesn't wear, or if the block
+/// is dug with the bare hand instead of the tool.
+pub fn tool_wear(params :&GameParams, blk :MapBlock, tool :MapBlock) -> Option<(u16, u16)> {
+	let dig_group = params.get_block_params(blk)?.dig_group;
+	let tool_params = params.get_block_params(tool)?;
+	let max_wear = tool_params.max_wear?;
+	matching_tool_group(&tool_params.tool_groups, dig_group)?;
+	Some((dig_group.1.max(1), max_wear))
+}
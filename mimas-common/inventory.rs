@@ -18,15 +18,22 @@ pub enum Stack {
 	Content {
 		item :MapBlock,
 		count :NonZeroU16,
+		/// Wear of the topmost item of the stack.
+		/// Only stacks without wear can be merged.
+		wear :u16,
 	},
 }
 
 impl Stack {
 	pub fn with(item :MapBlock, count :u16) -> Self {
+		Self::with_wear(item, count, 0)
+	}
+	pub fn with_wear(item :MapBlock, count :u16, wear :u16) -> Self {
 		if let Some(count) = NonZeroU16::new(count) {
 			Stack::Content {
 				item,
 				count,
+				wear,
 			}
 		} else {
 			Stack::Empty
@@ -38,11 +45,37 @@ impl Stack {
 	pub fn content(&self) -> Option<(MapBlock, u16)> {
 		match self {
 			Stack::Empty => None,
-			Stack::Content { item, count, } => {
+			Stack::Content { item, count, .. } => {
 				Some((*item, count.get()))
 			},
 		}
 	}
+	pub fn wear(&self) -> u16 {
+		match self {
+			Stack::Empty => 0,
+			Stack::Content { wear, .. } => *wear,
+		}
+	}
+	/// Adds wear to the topmost item of the stack.
+	///
+	/// If the wear reaches the given maximum, the item breaks
+	/// and gets removed. Returns whether this happened.
+	pub fn add_wear(&mut self, amount :u16, max_wear :u16) -> bool {
+		let new_wear = match self {
+			Stack::Empty => return false,
+			Stack::Content { wear, .. } => wear.saturating_add(amount),
+		};
+		if new_wear >= max_wear {
+			// take_n resets the wear of the remaining items
+			self.take_n(1);
+			true
+		} else {
+			if let Stack::Content { wear, .. } = self {
+				*wear = new_wear;
+			}
+			false
+		}
+	}
 	pub fn put(&mut self, other :Stack, allow_empty :bool,
 			limit :u16) -> Stack {
 		if self.is_empty() {
@@ -52,9 +85,9 @@ impl Stack {
 			*self = other;
 			return Stack::Empty;
 		}
-		if let Stack::Content { item : item2, count : count2, } = other {
+		if let Stack::Content { item : item2, count : count2, wear : wear2 } = other {
 			let (item, count) = self.content().unwrap();
-			if item == item2 {
+			if item == item2 && self.wear() == 0 && wear2 == 0 {
 				let wanted_count = (count as u32) + (count2.get() as u32);
 				let limit_exceeding = wanted_count.saturating_sub(limit as u32);
 				*self = Stack::with(item, (wanted_count - limit_exceeding) as u16);
@@ -67,18 +100,22 @@ impl Stack {
 		let mut emptied = false;
 		let stack_taken = match self {
 			Stack::Empty => Stack::Empty,
-			Stack::Content { item, count, } => {
+			Stack::Content { item, count, wear } => {
 				let item = *item;
+				let taken_wear = *wear;
 				let new_count = count.get().saturating_sub(n);
 				let items_removed = count.get() - new_count;
 				let new_count_nonzero = NonZeroU16::new(new_count);
 				if let Some(new_count) = new_count_nonzero {
+					// The worn item is the topmost one, which
+					// is always the first to be taken.
 					*count = new_count;
+					*wear = 0;
 				} else {
 					*self = Stack::Empty;
 					emptied = true;
 				}
-				Stack::with(item, items_removed)
+				Stack::with_wear(item, items_removed, taken_wear)
 			},
 		};
 		(stack_taken, emptied)
@@ -90,7 +127,7 @@ impl Stack {
 }
 
 // Stack size limit
-const STACK_SIZE_LIMIT :u16 = 60;
+pub const STACK_SIZE_LIMIT :u16 = 60;
 
 pub const HUD_SLOT_COUNT :usize = 8;
 
@@ -197,7 +234,7 @@ impl SelectableInventory {
 		res
 	}
 	pub fn serialize_to(&self, res :&mut Vec<u8>) {
-		res.write_u8(0).unwrap();
+		res.write_u8(1).unwrap();
 		let selection_id = self.selection.unwrap_or(0) + 1;
 		res.write_u16::<BigEndian>(selection_id as u16).unwrap();
 		res.write_u16::<BigEndian>(self.stacks.len() as u16).unwrap();
@@ -207,6 +244,7 @@ impl SelectableInventory {
 				.unwrap_or((0, 0)); // id doesn't matter if count is 0
 			res.write_u8(id).unwrap();
 			res.write_u16::<BigEndian>(count).unwrap();
+			res.write_u16::<BigEndian>(st.wear()).unwrap();
 		}
 	}
 	pub fn deserialize(buf :&[u8], m :&NameIdMap) -> Result<Self> {
@@ -215,7 +253,7 @@ impl SelectableInventory {
 
 	pub fn deserialize_rdr(mut rdr :impl Read, m :&NameIdMap) -> Result<Self> {
 		let version = rdr.read_u8()?;
-		if version != 0 {
+		if version > 1 {
 			// The version is too recent
 			bail!("Unsupported serialized inventory version {}", version);
 		}
@@ -231,12 +269,19 @@ impl SelectableInventory {
 		for _ in 0 .. cnt {
 			let item_id = rdr.read_u8()?;
 			let count = rdr.read_u16::<BigEndian>()?;
+			// Version 0 had no wear
+			let wear = if version >= 1 {
+				rdr.read_u16::<BigEndian>()?
+			} else {
+				0
+			};
 			if let Some(count) = NonZeroU16::new(count) {
 				let item = m.mb_from_id(item_id)
 					.ok_or_else(|| anyhow!("invalid item id"))?;
 				stacks.push(Stack::Content {
 					item,
 					count,
+					wear,
 				});
 			} else {
 				stacks.push(Stack::Empty);
@@ -268,6 +313,18 @@ pub fn merge_or_swap(invs :&mut [impl InvRef],
 	}
 }
 
+/// Whether moving the stack onto the other one
+/// moves or merges it, instead of swapping the two.
+///
+/// Stacks may only be taken from output slots like this.
+pub fn moves_without_swap(from :Stack, to :Stack) -> bool {
+	match (from.content(), to.content()) {
+		(_, None) => true,
+		(Some((from_item, _)), Some((to_item, _))) => from_item == to_item,
+		(None, Some(_)) => false,
+	}
+}
+
 pub fn move_n_if_possible(invs :&mut [impl InvRef],
 		from :(usize, usize), to :(usize, usize), count :u16) {
 	let stack_from = invs[from.0].as_mut().stacks[from.1].take_n(count).0;
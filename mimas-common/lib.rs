@@ -36,11 +36,16 @@ pub mod sqlite_generic;
 pub mod local_auth;
 pub mod inventory;
 pub mod crafting;
+pub mod interaction;
 pub mod game_params;
 pub mod toml_util;
 pub mod protocol;
 pub mod player;
 pub mod schematic;
+pub mod time_of_day;
+pub mod facedir;
+pub mod voxel_walk;
+pub mod collide;
 
 pub use protocol::{ClientToServerMsg, ServerToClientMsg};
 use map::CHUNKSIZE;
@@ -1,10 +1,12 @@
 use nalgebra::Vector3;
 use std::collections::hash_map::{HashMap, Entry};
+use std::collections::HashSet;
 use serde_big_array::BigArray;
 use crate::{btchn, btpic};
 use crate::map_storage::PlayerIdPair;
 use crate::game_params::{ServerGameParamsHdl, Id, UncheckedId};
-use crate::inventory::SelectableInventory;
+use crate::inventory::{SelectableInventory, Stack};
+use crate::facedir::Facedir;
 
 use super::schematic::Schematic;
 
@@ -33,22 +35,119 @@ impl Id for MapBlock {
 	}
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub enum MetadataEntry {
 	Inventory(SelectableInventory),
-	//Text(String), // TODO
-	//Orientation, // TODO
+	Furnace(FurnaceState),
+	/// The text of a sign
+	Text(String),
+	LockedChest(LockedChestState),
+}
+
+impl MetadataEntry {
+	/// The inventory of the chest or furnace
+	pub fn inventory(&self) -> Option<&SelectableInventory> {
+		match self {
+			MetadataEntry::Inventory(inv) => Some(inv),
+			MetadataEntry::Furnace(state) => Some(&state.inv),
+			MetadataEntry::Text(_) => None,
+			MetadataEntry::LockedChest(state) => Some(&state.inv),
+		}
+	}
+	pub fn inventory_mut(&mut self) -> Option<&mut SelectableInventory> {
+		match self {
+			MetadataEntry::Inventory(inv) => Some(inv),
+			MetadataEntry::Furnace(state) => Some(&mut state.inv),
+			MetadataEntry::Text(_) => None,
+			MetadataEntry::LockedChest(state) => Some(&mut state.inv),
+		}
+	}
+	/// Whether the player may access the inventory
+	/// or dig the block
+	pub fn may_access(&self, id :PlayerIdPair) -> bool {
+		match self {
+			MetadataEntry::LockedChest(state) => state.may_access(id),
+			_ => true,
+		}
+	}
+}
+
+/// A chest that only its owner and the members can access
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct LockedChestState {
+	pub inv :SelectableInventory,
+	pub owner :PlayerIdPair,
+	/// Players the owner has given access to
+	pub members :Vec<PlayerIdPair>,
+}
+
+impl LockedChestState {
+	pub fn may_access(&self, id :PlayerIdPair) -> bool {
+		self.owner == id || self.members.contains(&id)
+	}
+}
+
+/// Maximum number of characters of a sign's text
+pub const SIGN_TEXT_MAX_LEN :usize = 256;
+
+/// Maximum number of item entities in a chunk, as their
+/// count is stored as a u16
+pub const MAX_ITEM_ENTITIES_PER_CHUNK :usize = u16::MAX as usize;
+
+/// Slot of the furnace inventory holding the items to cook
+pub const FURNACE_INPUT_SLOT :usize = 0;
+/// Slot of the furnace inventory holding the fuel
+pub const FURNACE_FUEL_SLOT :usize = 1;
+/// Slot of the furnace inventory holding the cooked items
+pub const FURNACE_OUTPUT_SLOT :usize = 2;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct FurnaceState {
+	/// The input, fuel and output slots
+	pub inv :SelectableInventory,
+	/// Seconds the current input item has been cooking
+	pub cook_time :f32,
+	/// Seconds the current input item needs to cook,
+	/// or 0.0 if nothing is cooking
+	pub cook_time_total :f32,
+	/// Seconds the current fuel item still burns
+	pub burn_time :f32,
+	/// Seconds the current fuel item burns in total
+	pub burn_time_total :f32,
+}
+
+impl Default for FurnaceState {
+	fn default() -> Self {
+		Self {
+			inv : SelectableInventory::empty_with_size(3),
+			cook_time : 0.0,
+			cook_time_total : 0.0,
+			burn_time : 0.0,
+			burn_time_total : 0.0,
+		}
+	}
+}
+
+/// An item stack lying in the world
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ItemEntity {
+	pub pos :Vector3<f32>,
+	pub stack :Stack,
+	/// Unix time (in seconds) when the entity was spawned
+	pub spawned_at :u64,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct MapChunkMetadata {
 	pub metadata :HashMap<Vector3<u8>, MetadataEntry>,
+	pub item_entities :Vec<ItemEntity>,
 }
 
 impl MapChunkMetadata {
 	pub fn empty() -> Self {
 		Self {
 			metadata : HashMap::new(),
+			item_entities : Vec::new(),
 		}
 	}
 }
@@ -58,12 +157,21 @@ pub struct MapChunkData(
 	#[serde(with = "BigArray")]
 	pub [MapBlock; (CHUNKSIZE * CHUNKSIZE * CHUNKSIZE) as usize],
 	pub MapChunkMetadata,
+	/// The facedirs of the blocks, in the same order as the blocks.
+	/// Empty if all blocks have the default facedir.
+	pub Vec<u8>,
 );
 
+/// Handler for changes of the item entities of a chunk
+pub type ItemEntitiesHandler = Box<dyn Fn(Vector3<isize>, &[ItemEntity])>;
+
 pub struct Map<B :MapBackend> {
 	backend :B,
 	chunks :HashMap<Vector3<isize>, MapChunkData>,
 	on_change :Box<dyn Fn(Vector3<isize>, &MapChunkData)>,
+	on_item_entities_change :ItemEntitiesHandler,
+	/// Chunks with changes that haven't been given to the backend yet
+	unstored_chunks :HashSet<Vector3<isize>>,
 }
 
 pub type ClientMap = Map<ClientBackend>;
@@ -74,7 +182,7 @@ impl MapChunkData {
 	}
 	pub fn filled_with(m :MapBlock) -> Self {
 		Self([m; (CHUNKSIZE * CHUNKSIZE * CHUNKSIZE) as usize],
-			MapChunkMetadata::empty())
+			MapChunkMetadata::empty(), Vec::new())
 	}
 	pub fn get_blk_mut(&mut self, pos :Vector3<isize>) -> &mut MapBlock {
 		let (x, y, z) = (pos.x, pos.y, pos.z);
@@ -90,12 +198,30 @@ impl MapChunkData {
 	pub fn get_blk_meta(&self, pos :Vector3<isize>) -> Option<&MetadataEntry> {
 		self.1.metadata.get(&pos.map(|v| v as u8))
 	}
+	pub fn get_facedir(&self, pos :Vector3<isize>) -> Facedir {
+		let (x, y, z) = (pos.x, pos.y, pos.z);
+		self.2.get((x * CHUNKSIZE * CHUNKSIZE + y * CHUNKSIZE + z) as usize)
+			.and_then(|v| Facedir::from_u8(*v))
+			.unwrap_or_default()
+	}
+	pub fn set_facedir(&mut self, pos :Vector3<isize>, facedir :Facedir) {
+		if self.2.is_empty() {
+			if facedir == Facedir::default() {
+				return;
+			}
+			self.2 = vec![0; (CHUNKSIZE * CHUNKSIZE * CHUNKSIZE) as usize];
+		}
+		let (x, y, z) = (pos.x, pos.y, pos.z);
+		self.2[(x * CHUNKSIZE * CHUNKSIZE + y * CHUNKSIZE + z) as usize] = facedir.to_u8();
+	}
 }
 
-fn spawn_schematic<B :MapBackend>(map :&mut Map<B>, pos :Vector3<isize>, schematic :&Schematic) {
+pub fn spawn_schematic<B :MapBackend>(map :&mut Map<B>, pos :Vector3<isize>, schematic :&Schematic) {
 	for (bpos, mb) in schematic.items.iter() {
-		let blk = map.get_blk_mut_no_upd(pos + bpos).unwrap();
-		*blk = *mb;
+		// Parts in unloaded chunks are left out
+		if let Some(blk) = map.get_blk_mut_no_upd(pos + bpos) {
+			*blk = *mb;
+		}
 	}
 	let pos_min = btchn(pos + schematic.aabb_min);
 	let pos_max = btchn(pos + schematic.aabb_max);
@@ -126,9 +252,13 @@ pub struct MapBlockHandle<'a, B :MapBackend> {
 
 impl<'a, B :MapBackend> MapBlockHandle<'a, B> {
 	pub fn set(&mut self, b :MapBlock) {
+		self.set_with_facedir(b, Facedir::default());
+	}
+	pub fn set_with_facedir(&mut self, b :MapBlock, facedir :Facedir) {
 		let chunk_pos = btchn(self.pos);
 		let pos_in_chunk = btpic(self.pos);
 		*self.chk.get_blk_mut(pos_in_chunk) = b;
+		self.chk.set_facedir(pos_in_chunk, facedir);
 		self.backend.chunk_changed(chunk_pos, self.chk.clone());
 		(*self.on_change)(chunk_pos, &self.chk);
 	}
@@ -207,6 +337,12 @@ impl MapBackend for ClientBackend {
 			&mut self, _f :&mut F) {
 		// Do nothing. There is no storage on the client.
 	}
+	fn set_global_kv(&mut self, _key :&str, _value :Vec<u8>) {
+		// Do nothing. There is no storage on the client.
+	}
+	fn flush(&mut self) {
+		// Do nothing. There is no storage on the client.
+	}
 }
 
 pub trait MapBackend {
@@ -219,6 +355,9 @@ pub trait MapBackend {
 	fn get_player_kv(&mut self, id: PlayerIdPair, key :&str, data :u32);
 	fn run_for_kv_results<F :FnMut(PlayerIdPair, u32, String, Option<Vec<u8>>)>(
 		&mut self, f :&mut F);
+	fn set_global_kv(&mut self, key :&str, value :Vec<u8>);
+	/// Blocks until all changes have been written to storage
+	fn flush(&mut self);
 }
 
 impl Map<ClientBackend> {
@@ -233,11 +372,18 @@ impl<B :MapBackend> Map<B> {
 			backend,
 			chunks : HashMap::new(),
 			on_change : Box::new(|_, _| {}),
+			on_item_entities_change : Box::new(|_, _| {}),
+			unstored_chunks : HashSet::new(),
 		}
 	}
 	pub fn register_on_change(&mut self, f :Box<dyn Fn(Vector3<isize>, &MapChunkData)>) {
 		self.on_change = f;
 	}
+	/// Registers the handler for changes of item entities,
+	/// which are not passed to the on_change handler
+	pub fn register_on_item_entities_change(&mut self, f :ItemEntitiesHandler) {
+		self.on_item_entities_change = f;
+	}
 	pub fn get_chunk(&self, pos :Vector3<isize>) -> Option<&MapChunkData> {
 		self.chunks.get(&pos)
 	}
@@ -253,6 +399,20 @@ impl<B :MapBackend> Map<B> {
 			pos_max :Vector3<isize>) {
 		self.backend.gen_chunks_in_area(pos_min, pos_max,);
 	}
+	/// Gives the changes that are only stored
+	/// from time to time to the backend
+	pub fn store_changes(&mut self) {
+		for pos in self.unstored_chunks.drain() {
+			if let Some(chk) = self.chunks.get(&pos) {
+				self.backend.chunk_changed(pos, chk.clone());
+			}
+		}
+	}
+	/// Blocks until all changes have been written to storage
+	pub fn flush(&mut self) {
+		self.store_changes();
+		self.backend.flush();
+	}
 	pub fn tick(&mut self) {
 		let on_change = &self.on_change;
 		let chunks = &mut self.chunks;
@@ -303,6 +463,88 @@ impl<B :MapBackend> Map<B> {
 		self.get_chunk(chunk_pos)
 			.map(|blk| blk.get_blk_meta(pos_in_chunk))
 	}
+	/// Adds the item entity to the chunk at its position.
+	///
+	/// Gives the entity back if the chunk is not loaded,
+	/// or if it already has the maximum number of item entities.
+	pub fn add_item_entity(&mut self, entity :ItemEntity) -> Option<ItemEntity> {
+		let chunk_pos = btchn(entity.pos.map(|v| v.floor() as isize));
+		let chk = if let Some(chk) = self.chunks.get_mut(&chunk_pos) {
+			chk
+		} else {
+			return Some(entity);
+		};
+		if chk.1.item_entities.len() >= MAX_ITEM_ENTITIES_PER_CHUNK {
+			return Some(entity);
+		}
+		chk.1.item_entities.push(entity);
+		self.unstored_chunks.insert(chunk_pos);
+		(self.on_item_entities_change)(chunk_pos, &chk.1.item_entities);
+		None
+	}
+	/// Runs the closure on the item entities of the given chunk.
+	///
+	/// The closure may modify the entity, and returns whether
+	/// to keep it. Changes are passed to the handler for item
+	/// entity changes, and stored with the next store_changes call.
+	pub fn update_item_entities(&mut self, chunk_pos :Vector3<isize>,
+			mut f :impl FnMut(&mut ItemEntity) -> bool) {
+		let chk = if let Some(chk) = self.chunks.get_mut(&chunk_pos) {
+			chk
+		} else {
+			return;
+		};
+		let mut changed = false;
+		// Vec::retain only gives shared references to the entities
+		let keep = chk.1.item_entities.iter_mut()
+			.map(|ent| {
+				let before = ent.stack;
+				let keep = f(ent);
+				changed |= !keep || ent.stack != before;
+				keep
+			})
+			.collect::<Vec<_>>();
+		let mut keep = keep.into_iter();
+		chk.1.item_entities.retain(|_ent| keep.next().unwrap());
+		if changed {
+			self.unstored_chunks.insert(chunk_pos);
+			(self.on_item_entities_change)(chunk_pos, &chk.1.item_entities);
+		}
+	}
+	/// Replaces the item entities of the given chunk, if it is loaded
+	pub fn set_item_entities(&mut self, chunk_pos :Vector3<isize>, entities :Vec<ItemEntity>) {
+		if let Some(chk) = self.chunks.get_mut(&chunk_pos) {
+			chk.1.item_entities = entities;
+		}
+	}
+	/// Calls the function on the metadata entries of all loaded chunks.
+	///
+	/// The function returns whether it has changed the entry.
+	/// Returns the positions of the changed entries. The changes are
+	/// stored with the next store_changes call, but not passed to the
+	/// on_change handler.
+	pub fn update_metadata(&mut self,
+			mut f :impl FnMut(Vector3<isize>, &mut MetadataEntry) -> bool) -> Vec<Vector3<isize>> {
+		let mut changed = Vec::new();
+		for (chunk_pos, chk) in self.chunks.iter_mut() {
+			let changed_before = changed.len();
+			for (pos, entry) in chk.1.metadata.iter_mut() {
+				let pos = chunk_pos + pos.map(|v| v as isize);
+				if f(pos, entry) {
+					changed.push(pos);
+				}
+			}
+			if changed.len() > changed_before {
+				self.unstored_chunks.insert(*chunk_pos);
+			}
+		}
+		changed
+	}
+	/// Iterates over the item entities of all loaded chunks
+	pub fn item_entities(&self) -> impl Iterator<Item = &ItemEntity> {
+		self.chunks.values()
+			.flat_map(|chk| chk.1.item_entities.iter())
+	}
 	pub fn set_player_kv(&mut self, id :PlayerIdPair, key :&str, value :Vec<u8>) {
 		self.backend.set_player_kv(id, key, value);
 	}
@@ -312,4 +554,7 @@ impl<B :MapBackend> Map<B> {
 	pub fn run_for_kv_results<F :FnMut(PlayerIdPair, u32, String, Option<Vec<u8>>)>(&mut self, f :&mut F) {
 		self.backend.run_for_kv_results(f);
 	}
+	pub fn set_global_kv(&mut self, key :&str, value :Vec<u8>) {
+		self.backend.set_global_kv(key, value);
+	}
 }
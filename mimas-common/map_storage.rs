@@ -79,7 +79,7 @@ fn test_player_id_pair() {
 	}
 }
 
-#[derive(Clone, Copy, Serialize, Deserialize)]
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct PlayerPosition {
 	x :f32,
 	y :f32,
@@ -1,3 +1,6 @@
+/// The maximum (and initial) health of a player
+pub const MAX_HP :u16 = 20;
+
 #[derive(Serialize, Deserialize, Hash, Clone, Copy, PartialEq, Eq)]
 pub enum PlayerMode {
 	/// The player has fly mode enabled
@@ -1,12 +1,24 @@
-use crate::map::{MapChunkData, MapBlock};
+use crate::map::{MapChunkData, MapBlock, MetadataEntry, ItemEntity};
 use crate::map_storage::{PlayerIdPair, PlayerPosition};
 use crate::inventory::{SelectableInventory, InventoryPos};
 use crate::local_auth::{PlayerPwHash, HashParams};
 use crate::game_params::GameParams;
 use crate::player::PlayerMode;
+use crate::facedir::Facedir;
 use nalgebra::Vector3;
 use std::collections::HashSet;
 
+/// The kind of a chat message, for the client to style it
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChatKind {
+	/// A message of a player to everyone
+	Public,
+	/// A message of a player to only the receiving one
+	Private,
+	/// A message of the server
+	System,
+}
+
 #[derive(Serialize, Deserialize)]
 pub enum ClientToServerMsg {
 	LogIn(String, Vec<u8>),
@@ -14,24 +26,40 @@ pub enum ClientToServerMsg {
 	SendM1(Vec<u8>), // Auth for existing users
 	GetHashedBlobs(Vec<Vec<u8>>),
 
-	/// Params: Position, current inventory selection location, mapblock to place
+	/// Params: Position, current inventory selection location, mapblock to place,
+	/// orientation of the block
 	///
 	/// The redundancy of specifying both inventory selection location
 	/// and mapblock to place allows the server to recognize cases
 	/// where client and server have desynced, and prevents mistakingly
 	/// placing a wrong block.
-	PlaceBlock(Vector3<isize>, usize, MapBlock),
+	PlaceBlock(Vector3<isize>, usize, MapBlock, Facedir),
 	/// Params: Position, current inventory selection location, mapblock to place
 	PlaceTree(Vector3<isize>, usize, MapBlock),
+	/// The client has started digging the block at the given position
+	StartDig(Vector3<isize>),
+	/// The client has finished digging the block at the given position
 	Dig(Vector3<isize>),
 
 	SetPos(PlayerPosition),
 	/// Client has enabled/disabled a mode
 	SetMode(PlayerMode, bool),
 	InventorySwap(InventoryPos, InventoryPos, bool),
+	/// The client has opened the chest or furnace at the given position
+	OpenChest(Vector3<isize>),
+	/// The client has closed the chest or furnace at the given position
+	CloseChest(Vector3<isize>),
 	Craft,
+	/// Crafts as many times as the craft inventory allows
+	CraftMax,
 	InventorySelect(Option<usize>),
+	/// Drop the selected stack into the world
+	DropSelected,
 	Chat(String),
+	/// Sets the text of the sign at the given position
+	SetSignText(Vector3<isize>, String),
+	/// Requests the completions of a partially typed command
+	CompleteCommand(String),
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -48,6 +76,20 @@ pub enum ServerToClientMsg {
 	SetInventory(SelectableInventory),
 	SetCraftInventory(SelectableInventory),
 	SetModes(HashSet<PlayerMode>),
+	/// Sets the health of the player
+	SetHp(u16),
+	/// Sets the time of day and the speed it advances at
+	SetTimeOfDay(f32, f32),
 	ChunkUpdated(Vector3<isize>, MapChunkData),
-	Chat(String),
+	/// Sets the item entities of a chunk the client has been sent
+	ItemEntitiesUpdated(Vector3<isize>, Vec<ItemEntity>),
+	/// Sets the contents of a chest or furnace the client has opened.
+	/// None means that the client can't view it (any more).
+	SetChestMeta(Vector3<isize>, Option<MetadataEntry>),
+	Chat(ChatKind, String),
+	/// The server closes the connection, for the given reason
+	Disconnect(String),
+	/// The completions of a partially typed command: the
+	/// typed text, and the full texts it can be completed to
+	CommandCompletions(String, Vec<String>),
 }
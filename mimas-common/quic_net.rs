@@ -1,10 +1,10 @@
 use anyhow::Result;
 use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
-use std::time::SystemTime;
+use std::time::{SystemTime, Duration};
 use std::net::{SocketAddr, ToSocketAddrs};
 use quinn::RecvStream;
 use crate::generic_net::{MsgStream, NetErr, MsgStreamClientConn,
-	MsgStreamServerConn, NetworkServerSocket};
+	MsgStreamServerConn, NetworkServerSocket, Wakeup};
 use std::sync::Arc;
 
 use std::thread;
@@ -53,7 +53,8 @@ impl ServerCertVerifier for NullVerifier {
 	}
 }
 
-fn run_quinn_server(addr :&SocketAddr, conn_send :Sender<QuicServerConn>) -> Result<()> {
+fn run_quinn_server(addr :&SocketAddr, conn_send :Sender<QuicServerConn>,
+		wakeup :Wakeup) -> Result<()> {
 
 	let cert = rcgen::generate_simple_self_signed(vec!["mimas-host".into()])?;
 
@@ -84,6 +85,7 @@ fn run_quinn_server(addr :&SocketAddr, conn_send :Sender<QuicServerConn>) -> Res
 
 		while let Some(connecting) = incoming.next().await {
 			let sender_clone = conn_send.clone();
+			let wakeup = wakeup.clone();
 			tokio::spawn(async move { loop {
 				let new_conn = if let Ok(new_conn) = connecting.await {
 					new_conn
@@ -105,8 +107,9 @@ fn run_quinn_server(addr :&SocketAddr, conn_send :Sender<QuicServerConn>) -> Res
 					addr,
 				};
 				ltry!(sender_clone.send(conn); break);
+				wakeup.wake();
 
-				spawn_msg_rcv_task(rdr, snd);
+				spawn_msg_rcv_task(rdr, snd, Some(wakeup));
 
 				while let Some(msg) = rcv.next().await {
 					let len_buf = (msg.len() as u64).to_be_bytes();
@@ -124,7 +127,8 @@ fn run_quinn_server(addr :&SocketAddr, conn_send :Sender<QuicServerConn>) -> Res
 	})
 }
 
-async fn msg_rcv_task(mut rdr :RecvStream, to_receive :Sender<Vec<u8>>) {
+async fn msg_rcv_task(mut rdr :RecvStream, to_receive :Sender<Vec<u8>>,
+		wakeup :Option<Wakeup>) {
 	loop {
 		let mut len_buf = [0; 8];
 		if let Err(e) = rdr.read_exact(&mut len_buf).await {
@@ -141,11 +145,20 @@ async fn msg_rcv_task(mut rdr :RecvStream, to_receive :Sender<Vec<u8>>) {
 		let mut buf = vec![0; len];
 		ltry!(rdr.read_exact(&mut buf).await; break);
 		ltry!(to_receive.send(buf); break);
+		if let Some(wakeup) = &wakeup {
+			wakeup.wake();
+		}
+	}
+	// Let the server notice the closed connection
+	drop(to_receive);
+	if let Some(wakeup) = &wakeup {
+		wakeup.wake();
 	}
 }
 
-fn spawn_msg_rcv_task(rdr :RecvStream, to_receive :Sender<Vec<u8>>) {
-	tokio::spawn(msg_rcv_task(rdr, to_receive));
+fn spawn_msg_rcv_task(rdr :RecvStream, to_receive :Sender<Vec<u8>>,
+		wakeup :Option<Wakeup>) {
+	tokio::spawn(msg_rcv_task(rdr, to_receive, wakeup));
 }
 
 fn run_quinn_client(url :impl ToSocketAddrs,
@@ -196,7 +209,7 @@ fn run_quinn_client(url :impl ToSocketAddrs,
 					break Ok(());
 				},
 			};
-			spawn_msg_rcv_task(rdr, to_receive);
+			spawn_msg_rcv_task(rdr, to_receive, None);
 			while let Some(msg) = to_send.next().await {
 				let len_buf = (msg.len() as u64).to_be_bytes();
 				ltry!(wtr.write_all(&len_buf).await; break);
@@ -261,6 +274,7 @@ impl QuicClientConn {
 pub struct QuicServerSocket {
 	listen_addr :SocketAddr,
 	conn_recv :Receiver<QuicServerConn>,
+	wakeup :Wakeup,
 }
 
 impl QuicServerSocket {
@@ -271,13 +285,16 @@ impl QuicServerSocket {
 	pub fn with_socket_addr(addr :&SocketAddr) -> Result<Self> {
 		let addr = addr.clone();
 		let (conn_send, conn_recv) = channel();
+		let wakeup = Wakeup::default();
+		let wakeup_clone = wakeup.clone();
 
 		thread::spawn(move || {
-			run_quinn_server(&addr, conn_send).expect("errors in quic server");
+			run_quinn_server(&addr, conn_send, wakeup_clone).expect("errors in quic server");
 		});
 		Ok(Self {
 			listen_addr : addr,
-			conn_recv
+			conn_recv,
+			wakeup,
 		})
 	}
 	pub fn listen_addr(&self) -> &SocketAddr {
@@ -290,4 +307,7 @@ impl NetworkServerSocket for QuicServerSocket {
 	fn try_open_conn(&mut self) -> Option<Self::Conn> {
 		self.conn_recv.try_recv().ok()
 	}
+	fn wait_for_msgs(&self, timeout :Duration) {
+		self.wakeup.wait_timeout(timeout);
+	}
 }
@@ -0,0 +1,100 @@
+//! Time of day helpers.
+//!
+//! The time of day is represented as the elapsed fraction
+//! of the day, in the range [0, 1), with 0 being midnight
+//! and 0.5 being noon.
+
+use std::f32::consts::PI;
+
+/// Length of a day in seconds at a time speed of 1
+const DAY_SECS :f32 = 86_400.0;
+
+/// Advances the given time of day by the given number of
+/// real seconds at the given speed
+pub fn advance(time :f32, secs :f32, speed :f32) -> f32 {
+	(time + secs * speed / DAY_SECS).rem_euclid(1.0)
+}
+
+/// Returns the light level at the given time of day,
+/// 0.0 at night and 1.0 during the day, with smooth
+/// transitions at dawn and dusk.
+pub fn daylight(time :f32) -> f32 {
+	(0.5 - (2.0 * PI * time).cos() * 1.5).clamp(0.0, 1.0)
+}
+
+/// Parses a time in the form HH:MM
+pub fn parse_hhmm(s :&str) -> Option<f32> {
+	let mut it = s.splitn(2, ':');
+	let hours = it.next()?.parse::<u32>().ok()?;
+	let minutes = it.next()?.parse::<u32>().ok()?;
+	if hours > 24 || minutes >= 60 || (hours == 24 && minutes > 0) {
+		return None;
+	}
+	Some(((hours * 60 + minutes) as f32 / (24.0 * 60.0)).rem_euclid(1.0))
+}
+
+/// Formats the time of day in the form HH:MM
+pub fn format_hhmm(time :f32) -> String {
+	let minutes = (time.rem_euclid(1.0) * 24.0 * 60.0) as u32 % (24 * 60);
+	format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// Returns whether the time of day is inside the range
+/// that starts at from and ends at to.
+///
+/// If from is larger than to, the range contains midnight.
+pub fn in_range(time :f32, from :f32, to :f32) -> bool {
+	if from <= to {
+		from <= time && time < to
+	} else {
+		from <= time || time < to
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_time_of_day() {
+	let close = |a :f32, b :f32| (a - b).abs() < 1e-4;
+
+	// An hour at normal speed, and wrapping around midnight
+	assert!(close(advance(0.5, 3600.0, 1.0), 0.5 + 1.0 / 24.0));
+	assert!(close(advance(0.75, 3600.0, 12.0), 0.25));
+	assert!(close(advance(0.9, 8640.0, 2.0), 0.1));
+	assert!(close(advance(0.1, -8640.0, 1.0), 0.0));
+	assert!(advance(0.99, DAY_SECS * 5.0 + 1.0, 1.0) < 1.0);
+
+	assert_eq!(daylight(0.0), 0.0);
+	assert_eq!(daylight(0.5), 1.0);
+	let dawn = daylight(0.25);
+	assert!(dawn > 0.0 && dawn < 1.0);
+
+	assert_eq!(parse_hhmm("00:00"), Some(0.0));
+	assert_eq!(parse_hhmm("12:00"), Some(0.5));
+	assert_eq!(parse_hhmm("06:00"), Some(0.25));
+	assert_eq!(parse_hhmm("24:00"), Some(0.0));
+	assert_eq!(parse_hhmm("24:01"), None);
+	assert_eq!(parse_hhmm("25:00"), None);
+	assert_eq!(parse_hhmm("12:60"), None);
+	assert_eq!(parse_hhmm("12"), None);
+	assert_eq!(parse_hhmm("ab:cd"), None);
+	assert_eq!(parse_hhmm("-1:00"), None);
+
+	assert_eq!(format_hhmm(0.0), "00:00");
+	assert_eq!(format_hhmm(0.5), "12:00");
+	assert_eq!(format_hhmm(1.0), "00:00");
+	assert_eq!(format_hhmm(-0.25), "18:00");
+	for s in ["00:00", "06:30", "13:45", "23:59"].iter() {
+		assert_eq!(format_hhmm(parse_hhmm(s).unwrap()), *s);
+	}
+
+	assert!(in_range(0.5, 0.25, 0.75));
+	assert!(!in_range(0.8, 0.25, 0.75));
+	assert!(!in_range(0.75, 0.25, 0.75));
+	// A range from 20:00 to 06:00 contains midnight
+	let (from, to) = (parse_hhmm("20:00").unwrap(), parse_hhmm("06:00").unwrap());
+	assert!(in_range(0.9, from, to));
+	assert!(in_range(0.0, from, to));
+	assert!(in_range(0.1, from, to));
+	assert!(!in_range(0.5, from, to));
+	assert!(!in_range(to, from, to));
+}
@@ -1,4 +1,5 @@
 use nalgebra::Vector3;
+use crate::interaction::SELECTION_RANGE;
 
 pub struct VoxelWalker {
 	first : bool,
@@ -43,10 +44,9 @@ fn next_for_dim(off :f32, dir :f32) -> f32 {
 		off_floor + 1.0
 	};
 	if dir == 0.0 {
-		return std::f32::INFINITY;
+		return f32::INFINITY;
 	}
-	let m = (off_next - off) / dir + 0.001;
-	m
+	(off_next - off) / dir + 0.001
 }
 
 #[cfg(test)]
@@ -98,7 +98,6 @@ impl Iterator for VoxelWalker {
 			return Some((self.pos, self.pos));
 		}
 		let next_pos = self.peek_next();
-		const SELECTION_RANGE :f32 = 10.0;
 		if (next_pos - self.start).norm() < SELECTION_RANGE {
 			let old_pos = self.pos;
 			self.pos = next_pos;
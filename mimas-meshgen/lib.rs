@@ -9,6 +9,7 @@ use mimas_common::map::{MapChunkData,
 	CHUNKSIZE};
 use mimas_common::game_params::{GameParamsHdl, DrawStyle, Id};
 use mimas_common::map::MapBlock;
+use mimas_common::facedir::Facedir;
 use nalgebra::Vector3;
 
 #[repr(transparent)]
@@ -68,6 +69,18 @@ impl BlockTextureIds {
 			id_sides, id_top, id_bottom,
 		}
 	}
+	/// Returns the texture of the face with the given normal
+	/// for a block that is rotated according to the facedir
+	pub fn for_face(&self, normal :[isize; 3], facedir :Facedir) -> TextureId {
+		let top = facedir.top_dir();
+		if normal == top {
+			self.id_top
+		} else if normal == [-top[0], -top[1], -top[2]] {
+			self.id_bottom
+		} else {
+			self.id_sides
+		}
+	}
 }
 
 #[derive(Clone)]
@@ -293,7 +306,6 @@ pub fn mesh_for_chunk(offs :Vector3<isize>, chunk :&MapChunkData,
 	}
 	fn walk_for_all_blocks<G :FnMut(&mut Walker<TextureId>, Option<TextureId>, Vector3<isize>)>(
 			f :fn(isize, isize, isize) -> Vector3<isize>,
-			face :fn(BlockTextureIds) -> TextureId,
 			offsets :[isize; 3],
 			chunk :&MapChunkData, g :&mut G,
 			cache :&TextureIdCache) {
@@ -303,7 +315,8 @@ pub fn mesh_for_chunk(offs :Vector3<isize>, chunk :&MapChunkData,
 				for cinner in 0 .. CHUNKSIZE {
 					let rel_pos = f(c1, c2, cinner);
 					let texture_ids = get_tex_ind(chunk, rel_pos, offsets, cache);
-					let tex_ind = texture_ids.map(face);
+					let facedir = chunk.get_facedir(rel_pos);
+					let tex_ind = texture_ids.map(|ids| ids.for_face(offsets, facedir));
 					g(&mut walker, tex_ind, rel_pos)
 				}
 				let rel_pos = f(c1, c2, CHUNKSIZE);
@@ -316,7 +329,6 @@ pub fn mesh_for_chunk(offs :Vector3<isize>, chunk :&MapChunkData,
 	// X-Y face (unify over y)
 	walk_for_all_blocks(
 		|c1, c2, cinner| Vector3::new(c1, cinner, c2),
-		|bti| bti.id_bottom,
 		[0, 0, -1],
 		chunk,
 		&mut |walker, color, rel_pos| {
@@ -332,7 +344,6 @@ pub fn mesh_for_chunk(offs :Vector3<isize>, chunk :&MapChunkData,
 	// X-Z face (unify over x)
 	walk_for_all_blocks(
 		|c1, c2, cinner| Vector3::new(cinner, c1, c2),
-		|bti| bti.id_sides,
 		[0, -1, 0],
 		chunk,
 		&mut |walker, color, rel_pos| {
@@ -348,7 +359,6 @@ pub fn mesh_for_chunk(offs :Vector3<isize>, chunk :&MapChunkData,
 	// Y-Z face (unify over y)
 	walk_for_all_blocks(
 		|c1, c2, cinner| Vector3::new(c1, cinner, c2),
-		|bti| bti.id_sides,
 		[-1, 0, 0],
 		chunk,
 		&mut |walker, color, rel_pos| {
@@ -364,7 +374,6 @@ pub fn mesh_for_chunk(offs :Vector3<isize>, chunk :&MapChunkData,
 	// X-Y face (z+1) (unify over y)
 	walk_for_all_blocks(
 		|c1, c2, cinner| Vector3::new(c1, cinner, c2),
-		|bti| bti.id_top,
 		[0, 0, 1],
 		chunk,
 		&mut |walker, color, rel_pos| {
@@ -380,7 +389,6 @@ pub fn mesh_for_chunk(offs :Vector3<isize>, chunk :&MapChunkData,
 	// X-Z face (y+1) (unify over x)
 	walk_for_all_blocks(
 		|c1, c2, cinner| Vector3::new(cinner, c1, c2),
-		|bti| bti.id_sides,
 		[0, 1, 0],
 		chunk,
 		&mut |walker, color, rel_pos| {
@@ -396,7 +404,6 @@ pub fn mesh_for_chunk(offs :Vector3<isize>, chunk :&MapChunkData,
 	// Y-Z face (x+1) (unify over y)
 	walk_for_all_blocks(
 		|c1, c2, cinner| Vector3::new(c1, cinner, c2),
-		|bti| bti.id_sides,
 		[1, 0, 0],
 		chunk,
 		&mut |walker, color, rel_pos| {
@@ -0,0 +1,137 @@
+use mimas_common::map::{Map, MapBackend, CHUNKSIZE};
+use mimas_common::game_params::{ServerGameParams, Abm};
+use nalgebra::Vector3;
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::time::{Instant, Duration};
+
+/// Keeps track of when each active block modifier has last run
+pub struct AbmTimer {
+	last_runs :Vec<Instant>,
+}
+
+impl AbmTimer {
+	pub fn new(params :&ServerGameParams, now :Instant) -> Self {
+		Self {
+			last_runs : params.abms.iter().map(|_| now).collect(),
+		}
+	}
+	/// Returns the indices of the modifiers in the params
+	/// whose interval has passed since they last ran
+	pub fn take_due(&mut self, params :&ServerGameParams, now :Instant) -> Vec<usize> {
+		let mut due = Vec::new();
+		for (idx, (abm, last_run)) in params.abms.iter().zip(self.last_runs.iter_mut()).enumerate() {
+			if now - *last_run >= Duration::from_secs_f64(abm.interval) {
+				*last_run = now;
+				due.push(idx);
+			}
+		}
+		due
+	}
+}
+
+/// Whether the block at the given position satisfies the
+/// neighbour conditions of the modifier
+fn conditions_met<B :MapBackend>(map :&Map<B>, params :&ServerGameParams,
+		abm :&Abm, pos :Vector3<isize>) -> bool {
+	if abm.air_above && map.get_blk(pos + Vector3::z()) != Some(params.p.block_roles.air) {
+		return false;
+	}
+	if abm.neighbours.is_empty() {
+		return true;
+	}
+	for x in -1 ..= 1 {
+		for y in -1 ..= 1 {
+			for z in -1 ..= 1 {
+				if (x, y, z) == (0, 0, 0) {
+					continue;
+				}
+				let npos = pos + Vector3::new(x, y, z);
+				if let Some(nblk) = map.get_blk(npos) {
+					if abm.neighbours.contains(&nblk) {
+						return true;
+					}
+				}
+			}
+		}
+	}
+	false
+}
+
+/// The blocks that meet the conditions of each modifier, per chunk,
+/// so that chunks don't need to be scanned each time a modifier runs
+#[derive(Default)]
+pub struct AbmIndex {
+	/// The positions of the blocks for each modifier, by chunk position
+	chunks :HashMap<Vector3<isize>, Vec<Vec<Vector3<isize>>>>,
+}
+
+impl AbmIndex {
+	/// Drops the index of a changed chunk, and of its neighbours
+	/// whose blocks at the border have its blocks as neighbours
+	pub fn invalidate(&mut self, chunk_pos :Vector3<isize>) {
+		for x in -1 ..= 1 {
+			for y in -1 ..= 1 {
+				for z in -1 ..= 1 {
+					self.chunks.remove(&(chunk_pos + Vector3::new(x, y, z) * CHUNKSIZE));
+				}
+			}
+		}
+	}
+	/// Drops the index of all chunks but the given ones
+	pub fn retain(&mut self, chunks :&HashSet<Vector3<isize>>) {
+		self.chunks.retain(|chunk_pos, _| chunks.contains(chunk_pos));
+	}
+	/// Returns the positions of the blocks for each modifier
+	/// in the chunk, or None if it isn't loaded
+	fn get<B :MapBackend>(&mut self, map :&Map<B>, params :&ServerGameParams,
+			chunk_pos :Vector3<isize>) -> Option<&[Vec<Vector3<isize>>]> {
+		let entry = match self.chunks.entry(chunk_pos) {
+			Entry::Occupied(entry) => return Some(entry.into_mut().as_slice()),
+			Entry::Vacant(entry) => entry,
+		};
+		let chunk = map.get_chunk(chunk_pos)?;
+		let mut positions = vec![Vec::new(); params.abms.len()];
+		for x in 0 .. CHUNKSIZE {
+			for y in 0 .. CHUNKSIZE {
+				for z in 0 .. CHUNKSIZE {
+					let rel_pos = Vector3::new(x, y, z);
+					let blk = chunk.get_blk(rel_pos);
+					for (abm, positions) in params.abms.iter().zip(positions.iter_mut()) {
+						let pos = chunk_pos + rel_pos;
+						if abm.blocks.contains(blk) && conditions_met(map, params, abm, pos) {
+							positions.push(pos);
+						}
+					}
+				}
+			}
+		}
+		Some(entry.insert(positions).as_slice())
+	}
+}
+
+/// Finds the positions in the given chunks the action of
+/// the modifier with the given index should be run on.
+///
+/// Each matching block is picked with a chance of 1 / abm.chance.
+pub fn find_matches<B :MapBackend>(map :&Map<B>, params :&ServerGameParams,
+		index :&mut AbmIndex, abm_idx :usize, chunks :&[Vector3<isize>]) -> Vec<Vector3<isize>> {
+	let abm = &params.abms[abm_idx];
+	let mut rng = rand::thread_rng();
+	let mut matches = Vec::new();
+	for chunk_pos in chunks.iter() {
+		let positions = if let Some(positions) = index.get(map, params, *chunk_pos) {
+			&positions[abm_idx]
+		} else {
+			continue;
+		};
+		for pos in positions.iter() {
+			if rng.gen_range(0 .. abm.chance) != 0 {
+				continue;
+			}
+			matches.push(*pos);
+		}
+	}
+	matches
+}
@@ -0,0 +1,67 @@
+use nalgebra::Vector3;
+use std::collections::{HashSet, VecDeque};
+use std::time::{Instant, Duration};
+
+/// The offsets of the six direct neighbours of a block
+pub const NEIGHBOURS :[Vector3<isize>; 6] = [
+	Vector3::new(1, 0, 0),
+	Vector3::new(-1, 0, 0),
+	Vector3::new(0, 1, 0),
+	Vector3::new(0, -1, 0),
+	Vector3::new(0, 0, 1),
+	Vector3::new(0, 0, -1),
+];
+
+/// Queue of positions that need to be updated because
+/// they or one of their neighbours have changed.
+///
+/// Updates are done in steps, with a fixed interval between
+/// two steps and a limited number of updates per step.
+/// Positions queued during a step are only updated in later steps.
+pub struct UpdateQueue {
+	queue :VecDeque<Vector3<isize>>,
+	queued :HashSet<Vector3<isize>>,
+	interval :Duration,
+	max_per_step :usize,
+	last_step_time :Option<Instant>,
+}
+
+impl UpdateQueue {
+	pub fn new(interval :Duration, max_per_step :usize) -> Self {
+		Self {
+			queue : VecDeque::new(),
+			queued : HashSet::new(),
+			interval,
+			max_per_step,
+			last_step_time : None,
+		}
+	}
+	pub fn push(&mut self, pos :Vector3<isize>) {
+		if self.queued.insert(pos) {
+			self.queue.push_back(pos);
+		}
+	}
+	/// Queues the position and its six neighbours
+	pub fn push_with_neighbours(&mut self, pos :Vector3<isize>) {
+		self.push(pos);
+		for offs in NEIGHBOURS.iter() {
+			self.push(pos + offs);
+		}
+	}
+	/// Returns the positions to update if a step is due
+	pub fn take_due(&mut self, now :Instant) -> Vec<Vector3<isize>> {
+		let step_due = self.last_step_time
+			.map(|last| now - last >= self.interval)
+			.unwrap_or(true);
+		if self.queue.is_empty() || !step_due {
+			return Vec::new();
+		}
+		self.last_step_time = Some(now);
+		let count = self.queue.len().min(self.max_per_step);
+		let positions = self.queue.drain(.. count).collect::<Vec<_>>();
+		for pos in positions.iter() {
+			self.queued.remove(pos);
+		}
+		positions
+	}
+}
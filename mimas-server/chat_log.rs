@@ -0,0 +1,84 @@
+//! Persistent log of public chat messages and moderation events.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+pub struct ChatLog {
+	file :Option<File>,
+}
+
+impl ChatLog {
+	/// Opens the log at the path for appending.
+	/// Without a path, nothing gets logged.
+	pub fn open(path :Option<&str>) -> Self {
+		let file = path.and_then(|path| {
+			OpenOptions::new()
+				.create(true)
+				.append(true)
+				.open(path)
+				.map_err(|e| println!("Couldn't open chat log {}: {:?}", path, e))
+				.ok()
+		});
+		Self {
+			file,
+		}
+	}
+	/// Appends the line with the given unix time (in seconds)
+	pub fn log(&mut self, unix_secs :u64, line :&str) {
+		let file = if let Some(file) = &mut self.file {
+			file
+		} else {
+			return;
+		};
+		if let Err(e) = writeln!(file, "[{}] {}", format_utc(unix_secs), line) {
+			println!("Couldn't write to the chat log, disabling it: {:?}", e);
+			self.file = None;
+		}
+	}
+}
+
+/// Formats the unix time as `YYYY-MM-DD HH:MM:SS` in UTC
+fn format_utc(secs :u64) -> String {
+	let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+	// Convert the days since 1970-01-01 to a date,
+	// with years starting on the first of March
+	let z = days + 719_468;
+	let era = z.div_euclid(146_097);
+	let day_of_era = z.rem_euclid(146_097);
+	let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+		- day_of_era / 146_096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let month_idx = (5 * day_of_year + 2) / 153;
+	let day = day_of_year - (153 * month_idx + 2) / 5 + 1;
+	let month = if month_idx < 10 { month_idx + 3 } else { month_idx - 9 };
+	let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+	format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day,
+		secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60)
+}
+
+#[cfg(test)]
+#[test]
+fn test_format_utc() {
+	assert_eq!(format_utc(0), "1970-01-01 00:00:00");
+	// Leap days
+	assert_eq!(format_utc(951_782_400), "2000-02-29 00:00:00");
+	assert_eq!(format_utc(951_868_799), "2000-02-29 23:59:59");
+	assert_eq!(format_utc(951_868_800), "2000-03-01 00:00:00");
+	assert_eq!(format_utc(1_709_210_096), "2024-02-29 12:34:56");
+	// 2100 is no leap year
+	assert_eq!(format_utc(4_107_542_400), "2100-03-01 00:00:00");
+}
+
+#[cfg(test)]
+#[test]
+fn test_chat_log() {
+	let path = std::env::temp_dir().join(format!("mimas-chat-log-{}.txt", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	let mut log = ChatLog::open(path.to_str());
+	log.log(0, "<alice> hi");
+	log.log(951_868_799, "bob has been muted");
+	let content = std::fs::read_to_string(&path).unwrap();
+	std::fs::remove_file(&path).unwrap();
+	assert_eq!(content, "[1970-01-01 00:00:00] <alice> hi\n\
+		[2000-02-29 23:59:59] bob has been muted\n");
+}
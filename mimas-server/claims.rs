@@ -0,0 +1,267 @@
+use anyhow::{bail, Result};
+use mimas_common::map::CHUNKSIZE;
+use mimas_common::map_storage::PlayerIdPair;
+use mimas_common::btchn;
+use nalgebra::Vector3;
+use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
+use std::collections::HashMap;
+use crate::map_storage::{serialize_player_id, deserialize_player_id};
+
+/// The largest number of blocks a claim may contain,
+/// unless it is created by a player with the protection privilege
+pub const MAX_CLAIM_VOLUME :isize = 64 * 64 * 128;
+/// The largest horizontal extent of any claim,
+/// including the ones of players with the protection privilege
+pub const MAX_CLAIM_EXTENT_XY :isize = 1024;
+/// The largest vertical extent of any claim
+pub const MAX_CLAIM_EXTENT_Z :isize = 512;
+
+/// Checks whether a claim of the cuboid may be created,
+/// and returns the reason if not
+pub fn check_claim_size(min :Vector3<isize>, max :Vector3<isize>,
+		is_admin :bool) -> Result<(), String> {
+	let max_extent = Vector3::new(MAX_CLAIM_EXTENT_XY, MAX_CLAIM_EXTENT_XY, MAX_CLAIM_EXTENT_Z);
+	let size = (0 .. 3)
+		.map(|i| max[i].checked_sub(min[i]).and_then(|v| v.checked_add(1)))
+		.collect::<Option<Vec<_>>>()
+		.filter(|size| (0 .. 3).all(|i| size[i] <= max_extent[i]));
+	let size = if let Some(size) = size {
+		size
+	} else {
+		return Err(format!("Claims may be at most {}x{}x{} blocks large",
+			max_extent.x, max_extent.y, max_extent.z));
+	};
+	let volume = size[0].checked_mul(size[1])
+		.and_then(|v| v.checked_mul(size[2]));
+	match volume {
+		Some(volume) if volume <= MAX_CLAIM_VOLUME || is_admin => Ok(()),
+		_ => Err(format!("Claims may contain at most {} blocks", MAX_CLAIM_VOLUME)),
+	}
+}
+
+/// Permissions that the owner of a claim can
+/// give to everyone, not only to the members
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ClaimFlag {
+	/// Anyone may dig and place blocks
+	Build,
+	/// Anyone may take from and put into chests
+	Chests,
+}
+
+impl ClaimFlag {
+	pub const ALL :&'static [ClaimFlag] = &[ClaimFlag::Build, ClaimFlag::Chests];
+	/// The names of the flags, in the same order as `ALL`
+	pub const NAMES :&'static [&'static str] = &["build", "chests"];
+	pub fn name(self) -> &'static str {
+		let idx = ClaimFlag::ALL.iter().position(|f| *f == self).unwrap();
+		ClaimFlag::NAMES[idx]
+	}
+	pub fn from_name(name :&str) -> Option<Self> {
+		ClaimFlag::ALL.iter()
+			.find(|f| f.name() == name)
+			.copied()
+	}
+	fn bit(self) -> u8 {
+		match self {
+			ClaimFlag::Build => 1,
+			ClaimFlag::Chests => 2,
+		}
+	}
+}
+
+/// A cuboid region of the map claimed by a player
+#[derive(Clone)]
+pub struct Claim {
+	pub id :u32,
+	pub owner :PlayerIdPair,
+	pub members :Vec<PlayerIdPair>,
+	pub min :Vector3<isize>,
+	pub max :Vector3<isize>,
+	flags :u8,
+}
+
+impl Claim {
+	pub fn contains(&self, p :Vector3<isize>) -> bool {
+		(0 .. 3).all(|i| self.min[i] <= p[i] && p[i] <= self.max[i])
+	}
+	pub fn intersects(&self, min :Vector3<isize>, max :Vector3<isize>) -> bool {
+		(0 .. 3).all(|i| self.min[i] <= max[i] && min[i] <= self.max[i])
+	}
+	/// Whether the player is the owner or a member of the claim
+	pub fn is_member(&self, id :PlayerIdPair) -> bool {
+		self.owner == id || self.members.contains(&id)
+	}
+	pub fn has_flag(&self, flag :ClaimFlag) -> bool {
+		self.flags & flag.bit() != 0
+	}
+	pub fn set_flag(&mut self, flag :ClaimFlag, enabled :bool) {
+		if enabled {
+			self.flags |= flag.bit();
+		} else {
+			self.flags &= !flag.bit();
+		}
+	}
+	/// Whether the claim allows the player the action of the flag
+	pub fn allows(&self, id :PlayerIdPair, flag :ClaimFlag) -> bool {
+		self.is_member(id) || self.has_flag(flag)
+	}
+}
+
+/// All claims, with a spatial index to look them up by position
+#[derive(Default)]
+pub struct Claims {
+	claims :Vec<Claim>,
+	next_id :u32,
+	/// Indices of the claims intersecting each chunk
+	index :HashMap<Vector3<isize>, Vec<usize>>,
+}
+
+/// Returns the positions of the chunks intersecting the cuboid
+fn chunks_of(min :Vector3<isize>, max :Vector3<isize>) -> impl Iterator<Item = Vector3<isize>> {
+	let (cmin, cmax) = (btchn(min), btchn(max));
+	let step = CHUNKSIZE as usize;
+	(cmin.x ..= cmax.x).step_by(step).flat_map(move |x| {
+		(cmin.y ..= cmax.y).step_by(step).flat_map(move |y| {
+			(cmin.z ..= cmax.z).step_by(step).map(move |z| Vector3::new(x, y, z))
+		})
+	})
+}
+
+impl Claims {
+	fn rebuild_index(&mut self) {
+		self.index.clear();
+		for (idx, claim) in self.claims.iter().enumerate() {
+			for chunk_pos in chunks_of(claim.min, claim.max) {
+				self.index.entry(chunk_pos).or_default().push(idx);
+			}
+		}
+	}
+	pub fn iter(&self) -> impl Iterator<Item = &Claim> {
+		self.claims.iter()
+	}
+	/// Returns the claims containing the position
+	pub fn at(&self, p :Vector3<isize>) -> impl Iterator<Item = &Claim> {
+		self.index.get(&btchn(p))
+			.into_iter()
+			.flat_map(|idxs| idxs.iter())
+			.map(move |idx| &self.claims[*idx])
+			.filter(move |claim| claim.contains(p))
+	}
+	/// Returns the claims intersecting the cuboid
+	pub fn intersecting(&self, min :Vector3<isize>, max :Vector3<isize>) -> Vec<&Claim> {
+		let mut idxs = chunks_of(min, max)
+			.filter_map(|chunk_pos| self.index.get(&chunk_pos))
+			.flat_map(|idxs| idxs.iter().copied())
+			.collect::<Vec<_>>();
+		idxs.sort_unstable();
+		idxs.dedup();
+		idxs.into_iter()
+			.map(|idx| &self.claims[idx])
+			.filter(|claim| claim.intersects(min, max))
+			.collect()
+	}
+	/// Whether all claims containing the position
+	/// allow the player the action of the flag
+	pub fn allows(&self, id :PlayerIdPair, p :Vector3<isize>, flag :ClaimFlag) -> bool {
+		self.at(p).all(|claim| claim.allows(id, flag))
+	}
+	/// Whether all claims intersecting the cuboid
+	/// allow the player the action of the flag
+	pub fn allows_area(&self, id :PlayerIdPair, min :Vector3<isize>,
+			max :Vector3<isize>, flag :ClaimFlag) -> bool {
+		self.intersecting(min, max).iter().all(|claim| claim.allows(id, flag))
+	}
+	/// Whether liquids or falling blocks may move from one position
+	/// to the other, which they may unless they'd enter a claim
+	/// that they aren't inside of already
+	pub fn may_spread(&self, from :Vector3<isize>, to :Vector3<isize>) -> bool {
+		self.at(to).all(|claim| claim.contains(from))
+	}
+	/// Adds a claim and returns its id
+	pub fn add(&mut self, owner :PlayerIdPair, min :Vector3<isize>, max :Vector3<isize>) -> u32 {
+		let id = self.next_id;
+		self.next_id += 1;
+		self.claims.push(Claim {
+			id,
+			owner,
+			members : Vec::new(),
+			min,
+			max,
+			flags : 0,
+		});
+		self.rebuild_index();
+		id
+	}
+	pub fn get(&self, id :u32) -> Option<&Claim> {
+		self.claims.iter().find(|claim| claim.id == id)
+	}
+	/// Returns the claim to change its members or flags
+	pub fn get_mut(&mut self, id :u32) -> Option<&mut Claim> {
+		self.claims.iter_mut().find(|claim| claim.id == id)
+	}
+	pub fn remove(&mut self, id :u32) -> bool {
+		let len = self.claims.len();
+		self.claims.retain(|claim| claim.id != id);
+		self.rebuild_index();
+		self.claims.len() != len
+	}
+	pub fn serialize(&self) -> Vec<u8> {
+		let mut buf = Vec::new();
+		// Version
+		buf.write_u8(0).unwrap();
+		buf.write_u32::<BigEndian>(self.next_id).unwrap();
+		buf.write_u32::<BigEndian>(self.claims.len() as u32).unwrap();
+		for claim in self.claims.iter() {
+			buf.write_u32::<BigEndian>(claim.id).unwrap();
+			serialize_player_id(&mut buf, claim.owner);
+			buf.write_u16::<BigEndian>(claim.members.len() as u16).unwrap();
+			for member in claim.members.iter() {
+				serialize_player_id(&mut buf, *member);
+			}
+			for v in claim.min.iter().chain(claim.max.iter()) {
+				buf.write_i64::<BigEndian>(*v as i64).unwrap();
+			}
+			buf.write_u8(claim.flags).unwrap();
+		}
+		buf
+	}
+	pub fn deserialize(buf :&[u8]) -> Result<Self> {
+		let mut rdr = buf;
+		let version = rdr.read_u8()?;
+		if version != 0 {
+			bail!("Unsupported claims version {}", version);
+		}
+		let next_id = rdr.read_u32::<BigEndian>()?;
+		let count = rdr.read_u32::<BigEndian>()?;
+		let mut claims = Vec::new();
+		for _ in 0 .. count {
+			let id = rdr.read_u32::<BigEndian>()?;
+			let owner = deserialize_player_id(&mut rdr)?;
+			let members_count = rdr.read_u16::<BigEndian>()?;
+			let members = (0 .. members_count)
+				.map(|_| deserialize_player_id(&mut rdr))
+				.collect::<Result<Vec<_>>>()?;
+			let mut coords = [0; 6];
+			for v in coords.iter_mut() {
+				*v = rdr.read_i64::<BigEndian>()? as isize;
+			}
+			let flags = rdr.read_u8()?;
+			claims.push(Claim {
+				id,
+				owner,
+				members,
+				min : Vector3::new(coords[0], coords[1], coords[2]),
+				max : Vector3::new(coords[3], coords[4], coords[5]),
+				flags,
+			});
+		}
+		let mut claims = Claims {
+			claims,
+			next_id,
+			index : HashMap::new(),
+		};
+		claims.rebuild_index();
+		Ok(claims)
+	}
+}
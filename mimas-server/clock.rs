@@ -0,0 +1,138 @@
+//! The source of time for the server, and the scheduling
+//! of its ticks at a fixed rate.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The source of time for all time based logic of the server
+pub trait Clock {
+	fn now(&self) -> Instant;
+	/// The unix time in seconds, for times that are stored
+	fn unix_secs(&self) -> u64;
+}
+
+fn system_unix_secs() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0)
+}
+
+/// The clock of the system, which is what servers normally use
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+	fn now(&self) -> Instant {
+		Instant::now()
+	}
+	fn unix_secs(&self) -> u64 {
+		system_unix_secs()
+	}
+}
+
+/// A clock that only advances when told to, for tests.
+///
+/// Clones share the time, so a test can keep a clone
+/// of the clock it has given to the server.
+#[derive(Clone)]
+pub struct ManualClock {
+	now :Arc<Mutex<Instant>>,
+	/// The time and unix time the clock started at
+	start :(Instant, u64),
+}
+
+impl ManualClock {
+	pub fn new() -> Self {
+		let now = Instant::now();
+		Self {
+			now : Arc::new(Mutex::new(now)),
+			start : (now, system_unix_secs()),
+		}
+	}
+	pub fn advance(&self, duration :Duration) {
+		*self.now.lock().unwrap() += duration;
+	}
+}
+
+impl Default for ManualClock {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Clock for ManualClock {
+	fn now(&self) -> Instant {
+		*self.now.lock().unwrap()
+	}
+	fn unix_secs(&self) -> u64 {
+		let (start, start_unix_secs) = self.start;
+		start_unix_secs + self.now().duration_since(start).as_secs()
+	}
+}
+
+/// Decides when the next tick is due, for ticks at a fixed rate
+pub struct TickScheduler {
+	interval :Duration,
+	next_tick :Instant,
+}
+
+/// The maximum number of ticks the scheduler catches up on after
+/// the server has fallen behind. Further ticks are skipped.
+const MAX_CATCH_UP_TICKS :u32 = 5;
+
+impl TickScheduler {
+	pub fn new(tps :u32, now :Instant) -> Self {
+		Self {
+			interval : Duration::from_secs(1) / tps.max(1),
+			next_tick : now,
+		}
+	}
+	/// The fixed time between two ticks
+	pub fn interval(&self) -> Duration {
+		self.interval
+	}
+	/// Returns whether a tick is due, and if so,
+	/// schedules the one after it
+	pub fn take_due(&mut self, now :Instant) -> bool {
+		if now < self.next_tick {
+			return false;
+		}
+		self.next_tick += self.interval;
+		let max_lag = self.interval * MAX_CATCH_UP_TICKS;
+		if now.saturating_duration_since(self.next_tick) > max_lag {
+			self.next_tick = now + self.interval;
+		}
+		true
+	}
+	/// Returns how long to wait until the next tick is due
+	pub fn time_until_due(&self, now :Instant) -> Duration {
+		self.next_tick.saturating_duration_since(now)
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_tick_scheduler() {
+	let clock = ManualClock::new();
+	let mut scheduler = TickScheduler::new(20, clock.now());
+	assert!(scheduler.take_due(clock.now()));
+	assert!(!scheduler.take_due(clock.now()));
+	assert_eq!(scheduler.time_until_due(clock.now()), Duration::from_millis(50));
+
+	clock.advance(Duration::from_millis(30));
+	assert!(!scheduler.take_due(clock.now()));
+	assert_eq!(scheduler.time_until_due(clock.now()), Duration::from_millis(20));
+	clock.advance(Duration::from_millis(20));
+	assert!(scheduler.take_due(clock.now()));
+	assert!(!scheduler.take_due(clock.now()));
+
+	// Missed ticks are caught up on
+	clock.advance(Duration::from_millis(120));
+	let ticks = (0 .. 10).filter(|_| scheduler.take_due(clock.now())).count();
+	assert_eq!(ticks, 2);
+
+	// Unless the server is too far behind
+	clock.advance(Duration::from_secs(10));
+	let ticks = (0 .. 10).filter(|_| scheduler.take_due(clock.now())).count();
+	assert_eq!(ticks, 1);
+	assert_eq!(scheduler.time_until_due(clock.now()), Duration::from_millis(50));
+}
@@ -0,0 +1,520 @@
+//! Chat commands: their registry, argument parsing and completion.
+
+use mimas_common::map_storage::PlayerIdPair;
+use std::time::Duration;
+
+/// The kind of value an argument accepts
+#[derive(Clone, Copy)]
+pub enum ArgKind {
+	/// Any single word
+	Word,
+	/// A non-negative integer up to the given maximum,
+	/// usually that of the type the handler parses it as
+	Number(u32),
+	/// A finite number like `-12.5`, for coordinates
+	Coordinate,
+	/// A duration like `30s`, `10m`, `2h` or `1d`
+	Duration,
+	/// One of a fixed list of words
+	Choice(&'static [&'static str]),
+	/// The nick of a player
+	Player,
+	/// The name of an item
+	Item,
+	/// The name of a privilege
+	Privilege,
+	/// The rest of the line as typed, without splitting it
+	/// into words. Only allowed as the last argument.
+	Text,
+}
+
+impl ArgKind {
+	/// Whether the parameter is a valid value of the kind
+	fn accepts(self, param :&str) -> bool {
+		match self {
+			ArgKind::Number(max) => matches!(param.parse::<u32>(), Ok(v) if v <= max),
+			ArgKind::Coordinate => matches!(param.parse::<f32>(), Ok(v) if v.is_finite()),
+			ArgKind::Duration => parse_duration(param).is_some(),
+			ArgKind::Choice(choices) => choices.contains(&param),
+			_ => true,
+		}
+	}
+}
+
+#[derive(Clone)]
+pub struct Arg {
+	pub name :&'static str,
+	pub kind :ArgKind,
+	pub optional :bool,
+}
+
+/// A required argument
+pub fn arg(name :&'static str, kind :ArgKind) -> Arg {
+	Arg {
+		name,
+		kind,
+		optional : false,
+	}
+}
+
+/// An optional argument. Only allowed after the required ones.
+pub fn opt(name :&'static str, kind :ArgKind) -> Arg {
+	Arg {
+		name,
+		kind,
+		optional : true,
+	}
+}
+
+/// Runs a command with its already validated parameters
+pub type Handler<T> = fn(&mut T, PlayerIdPair, &[&str]);
+
+/// A form of a command.
+///
+/// A command can have several forms with the same name, e.g. one per
+/// subcommand. Each form has its own arguments and handler, and the
+/// first form whose arguments the parameters fit gets run.
+pub struct Command<T> {
+	pub name :&'static str,
+	pub description :&'static str,
+	pub args :Vec<Arg>,
+	/// The privilege needed to run the command at all
+	pub privilege :Option<&'static str>,
+	/// Whether only players, not the console, can run the command
+	pub needs_player :bool,
+	pub handler :Handler<T>,
+}
+
+impl<T> Command<T> {
+	pub fn usage(&self) -> String {
+		let mut usage = format!("/{}", self.name);
+		for arg in self.args.iter() {
+			let name = match arg.kind {
+				// Subcommands
+				ArgKind::Choice([choice]) if !arg.optional => {
+					usage += &format!(" {}", choice);
+					continue;
+				},
+				ArgKind::Choice(choices) => choices.join("|"),
+				ArgKind::Text => format!("{}...", arg.name),
+				_ => arg.name.to_owned(),
+			};
+			if arg.optional {
+				usage += &format!(" [{}]", name);
+			} else {
+				usage += &format!(" <{}>", name);
+			}
+		}
+		usage
+	}
+	/// Checks the parameters against the arguments of the command
+	pub fn check_params(&self, params :&[&str]) -> Result<(), String> {
+		if !self.takes_count(params.len()) {
+			return Err(format!("Usage: {}", self.usage()));
+		}
+		for (param, arg) in params.iter().zip(self.args.iter()) {
+			if !arg.kind.accepts(param) {
+				return Err(format!("Invalid {} \"{}\". Usage: {}",
+					arg.name, param, self.usage()));
+			}
+		}
+		Ok(())
+	}
+	/// Splits the parameters of the command off the line
+	pub fn split_params(&self, line :&str) -> Result<Vec<String>, String> {
+		if !matches!(self.args.last(), Some(Arg { kind : ArgKind::Text, .. })) {
+			return split_line(line);
+		}
+		let (mut params, text) = split_first_words(line, self.args.len() - 1)?;
+		if !text.is_empty() {
+			params.push(text.to_owned());
+		}
+		Ok(params)
+	}
+	/// Whether the command takes the number of parameters
+	fn takes_count(&self, count :usize) -> bool {
+		let required = self.args.iter()
+			.filter(|arg| !arg.optional)
+			.count();
+		count >= required && count <= self.args.len()
+	}
+	/// Whether the parameters fit the first arguments of the command,
+	/// for lines that are still being typed
+	fn accepts_start(&self, params :&[String]) -> bool {
+		params.iter()
+			.enumerate()
+			.all(|(i, param)| matches!(self.arg_for(i), Some(arg) if arg.kind.accepts(param)))
+	}
+	/// Returns the argument the parameter with the index is for.
+	/// All words after the start of a text belong to the text.
+	fn arg_for(&self, idx :usize) -> Option<&Arg> {
+		self.args.get(idx).or_else(|| {
+			self.args.last().filter(|arg| matches!(arg.kind, ArgKind::Text))
+		})
+	}
+}
+
+/// A form of a command together with the parameters split off the line
+pub type ParsedCommand<'a, T> = (&'a Command<T>, Vec<String>);
+
+pub struct CommandRegistry<T> {
+	commands :Vec<Command<T>>,
+}
+
+impl<T> CommandRegistry<T> {
+	pub fn new() -> Self {
+		Self {
+			commands : Vec::new(),
+		}
+	}
+	pub fn register(&mut self, command :Command<T>) {
+		assert!(self.forms(command.name).all(|cmd| cmd.usage() != command.usage()),
+			"Command {} registered twice", command.usage());
+		self.commands.push(command);
+	}
+	/// Returns the forms of the command, in the order they were registered
+	pub fn forms<'a>(&'a self, name :&'a str) -> impl Iterator<Item = &'a Command<T>> {
+		self.commands.iter().filter(move |cmd| cmd.name == name)
+	}
+	/// Finds the form of the command that the rest of the line fits,
+	/// and returns it with the parameters split off the line.
+	///
+	/// Returns None if there is no command with the name.
+	pub fn parse(&self, name :&str, line :&str)
+			-> Option<Result<ParsedCommand<'_, T>, String>> {
+		let mut errors = Vec::new();
+		for cmd in self.commands.iter().filter(|cmd| cmd.name == name) {
+			let checked = cmd.split_params(line).and_then(|params| {
+				let param_refs = params.iter().map(|p| p.as_str()).collect::<Vec<_>>();
+				cmd.check_params(&param_refs)?;
+				Ok(params)
+			});
+			match checked {
+				Ok(params) => return Some(Ok((cmd, params))),
+				Err(e) => errors.push((cmd, e)),
+			}
+		}
+		if errors.len() <= 1 {
+			return errors.pop().map(|(_cmd, e)| Err(e));
+		}
+		// Report the error of the only form that takes the number
+		// of parameters and whose first argument fits
+		let words = split_line(line).unwrap_or_default();
+		let mut fitting = errors.iter()
+			.filter(|(cmd, _e)| cmd.takes_count(words.len()) &&
+				cmd.accepts_start(&words[.. words.len().min(1)]));
+		if let (Some((_cmd, e)), None) = (fitting.next(), fitting.next()) {
+			return Some(Err(e.clone()));
+		}
+		let usages = errors.iter()
+			.map(|(cmd, _e)| cmd.usage())
+			.collect::<Vec<_>>();
+		Some(Err(format!("Usage: {}", usages.join(" or "))))
+	}
+	pub fn iter(&self) -> impl Iterator<Item = &Command<T>> {
+		self.commands.iter()
+	}
+	/// Returns the possible completions of a partially typed command line,
+	/// each being the full line with the last word completed.
+	///
+	/// `may_run` tells which commands to offer, and `values` lists
+	/// the values of arguments of the player, item and privilege kinds.
+	pub fn complete(&self, line :&str,
+			may_run :impl Fn(&Command<T>) -> bool,
+			values :impl Fn(ArgKind) -> Vec<String>) -> Vec<String> {
+		let line = match line.strip_prefix('/') {
+			Some(line) if !line.starts_with(char::is_whitespace) => line,
+			_ => return Vec::new(),
+		};
+		let mut words = if let Ok(words) = split_line(line) {
+			words
+		} else {
+			return Vec::new();
+		};
+		if words.is_empty() || line.ends_with(char::is_whitespace) {
+			words.push(String::new());
+		}
+		let last = words.pop().unwrap();
+		let candidates = if words.is_empty() {
+			self.commands.iter()
+				.filter(|cmd| may_run(cmd))
+				.map(|cmd| cmd.name.to_owned())
+				.collect::<Vec<_>>()
+		} else {
+			// Offer the values of all forms that the words typed so far fit
+			self.forms(&words[0])
+				.filter(|cmd| may_run(cmd) && cmd.accepts_start(&words[1..]))
+				.filter_map(|cmd| cmd.arg_for(words.len() - 1))
+				.flat_map(|arg| match arg.kind {
+					ArgKind::Choice(choices) => choices.iter()
+						.map(|c| c.to_string())
+						.collect(),
+					kind @ ArgKind::Player |
+					kind @ ArgKind::Item |
+					kind @ ArgKind::Privilege => values(kind),
+					_ => Vec::new(),
+				})
+				.collect()
+		};
+		let start = words.iter()
+			.map(|w| quote(w) + " ")
+			.collect::<String>();
+		let mut completions = candidates.into_iter()
+			.filter(|c| c.starts_with(&last))
+			.map(|c| format!("/{}{}", start, quote(&c)))
+			.collect::<Vec<_>>();
+		completions.sort();
+		completions.dedup();
+		completions
+	}
+}
+
+/// Parses a duration like `30s`, `10m`, `2h` or `1d`.
+/// Without a unit, the number is in seconds.
+pub fn parse_duration(s :&str) -> Option<Duration> {
+	let (num, factor) = match s.chars().last()? {
+		's' => (&s[..s.len() - 1], 1),
+		'm' => (&s[..s.len() - 1], 60),
+		'h' => (&s[..s.len() - 1], 60 * 60),
+		'd' => (&s[..s.len() - 1], 24 * 60 * 60),
+		_ => (s, 1),
+	};
+	let secs = num.parse::<u64>().ok()?.checked_mul(factor)?;
+	Some(Duration::from_secs(secs))
+}
+
+/// Splits a command line into words.
+///
+/// Words are separated by whitespace, unless it is inside
+/// double quotes. A backslash escapes the following character.
+pub fn split_line(line :&str) -> Result<Vec<String>, String> {
+	split_first_words(line, usize::MAX).map(|(words, _rest)| words)
+}
+
+/// Splits up to `count` words off the line like split_line does,
+/// and returns them together with the rest of the line as typed
+pub fn split_first_words(line :&str, count :usize) -> Result<(Vec<String>, &str), String> {
+	let mut words = Vec::new();
+	let mut word = None::<String>;
+	let mut in_quotes = false;
+	let mut chars = line.char_indices();
+	if count == 0 {
+		return Ok((words, line.trim()));
+	}
+	while let Some((idx, ch)) = chars.next() {
+		match ch {
+			'\\' => {
+				let (_, escaped) = chars.next()
+					.ok_or_else(|| "Unfinished escape sequence".to_owned())?;
+				word.get_or_insert_with(String::new).push(escaped);
+			},
+			'"' => {
+				in_quotes = !in_quotes;
+				word.get_or_insert_with(String::new);
+			},
+			ch if ch.is_whitespace() && !in_quotes => {
+				words.extend(word.take());
+				if words.len() == count {
+					return Ok((words, line[idx..].trim()));
+				}
+			},
+			ch => {
+				word.get_or_insert_with(String::new).push(ch);
+			},
+		}
+	}
+	if in_quotes {
+		return Err("Unterminated quote".to_owned());
+	}
+	words.extend(word);
+	Ok((words, ""))
+}
+
+/// Quotes the word if needed so that split_line gives it back
+fn quote(word :&str) -> String {
+	let needs_quotes = word.is_empty() ||
+		word.contains(|c :char| c.is_whitespace() || c == '"' || c == '\\');
+	if !needs_quotes {
+		return word.to_owned();
+	}
+	let escaped = word.replace('\\', "\\\\").replace('"', "\\\"");
+	format!("\"{}\"", escaped)
+}
+
+#[cfg(test)]
+#[test]
+fn test_split_line() {
+	assert_eq!(split_line("tp  a b").unwrap(), vec!["tp", "a", "b"]);
+	assert_eq!(split_line("msg \"two words\" x").unwrap(), vec!["msg", "two words", "x"]);
+	assert_eq!(split_line("a\\ b \\\"c").unwrap(), vec!["a b", "\"c"]);
+	assert_eq!(split_line("say \"\"").unwrap(), vec!["say", ""]);
+	assert_eq!(split_line("ab\"c d\"e").unwrap(), vec!["abc de"]);
+	assert!(split_line("say \"hi").is_err());
+	assert!(split_line("say hi\\").is_err());
+	assert!(split_line("").unwrap().is_empty());
+
+	assert_eq!(split_first_words("msg bob  he said \"hi ", 2).unwrap(),
+		(vec!["msg".to_owned(), "bob".to_owned()], "he said \"hi"));
+	assert_eq!(split_first_words("\"al bert\" x\\y", 1).unwrap(),
+		(vec!["al bert".to_owned()], "x\\y"));
+	assert_eq!(split_first_words("shutdown", 1).unwrap(), (vec!["shutdown".to_owned()], ""));
+	assert_eq!(split_first_words(" a b ", 0).unwrap(), (Vec::new(), "a b"));
+
+	for word in ["plain", "", "two words", "a \"quote\"", "back\\slash"].iter() {
+		let line = format!("cmd {}", quote(word));
+		assert_eq!(split_line(&line).unwrap(), vec!["cmd", word]);
+	}
+}
+
+#[cfg(test)]
+fn test_registry() -> CommandRegistry<()> {
+	fn handler(_ :&mut (), _ :PlayerIdPair, _ :&[&str]) {}
+	let mut registry = CommandRegistry::new();
+	registry.register(Command {
+		name : "grant",
+		description : "",
+		args : vec![arg("player", ArgKind::Player), arg("privilege", ArgKind::Privilege)],
+		privilege : None,
+		needs_player : false,
+		handler,
+	});
+	registry.register(Command {
+		name : "mute",
+		description : "",
+		args : vec![arg("player", ArgKind::Player), opt("duration", ArgKind::Duration)],
+		privilege : None,
+		needs_player : false,
+		handler,
+	});
+	for (subcommand, args) in [(&["create"], vec![]), (&["list"], vec![]),
+			(&["remove"], vec![arg("id", ArgKind::Number(u32::MAX))])].iter() {
+		registry.register(Command {
+			name : "claim",
+			description : "",
+			args : std::iter::once(arg("action", ArgKind::Choice(*subcommand)))
+				.chain(args.iter().cloned())
+				.collect(),
+			privilege : None,
+			needs_player : false,
+			handler,
+		});
+	}
+	registry.register(Command {
+		name : "tp",
+		description : "",
+		args : vec![arg("x", ArgKind::Coordinate), arg("y", ArgKind::Coordinate),
+			arg("z", ArgKind::Coordinate)],
+		privilege : None,
+		needs_player : false,
+		handler,
+	});
+	registry.register(Command {
+		name : "tp",
+		description : "",
+		args : vec![arg("player", ArgKind::Player)],
+		privilege : None,
+		needs_player : false,
+		handler,
+	});
+	registry.register(Command {
+		name : "gime",
+		description : "",
+		args : vec![arg("item", ArgKind::Item), opt("count", ArgKind::Number(u16::MAX as u32))],
+		privilege : None,
+		needs_player : false,
+		handler,
+	});
+	registry.register(Command {
+		name : "msg",
+		description : "",
+		args : vec![arg("player", ArgKind::Player), arg("text", ArgKind::Text)],
+		privilege : None,
+		needs_player : false,
+		handler,
+	});
+	registry
+}
+
+#[cfg(test)]
+#[test]
+fn test_check_params() {
+	let registry = test_registry();
+	let mute = registry.forms("mute").next().unwrap();
+	assert_eq!(mute.usage(), "/mute <player> [duration]");
+	assert!(mute.check_params(&["alice"]).is_ok());
+	assert!(mute.check_params(&["alice", "10m"]).is_ok());
+	assert!(mute.check_params(&["alice", "10x"]).is_err());
+	assert!(mute.check_params(&[]).is_err());
+	assert!(mute.check_params(&["alice", "10m", "extra"]).is_err());
+
+	let gime = registry.forms("gime").next().unwrap();
+	assert!(gime.check_params(&["default:stone", "65535"]).is_ok());
+	assert!(gime.check_params(&["default:stone", "70000"]).is_err());
+	assert!(gime.check_params(&["default:stone", "-1"]).is_err());
+
+	// The form that the parameters fit gets picked
+	let parse = |name, line| registry.parse(name, line)
+		.map(|res| res.map(|(cmd, params)| (cmd.usage(), params)));
+	assert_eq!(parse("claim", "remove 3"), Some(Ok(("/claim remove <id>".to_owned(), vec!["remove".to_owned(), "3".to_owned()]))));
+	assert_eq!(parse("claim", "list").unwrap().unwrap().0, "/claim list");
+	assert_eq!(parse("tp", "1 -2.5 3").unwrap().unwrap().0, "/tp <x> <y> <z>");
+	assert_eq!(parse("tp", "bob").unwrap().unwrap().0, "/tp <player>");
+	assert!(parse("unknown", "").is_none());
+	// Errors are those of the form that the first parameter fits
+	assert_eq!(parse("claim", "remove three"),
+		Some(Err("Invalid id \"three\". Usage: /claim remove <id>".to_owned())));
+	assert_eq!(parse("tp", "1 2 inf"),
+		Some(Err("Invalid z \"inf\". Usage: /tp <x> <y> <z>".to_owned())));
+	assert_eq!(parse("claim", "rename"),
+		Some(Err("Usage: /claim create or /claim list or /claim remove <id>".to_owned())));
+
+	let msg = registry.forms("msg").next().unwrap();
+	assert_eq!(msg.usage(), "/msg <player> <text...>");
+	assert!(msg.check_params(&["alice", "hello there"]).is_ok());
+	assert!(msg.check_params(&["alice"]).is_err());
+	// The text is kept as typed
+	assert_eq!(msg.split_params("\"al bert\" it's \"quoted\"  ").unwrap(),
+		vec!["al bert", "it's \"quoted\""]);
+	assert_eq!(msg.split_params("alice").unwrap(), vec!["alice"]);
+	assert_eq!(registry.forms("claim").next().unwrap().split_params("remove  3").unwrap(), vec!["remove", "3"]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_complete() {
+	let registry = test_registry();
+	let values = |kind| match kind {
+		ArgKind::Player => vec!["alice".to_owned(), "al bert".to_owned(), "bob".to_owned()],
+		ArgKind::Privilege => vec!["fly".to_owned(), "fast".to_owned()],
+		_ => Vec::new(),
+	};
+	let complete = |line| registry.complete(line, |_| true, values);
+
+	// Command names
+	assert_eq!(complete("/m"), vec!["/msg", "/mute"]);
+	assert_eq!(complete("/"), vec!["/claim", "/gime", "/grant", "/msg", "/mute", "/tp"]);
+	assert!(complete("m").is_empty());
+	assert!(complete("/ m").is_empty());
+	let only_claim = registry.complete("/", |cmd| cmd.name == "claim", values);
+	assert_eq!(only_claim, vec!["/claim"]);
+
+	// Names, quoted if needed
+	assert_eq!(complete("/mute al"), vec!["/mute \"al bert\"", "/mute alice"]);
+	assert_eq!(complete("/grant bob f"), vec!["/grant bob fast", "/grant bob fly"]);
+	// Free text isn't completed
+	assert!(complete("/msg bob hello a").is_empty());
+
+	// Choices
+	assert_eq!(complete("/claim r"), vec!["/claim remove"]);
+	assert!(complete("/claim remove 1").is_empty());
+	// Of all forms that the line fits
+	assert_eq!(complete("/tp b"), vec!["/tp bob"]);
+	assert!(complete("/tp 1 b").is_empty());
+
+	// After a trailing space, the next argument is completed
+	assert_eq!(complete("/claim "), vec!["/claim create", "/claim list", "/claim remove"]);
+	assert_eq!(complete("/grant bob "), vec!["/grant bob fast", "/grant bob fly"]);
+	assert!(complete("/mute bob ").is_empty());
+	assert!(complete("/unknown ").is_empty());
+	assert!(complete("/mute \"al").is_empty());
+}
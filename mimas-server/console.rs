@@ -0,0 +1,69 @@
+//! The server console: commands read line by line
+//! from an input like stdin, run with all privileges.
+
+use mimas_common::map_storage::PlayerIdPair;
+use std::io::BufRead;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+/// The id commands from the console are issued with.
+///
+/// Ids from source 0 are not handed out by the
+/// auth database, only singleplayer uses one.
+pub fn console_id() -> PlayerIdPair {
+	PlayerIdPair::from_components(0, 2)
+}
+
+/// Spawns a thread reading lines from the input.
+///
+/// Lines are prefixed with a `/` if they lack one.
+/// Once the input ends, the sender gets dropped.
+pub fn spawn_reader(input :impl BufRead + Send + 'static) -> Receiver<String> {
+	let (line_s, line_r) = channel();
+	thread::spawn(move || {
+		for line in input.lines() {
+			let line = match line {
+				Ok(line) => line,
+				Err(e) => {
+					println!("Couldn't read console input: {:?}", e);
+					break;
+				},
+			};
+			let line = line.trim();
+			if line.is_empty() {
+				continue;
+			}
+			let line = if line.starts_with('/') {
+				line.to_owned()
+			} else {
+				format!("/{}", line)
+			};
+			if line_s.send(line).is_err() {
+				break;
+			}
+		}
+	});
+	line_r
+}
+
+#[cfg(test)]
+#[test]
+fn test_console_shutdown() {
+	use crate::clock::SystemClock;
+	use crate::game_params::test_asset_dir;
+	use crate::server::Server;
+	use mimas_common::config::Config;
+	use mimas_common::generic_net::MpscServerSocket;
+	use std::io::Cursor;
+
+	let (server_socket, _client_conn) = MpscServerSocket::new();
+	let config = Config {
+		spawn_pos : Some([0.0, 0.0, 20.0]),
+		.. Config::default()
+	};
+	let mut server = Server::with_asset_dir(server_socket, true, config, SystemClock,
+		&test_asset_dir());
+	server.enable_console(Cursor::new("say hi\n\nshutdown\n"));
+	// Returns once the shutdown command has been run
+	server.run_loop();
+}
@@ -0,0 +1,64 @@
+use mimas_common::map::{Map, MapBackend, MapBlock};
+use mimas_common::game_params::ServerGameParams;
+use nalgebra::Vector3;
+use std::time::Duration;
+
+use crate::block_update::UpdateQueue;
+
+/// The time between two falling steps.
+/// In each step, falling blocks move down by one block.
+const STEP_INTERVAL :Duration = Duration::from_millis(50);
+
+/// Maximum number of positions updated in a single step.
+/// The remaining positions are updated in later steps.
+const MAX_UPDATES_PER_STEP :usize = 200;
+
+pub fn update_queue() -> UpdateQueue {
+	UpdateQueue::new(STEP_INTERVAL, MAX_UPDATES_PER_STEP)
+}
+
+/// Whether falling blocks can fall into the given block
+fn can_fall_into(params :&ServerGameParams, blk :MapBlock) -> bool {
+	blk == params.p.block_roles.air || params.p.get_block_params(blk)
+		.map(|bp| bp.buildable_to)
+		.unwrap_or(false)
+}
+
+/// Moves the block at the given position down by one
+/// if it falls and there is nothing below to carry it.
+/// Blocks only fall if `may_fall` returns true for their position
+/// and the one below.
+///
+/// Returns the changes to apply to the map.
+pub fn update<B :MapBackend>(map :&Map<B>, params :&ServerGameParams,
+		pos :Vector3<isize>,
+		may_fall :impl Fn(Vector3<isize>, Vector3<isize>) -> bool) -> Vec<(Vector3<isize>, MapBlock)> {
+	let blk = if let Some(blk) = map.get_blk(pos) {
+		blk
+	} else {
+		return Vec::new();
+	};
+	let falls = params.p.get_block_params(blk)
+		.map(|bp| bp.falls)
+		.unwrap_or(false);
+	if !falls {
+		return Vec::new();
+	}
+	let below = pos - Vector3::z();
+	if !may_fall(pos, below) {
+		return Vec::new();
+	}
+	// Blocks above unloaded chunks don't fall
+	let below_blk = if let Some(blk) = map.get_blk(below) {
+		blk
+	} else {
+		return Vec::new();
+	};
+	if !can_fall_into(params, below_blk) {
+		return Vec::new();
+	}
+	vec![
+		(pos, params.p.block_roles.air),
+		(below, blk),
+	]
+}
@@ -0,0 +1,63 @@
+use mimas_common::map::{FurnaceState, FURNACE_INPUT_SLOT,
+	FURNACE_FUEL_SLOT, FURNACE_OUTPUT_SLOT};
+use mimas_common::game_params::ServerGameParams;
+use mimas_common::inventory::{Stack, STACK_SIZE_LIMIT};
+use std::time::Duration;
+
+/// The time between two furnace steps
+pub const STEP_INTERVAL :Duration = Duration::from_secs(1);
+
+/// Returns the output of cooking the input item,
+/// if the output fits into the output slot
+fn cookable(params :&ServerGameParams, state :&FurnaceState) -> Option<(Stack, f32)> {
+	let (input, _count) = state.inv.stacks()[FURNACE_INPUT_SLOT].content()?;
+	let recipe = params.get_cooking_recipe(input)?;
+	let mut output_slot = state.inv.stacks()[FURNACE_OUTPUT_SLOT];
+	let leftover = output_slot.put(recipe.output, true, STACK_SIZE_LIMIT);
+	if !leftover.is_empty() {
+		return None;
+	}
+	Some((recipe.output, recipe.time))
+}
+
+/// Advances the furnace by the given number of seconds.
+///
+/// Returns whether the state has changed.
+pub fn step(params :&ServerGameParams, state :&mut FurnaceState, secs :f32) -> bool {
+	let before = state.clone();
+	let cookable = cookable(params, state);
+
+	// Only start burning new fuel if there is something to cook
+	if state.burn_time <= 0.0 && cookable.is_some() {
+		let fuel_slot = &mut state.inv.stacks_mut()[FURNACE_FUEL_SLOT];
+		let burn_time = fuel_slot.content()
+			.and_then(|(fuel, _count)| params.get_burn_time(fuel));
+		if let Some(burn_time) = burn_time {
+			fuel_slot.take_n(1);
+			state.burn_time = burn_time;
+			state.burn_time_total = burn_time;
+		}
+	}
+
+	if state.burn_time > 0.0 {
+		state.burn_time = (state.burn_time - secs).max(0.0);
+		if let Some((output, time)) = cookable {
+			state.cook_time += secs;
+			state.cook_time_total = time;
+			if state.cook_time >= time {
+				state.cook_time = 0.0;
+				state.inv.stacks_mut()[FURNACE_INPUT_SLOT].take_n(1);
+				state.inv.stacks_mut()[FURNACE_OUTPUT_SLOT]
+					.put(output, true, STACK_SIZE_LIMIT);
+			}
+		}
+	}
+	if cookable.is_none() {
+		state.cook_time = 0.0;
+		state.cook_time_total = 0.0;
+	}
+	if state.burn_time <= 0.0 {
+		state.burn_time_total = 0.0;
+	}
+	*state != before
+}
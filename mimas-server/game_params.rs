@@ -1,21 +1,149 @@
+use std::path::Path;
 use std::sync::Arc;
 
-use mimas_common::game_params::{NameIdMap, ServerGameParamsHdl, load_params_failible,
-	asset_dir_relative};
+use mimas_common::game_params::{NameIdMap, ServerGameParamsHdl, load_params_failible};
 
-pub fn load_server_game_params(nm :NameIdMap) -> ServerGameParamsHdl {
-	let asset_dir = asset_dir_relative().expect("Could not obtain asset directory");
-	Arc::new(load_params_failible(nm, DEFAULT_GAME_PARAMS_STR, &asset_dir)
+pub fn load_server_game_params(nm :NameIdMap, asset_dir :&Path) -> ServerGameParamsHdl {
+	Arc::new(load_params_failible(nm, DEFAULT_GAME_PARAMS_STR, asset_dir)
 		.expect("Couldn't load game params"))
 }
 
 static DEFAULT_GAME_PARAMS_STR :&str = include_str!("game-params.toml");
 
+/// The asset directory as seen from test binaries,
+/// which are one directory further down
+#[cfg(test)]
+pub(crate) fn test_asset_dir() -> std::path::PathBuf {
+	mimas_common::game_params::asset_dir_testing()
+		.expect("Could not obtain asset directory")
+}
+
+#[cfg(test)]
+pub(crate) fn test_game_params() -> mimas_common::game_params::ServerGameParams {
+	let nm = NameIdMap::builtin_name_list();
+	mimas_common::game_params::default_game_params(nm, DEFAULT_GAME_PARAMS_STR, &test_asset_dir()).unwrap()
+}
+
 #[cfg(test)]
 #[test]
 fn default_game_params_parse_test() {
-	use mimas_common::game_params::asset_dir_testing;
-	let asset_dir = asset_dir_testing().expect("Could not obtain asset directory");
-	let nm = NameIdMap::builtin_name_list();
-	mimas_common::game_params::default_game_params(nm, DEFAULT_GAME_PARAMS_STR, &asset_dir).unwrap();
+	test_game_params();
+}
+
+#[cfg(test)]
+#[test]
+fn test_crafting() {
+	use mimas_common::crafting::{craft, craft_into};
+	use mimas_common::inventory::{SelectableInventory, Stack};
+	let params = &test_game_params().p;
+	let item = |name :&str| params.search_block_name(name).unwrap();
+	let craft_inv = |items :&[(usize, &str, u16)]| {
+		let mut inv = SelectableInventory::crafting_inv();
+		for (idx, name, count) in items {
+			inv.stacks_mut()[*idx] = Stack::with(item(name), *count);
+		}
+		inv
+	};
+
+	// The 4 leaves of the sapling recipe form a square
+	let mut inv = craft_inv(&[(4, "default:leaves", 1), (5, "default:leaves", 1),
+		(7, "default:leaves", 1), (8, "default:leaves", 1)]);
+	assert_eq!(craft(&mut inv, params), Some(Stack::with(item("default:sapling"), 1)));
+	assert!(inv.is_empty());
+	let mut inv = craft_inv(&[(0, "default:leaves", 1), (1, "default:leaves", 1),
+		(2, "default:leaves", 1), (3, "default:leaves", 1)]);
+	assert_eq!(craft(&mut inv, params), None);
+
+	// Shaped recipes match at any offset
+	let mut inv = craft_inv(&[(8, "default:tree", 1)]);
+	assert_eq!(craft(&mut inv, params), Some(Stack::with(item("default:wood"), 4)));
+
+	// A non-square recipe of two wood on top of each other,
+	// which doesn't match when lying on its side
+	let sticks = Some(Stack::with(item("default:stick"), 4));
+	let mut inv = craft_inv(&[(0, "default:wood", 1), (3, "default:wood", 1)]);
+	assert_eq!(craft(&mut inv, params), sticks);
+	let mut inv = craft_inv(&[(5, "default:wood", 1), (8, "default:wood", 1)]);
+	assert_eq!(craft(&mut inv, params), sticks);
+	let mut inv = craft_inv(&[(4, "default:wood", 1), (5, "default:wood", 1)]);
+	assert_eq!(craft(&mut inv, params), None);
+	let mut inv = craft_inv(&[(2, "default:wood", 1), (8, "default:wood", 1)]);
+	assert_eq!(craft(&mut inv, params), None);
+
+	// A shaped 3x3 recipe with a hole, using groups
+	let mut stone = (0 .. 9)
+		.filter(|i| *i != 4)
+		.map(|i| (i, "default:cobble", 1))
+		.collect::<Vec<_>>();
+	let mut inv = craft_inv(&stone);
+	assert_eq!(craft(&mut inv, params), Some(Stack::with(item("default:furnace"), 1)));
+	stone.push((4, "default:cobble", 1));
+	let mut inv = craft_inv(&stone);
+	assert_eq!(craft(&mut inv, params), None);
+
+	// Shapeless recipes match in any arrangement
+	let mut inv = craft_inv(&[(7, "default:tin_ingot", 1), (2, "default:copper_ingot", 1)]);
+	assert_eq!(craft(&mut inv, params), Some(Stack::with(item("default:bronze_ingot"), 2)));
+	let mut inv = craft_inv(&[(7, "default:tin_ingot", 1), (2, "default:copper_ingot", 1),
+		(3, "default:tree", 1)]);
+	assert_eq!(craft(&mut inv, params), None);
+
+	// Crafting as often as possible, leaving the rest
+	let mut inv = craft_inv(&[(0, "default:tin_ingot", 3), (1, "default:copper_ingot", 5)]);
+	let mut target = SelectableInventory::new();
+	assert_eq!(craft_into(&mut inv, &mut target, params, true), Stack::Empty);
+	assert_eq!(target.stacks()[0], Stack::with(item("default:bronze_ingot"), 6));
+	assert_eq!(inv.stacks()[0], Stack::Empty);
+	assert_eq!(inv.stacks()[1], Stack::with(item("default:copper_ingot"), 2));
+
+	let mut inv = craft_inv(&[(0, "default:tin_ingot", 3), (1, "default:copper_ingot", 5)]);
+	let mut target = SelectableInventory::new();
+	craft_into(&mut inv, &mut target, params, false);
+	assert_eq!(target.stacks()[0], Stack::with(item("default:bronze_ingot"), 2));
+	assert_eq!(inv.stacks()[0], Stack::with(item("default:tin_ingot"), 2));
+}
+
+#[cfg(test)]
+#[test]
+fn test_crafting_shapeless_overlapping_groups() {
+	use mimas_common::crafting::{Recipe, RecipeInput, get_matching_recipe};
+	use mimas_common::game_params::Id;
+	use mimas_common::inventory::{SelectableInventory, Stack};
+	let mut params = test_game_params().p;
+	let wood = params.search_block_name("default:wood").unwrap();
+	let tree = params.search_block_name("default:tree").unwrap();
+	// Wood is in both groups, so the tree input may
+	// only take it if the wood input has another stack
+	params.block_params[wood.id() as usize].groups.push("tree".to_owned());
+	params.recipes = vec![Recipe {
+		inputs : vec![RecipeInput::Group("tree".to_owned(), 1),
+			RecipeInput::Group("wood".to_owned(), 1)],
+		width : 2,
+		shapeless : true,
+		output : Stack::with(wood, 1),
+	}];
+	let mut inv = SelectableInventory::crafting_inv();
+	inv.stacks_mut()[0] = Stack::with(wood, 1);
+	inv.stacks_mut()[1] = Stack::with(tree, 2);
+	assert!(get_matching_recipe(&inv, &params).is_some());
+	inv.stacks_mut()[1] = Stack::with(wood, 2);
+	assert!(get_matching_recipe(&inv, &params).is_some());
+	inv.stacks_mut()[1] = Stack::Empty;
+	assert!(get_matching_recipe(&inv, &params).is_none());
+}
+
+#[cfg(test)]
+#[test]
+fn test_resolve_recipe_input() {
+	use mimas_common::crafting::RecipeInput;
+	use mimas_common::game_params::resolve_recipe_input;
+	use std::collections::HashSet;
+	let params = test_game_params().p;
+	let groups = ["wood"].iter().copied().collect::<HashSet<_>>();
+	let resolve = |sp| resolve_recipe_input(&params.name_id_map, &groups, sp);
+	assert_eq!(resolve("group:wood 2").unwrap(), RecipeInput::Group("wood".to_owned(), 2));
+	assert_eq!(resolve("").unwrap(), RecipeInput::Empty);
+	assert!(resolve("group:wod").is_err());
+	assert!(resolve("default:wod").is_err());
+	assert!(resolve("group:wood 0").is_err());
 }
@@ -24,5 +24,17 @@ mod game_params;
 pub mod server;
 mod map_storage;
 mod mapgen;
+mod privs;
+mod block_update;
+mod liquid;
+mod falling;
+mod abm;
+mod furnace;
+mod claims;
+mod commands;
+mod chat_log;
+mod console;
+pub mod clock;
+mod movement;
 
 pub use server::Server;
@@ -0,0 +1,233 @@
+use mimas_common::map::{Map, MapBackend, MapBlock};
+use mimas_common::game_params::{ServerGameParams, Liquid};
+use nalgebra::Vector3;
+use std::time::Duration;
+
+use crate::block_update::UpdateQueue;
+
+/// The time between two liquid spreading steps
+const STEP_INTERVAL :Duration = Duration::from_millis(500);
+
+/// Maximum number of positions updated in a single step.
+/// The remaining positions are updated in later steps.
+const MAX_UPDATES_PER_STEP :usize = 500;
+
+const HORIZONTAL_NEIGHBOURS :[Vector3<isize>; 4] = [
+	Vector3::new(1, 0, 0),
+	Vector3::new(-1, 0, 0),
+	Vector3::new(0, 1, 0),
+	Vector3::new(0, -1, 0),
+];
+
+pub fn update_queue() -> UpdateQueue {
+	UpdateQueue::new(STEP_INTERVAL, MAX_UPDATES_PER_STEP)
+}
+
+/// Whether liquids may flow into the given block
+fn floodable(params :&ServerGameParams, blk :MapBlock) -> bool {
+	if blk == params.p.block_roles.air {
+		return true;
+	}
+	params.get_liquid(blk).is_none() && params.p.get_block_params(blk)
+		.map(|bp| bp.buildable_to)
+		.unwrap_or(false)
+}
+
+/// Computes the block that the liquid rules demand at the given position.
+/// Liquids only flow between positions for which `may_flow` returns true.
+///
+/// Returns None if the block should stay as it is.
+pub fn update<B :MapBackend>(map :&Map<B>, params :&ServerGameParams,
+		pos :Vector3<isize>,
+		may_flow :impl Fn(Vector3<isize>, Vector3<isize>) -> bool) -> Option<MapBlock> {
+	let blk = map.get_blk(pos)?;
+	let own_liquid = params.get_liquid(blk);
+	if let Some(liquid) = own_liquid {
+		if liquid.level(blk) == Some(liquid.source_level()) {
+			// Sources never change on their own
+			return None;
+		}
+	} else if !floodable(params, blk) {
+		return None;
+	}
+
+	// Liquid falling from above gets the highest flowing level
+	let above = map.get_blk(pos + Vector3::z())?;
+	let falling_liquid = params.get_liquid(above)
+		.filter(|_| may_flow(pos + Vector3::z(), pos));
+	let new_blk = if let Some(liquid) = falling_liquid {
+		liquid.flowing_with_level(liquid.source_level() - 1)
+	} else {
+		spread_horizontally(map, params, pos, may_flow)
+	};
+	let new_blk = new_blk.unwrap_or(params.p.block_roles.air);
+
+	if new_blk == blk || (own_liquid.is_none() && new_blk == params.p.block_roles.air) {
+		// Don't replace things like grass with air
+		return None;
+	}
+	Some(new_blk)
+}
+
+/// Determines the block resulting from liquids spreading
+/// horizontally into the given position
+fn spread_horizontally<B :MapBackend>(map :&Map<B>, params :&ServerGameParams,
+		pos :Vector3<isize>,
+		may_flow :impl Fn(Vector3<isize>, Vector3<isize>) -> bool) -> Option<MapBlock> {
+	let below = map.get_blk(pos - Vector3::z());
+	let mut best :Option<(&Liquid, u8)> = None;
+	let mut source_count = 0;
+	for offs in HORIZONTAL_NEIGHBOURS.iter() {
+		let npos = pos + offs;
+		if !may_flow(npos, pos) {
+			continue;
+		}
+		let nblk = if let Some(nblk) = map.get_blk(npos) {
+			nblk
+		} else {
+			continue;
+		};
+		let liquid = if let Some(liquid) = params.get_liquid(nblk) {
+			liquid
+		} else {
+			continue;
+		};
+		// We know that the level is Some because get_liquid found the liquid
+		let level = liquid.level(nblk).unwrap();
+		let is_source = level == liquid.source_level();
+		if is_source {
+			source_count += 1;
+		} else {
+			// Flowing liquid only spreads sideways once it can't fall any further
+			let below_neighbour = map.get_blk(npos - Vector3::z());
+			let can_fall = below_neighbour
+				.map(|b| floodable(params, b) || liquid.level(b).is_some())
+				.unwrap_or(false);
+			if can_fall {
+				continue;
+			}
+		}
+		if best.map(|(_, l)| level > l).unwrap_or(true) {
+			best = Some((liquid, level));
+		}
+	}
+	let (liquid, level) = best?;
+	// Renewable liquids create new sources between
+	// two sources, if the ground below can carry the source.
+	let carried = below
+		.map(|b| b == liquid.source || !(floodable(params, b) || liquid.level(b).is_some()))
+		.unwrap_or(false);
+	if liquid.renewable && source_count >= 2 && carried {
+		return Some(liquid.source);
+	}
+	liquid.flowing_with_level(level - 1)
+}
+
+#[cfg(test)]
+fn test_map(params :&ServerGameParams) -> mimas_common::map::ClientMap {
+	use mimas_common::map::MapChunkData;
+	let stone = params.p.search_block_name("default:stone").unwrap();
+	let mut map = mimas_common::map::ClientMap::new();
+	let mut chunk = MapChunkData::filled_with(params.p.block_roles.air);
+	for x in 0 .. 16 {
+		for y in 0 .. 16 {
+			*chunk.get_blk_mut(Vector3::new(x, y, 0)) = stone;
+		}
+	}
+	map.set_chunk(Vector3::new(0, 0, 0), chunk);
+	map
+}
+
+/// Applies liquid updates to the inside of the
+/// test map until nothing changes any more
+#[cfg(test)]
+fn settle<B :MapBackend>(map :&mut Map<B>, params :&ServerGameParams,
+		may_flow :impl Fn(Vector3<isize>, Vector3<isize>) -> bool) {
+	for _ in 0 .. 50 {
+		let changes = (1 .. 15)
+			.flat_map(|x| (1 .. 15).flat_map(move |y| (1 .. 8).map(move |z| Vector3::new(x, y, z))))
+			.filter_map(|p| update(map, params, p, &may_flow).map(|blk| (p, blk)))
+			.collect::<Vec<_>>();
+		if changes.is_empty() {
+			return;
+		}
+		for (p, blk) in changes {
+			map.get_blk_mut(p).unwrap().set(blk);
+		}
+	}
+	panic!("Liquid didn't settle");
+}
+
+#[cfg(test)]
+#[test]
+fn test_liquid_spread_and_decay() {
+	let params = crate::game_params::test_game_params();
+	let water = &params.liquids[0];
+	let level = |map :&mimas_common::map::ClientMap, x, y, z| {
+		water.level(map.get_blk(Vector3::new(x, y, z)).unwrap())
+	};
+	let mut map = test_map(&params);
+	map.get_blk_mut(Vector3::new(8, 8, 1)).unwrap().set(water.source);
+	settle(&mut map, &params, |_, _| true);
+	assert_eq!(level(&map, 8, 8, 1), Some(5));
+	assert_eq!(level(&map, 9, 8, 1), Some(4));
+	assert_eq!(level(&map, 9, 9, 1), Some(3));
+	assert_eq!(level(&map, 8, 4, 1), Some(1));
+	assert_eq!(level(&map, 8, 3, 1), None);
+	assert_eq!(level(&map, 8, 8, 2), None);
+
+	// Without the source, the flowing liquid decays
+	map.get_blk_mut(Vector3::new(8, 8, 1)).unwrap().set(params.p.block_roles.air);
+	settle(&mut map, &params, |_, _| true);
+	for x in 1 .. 15 {
+		assert_eq!(level(&map, x, 8, 1), None);
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_liquid_fall_and_renew() {
+	let params = crate::game_params::test_game_params();
+	let water = &params.liquids[0];
+	let stone = params.p.search_block_name("default:stone").unwrap();
+	let level = |map :&mimas_common::map::ClientMap, x, y, z| {
+		water.level(map.get_blk(Vector3::new(x, y, z)).unwrap())
+	};
+
+	// Liquid flowing off a pillar falls down with the highest flowing level
+	let mut map = test_map(&params);
+	for z in 1 ..= 3 {
+		map.get_blk_mut(Vector3::new(8, 8, z)).unwrap().set(stone);
+	}
+	map.get_blk_mut(Vector3::new(8, 8, 4)).unwrap().set(water.source);
+	settle(&mut map, &params, |_, _| true);
+	assert_eq!(level(&map, 9, 8, 4), Some(4));
+	assert_eq!(level(&map, 10, 8, 4), None);
+	assert_eq!(level(&map, 9, 8, 2), Some(4));
+	assert_eq!(level(&map, 9, 8, 1), Some(4));
+	assert_eq!(level(&map, 10, 8, 1), Some(3));
+
+	// Flowing liquid between two sources becomes a source
+	let mut map = test_map(&params);
+	map.get_blk_mut(Vector3::new(8, 8, 1)).unwrap().set(water.source);
+	map.get_blk_mut(Vector3::new(10, 8, 1)).unwrap().set(water.source);
+	settle(&mut map, &params, |_, _| true);
+	assert_eq!(level(&map, 9, 8, 1), Some(5));
+}
+
+#[cfg(test)]
+#[test]
+fn test_liquid_may_flow() {
+	let params = crate::game_params::test_game_params();
+	let water = &params.liquids[0];
+	let level = |map :&mimas_common::map::ClientMap, x, y, z| {
+		water.level(map.get_blk(Vector3::new(x, y, z)).unwrap())
+	};
+	let mut map = test_map(&params);
+	map.get_blk_mut(Vector3::new(8, 8, 1)).unwrap().set(water.source);
+	// Liquid may not enter x >= 10 from the outside
+	settle(&mut map, &params, |from :Vector3<isize>, to :Vector3<isize>| from.x >= 10 || to.x < 10);
+	assert_eq!(level(&map, 9, 8, 1), Some(4));
+	assert_eq!(level(&map, 10, 8, 1), None);
+	assert_eq!(level(&map, 10, 9, 1), None);
+}
@@ -10,6 +10,7 @@ use mimas_common::quic_net::QuicServerSocket;
 use mimas_common::config::load_config;
 
 use structopt::StructOpt;
+use std::io::{self, BufReader};
 
 /// Mimas server
 #[derive(StructOpt, Debug)]
@@ -32,6 +33,7 @@ fn main() -> Result<()> {
 	println!("Listening on {}", server_socket.listen_addr());
 	let config = load_config();
 	let mut server = Server::new(server_socket, false, config);
+	server.enable_console(BufReader::new(io::stdin()));
 	server.run_loop();
 
 	Ok(())
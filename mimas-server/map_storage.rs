@@ -1,20 +1,23 @@
 use anyhow::{anyhow, bail, Result};
 use rusqlite::{Connection, OptionalExtension};
 use rusqlite::types::{Value, ToSql};
-use mimas_common::map::{MapChunkData, MetadataEntry, CHUNKSIZE};
+use mimas_common::map::{MapChunkData, MetadataEntry, FurnaceState,
+	LockedChestState, ItemEntity, CHUNKSIZE};
 use nalgebra::Vector3;
-use std::{str, io, path::Path};
+use std::{str, io, io::Read, path::Path};
+use std::convert::TryFrom;
 use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
 use flate2::{Compression, GzBuilder, read::GzDecoder};
 use mimas_common::config::Config;
+use crate::claims::Claims;
 use toml::{from_str, to_string};
 use mimas_common::sqlite_generic::{get_user_version, set_user_version,
 	get_app_id, set_app_id, open_or_create_db};
 use mimas_common::local_auth::SqliteLocalAuth;
 use mimas_common::game_params::{NameIdMap, parse_block_name, Id};
-use mimas_common::inventory::SelectableInventory;
+use mimas_common::inventory::{SelectableInventory, Stack};
 
-use mimas_common::map_storage::{PlayerIdPair, DynStorageBackend, StorageBackend, NullStorageBackend};
+use mimas_common::map_storage::{PlayerIdPair, PlayerPosition, DynStorageBackend, StorageBackend, NullStorageBackend};
 
 pub struct SqliteStorageBackend {
 	conn :Connection,
@@ -145,22 +148,80 @@ fn serialize_mapchunk_data(data :&MapChunkData) -> Vec<u8> {
 				blocks.write_u8(0).unwrap();
 				inv.serialize_to(&mut blocks);
 			},
+			MetadataEntry::Furnace(state) => {
+				// Kind 1 stands for furnaces
+				blocks.write_u8(1).unwrap();
+				state.inv.serialize_to(&mut blocks);
+				blocks.write_f32::<BigEndian>(state.cook_time).unwrap();
+				blocks.write_f32::<BigEndian>(state.cook_time_total).unwrap();
+				blocks.write_f32::<BigEndian>(state.burn_time).unwrap();
+				blocks.write_f32::<BigEndian>(state.burn_time_total).unwrap();
+			},
+			MetadataEntry::Text(text) => {
+				// Kind 2 stands for texts
+				blocks.write_u8(2).unwrap();
+				blocks.write_u16::<BigEndian>(text.len() as u16).unwrap();
+				blocks.extend_from_slice(text.as_bytes());
+			},
+			MetadataEntry::LockedChest(state) => {
+				// Kind 3 stands for locked chests
+				blocks.write_u8(3).unwrap();
+				state.inv.serialize_to(&mut blocks);
+				serialize_player_id(&mut blocks, state.owner);
+				blocks.write_u16::<BigEndian>(state.members.len() as u16).unwrap();
+				for member in state.members.iter() {
+					serialize_player_id(&mut blocks, *member);
+				}
+			},
 		}
 	}
+	// Map::add_item_entity caps the number at MAX_ITEM_ENTITIES_PER_CHUNK
+	let item_entities_count = u16::try_from(data.1.item_entities.len())
+		.expect("too many item entities in chunk");
+	blocks.write_u16::<BigEndian>(item_entities_count).unwrap();
+	for ent in data.1.item_entities.iter() {
+		blocks.write_f32::<BigEndian>(ent.pos.x).unwrap();
+		blocks.write_f32::<BigEndian>(ent.pos.y).unwrap();
+		blocks.write_f32::<BigEndian>(ent.pos.z).unwrap();
+		blocks.write_u64::<BigEndian>(ent.spawned_at).unwrap();
+		let (id, count) = ent.stack.content()
+			.map(|(b, cnt)| (b.id(), cnt))
+			.unwrap_or((0, 0));
+		blocks.write_u8(id).unwrap();
+		blocks.write_u16::<BigEndian>(count).unwrap();
+		blocks.write_u16::<BigEndian>(ent.stack.wear()).unwrap();
+	}
+	// Facedirs, only stored if any block has a non default one
+	blocks.write_u8(!data.2.is_empty() as u8).unwrap();
+	blocks.extend_from_slice(&data.2);
 	let rdr :&[u8] = &blocks;
 	let mut gz_enc = GzBuilder::new().read(rdr, Compression::fast());
 	let mut r = Vec::<u8>::new();
 
 	// Version
-	r.write_u8(1).unwrap();
+	r.write_u8(3).unwrap();
 	io::copy(&mut gz_enc, &mut r).unwrap();
 	r
 }
 
+pub fn serialize_player_id(w :&mut Vec<u8>, id :PlayerIdPair) {
+	w.write_u8(id.id_src()).unwrap();
+	w.write_u64::<BigEndian>(id.id_u64()).unwrap();
+}
+
+pub fn deserialize_player_id(rdr :&mut &[u8]) -> Result<PlayerIdPair> {
+	let id_src = rdr.read_u8()?;
+	let id = rdr.read_u64::<BigEndian>()?;
+	if (id_src, id) == (0, 0) || id >= 1 << (64 - 17) {
+		bail!("Invalid player id {}", id);
+	}
+	Ok(PlayerIdPair::from_components(id_src, id))
+}
+
 fn deserialize_mapchunk_data(data :&[u8], m :&NameIdMap) -> Result<MapChunkData> {
 	let mut rdr = data;
 	let version = rdr.read_u8()?;
-	if version > 1 {
+	if version > 3 {
 		// The version is too recent
 
 		bail!("Unsupported map chunk version {}", version);
@@ -192,12 +253,70 @@ fn deserialize_mapchunk_data(data :&[u8], m :&NameIdMap) -> Result<MapChunkData>
 						let inv = SelectableInventory::deserialize_rdr(&mut rdr, m)?;
 						MetadataEntry::Inventory(inv)
 					},
+					// 1 is for furnaces
+					1 => {
+						let inv = SelectableInventory::deserialize_rdr(&mut rdr, m)?;
+						MetadataEntry::Furnace(FurnaceState {
+							inv,
+							cook_time : rdr.read_f32::<BigEndian>()?,
+							cook_time_total : rdr.read_f32::<BigEndian>()?,
+							burn_time : rdr.read_f32::<BigEndian>()?,
+							burn_time_total : rdr.read_f32::<BigEndian>()?,
+						})
+					},
+					// 2 is for texts
+					2 => {
+						let len = rdr.read_u16::<BigEndian>()? as usize;
+						let mut text = vec![0; len];
+						rdr.read_exact(&mut text)?;
+						MetadataEntry::Text(String::from_utf8(text)?)
+					},
+					// 3 is for locked chests
+					3 => {
+						let inv = SelectableInventory::deserialize_rdr(&mut rdr, m)?;
+						let owner = deserialize_player_id(&mut rdr)?;
+						let members_count = rdr.read_u16::<BigEndian>()?;
+						let members = (0 .. members_count)
+							.map(|_| deserialize_player_id(&mut rdr))
+							.collect::<Result<Vec<_>>>()?;
+						MetadataEntry::LockedChest(LockedChestState {
+							inv,
+							owner,
+							members,
+						})
+					},
 					_ => bail!("Unsupported entry kind"),
 				};
 				r.1.metadata.insert(pos, entry);
 			}
 		}
 	}
+	if version > 1 {
+		let count = rdr.read_u16::<BigEndian>()?;
+		for _ in 0 .. count {
+			let pos = Vector3::new(rdr.read_f32::<BigEndian>()?,
+				rdr.read_f32::<BigEndian>()?, rdr.read_f32::<BigEndian>()?);
+			let spawned_at = rdr.read_u64::<BigEndian>()?;
+			let item_id = rdr.read_u8()?;
+			let count = rdr.read_u16::<BigEndian>()?;
+			let wear = rdr.read_u16::<BigEndian>()?;
+			let item = m.mb_from_id(item_id)
+				.ok_or_else(|| anyhow!("invalid item id"))?;
+			r.1.item_entities.push(ItemEntity {
+				pos,
+				stack : Stack::with_wear(item, count, wear),
+				spawned_at,
+			});
+		}
+	}
+	if version > 2 {
+		let has_facedirs = rdr.read_u8()?;
+		if has_facedirs != 0 {
+			let mut facedirs = vec![0; (CHUNKSIZE * CHUNKSIZE * CHUNKSIZE) as usize];
+			rdr.read_exact(&mut facedirs)?;
+			r.2 = facedirs;
+		}
+	}
 	Ok(r)
 }
 
@@ -361,6 +480,40 @@ pub fn save_name_id_map(backend :&mut DynStorageBackend, nm :&NameIdMap) -> Resu
 	Ok(())
 }
 
+// This function is not generic on the backend because of a limitation of the language:
+// Box<dyn Trait> does not impl Trait.
+pub fn load_time_of_day(backend :&mut DynStorageBackend) -> Result<Option<f32>> {
+	let buf = if let Some(v) = backend.get_global_kv("time_of_day")? {
+		v
+	} else {
+		return Ok(None);
+	};
+	let time = (&buf[..]).read_f32::<BigEndian>()?;
+	Ok(Some(time))
+}
+
+pub fn serialize_time_of_day(time :f32) -> Vec<u8> {
+	let mut buf = Vec::new();
+	buf.write_f32::<BigEndian>(time).unwrap();
+	buf
+}
+
+pub fn load_claims(backend :&mut DynStorageBackend) -> Result<Claims> {
+	if let Some(buf) = backend.get_global_kv("claims")? {
+		Claims::deserialize(&buf)
+	} else {
+		Ok(Claims::default())
+	}
+}
+
+pub fn load_spawn_pos(backend :&mut DynStorageBackend) -> Result<Option<PlayerPosition>> {
+	if let Some(buf) = backend.get_global_kv("spawn_pos")? {
+		Ok(Some(PlayerPosition::deserialize(&buf)?))
+	} else {
+		Ok(None)
+	}
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct MapgenMetaToml {
 	seed :u64,
@@ -244,6 +244,68 @@ fn gen_chunk_phase_one(seed :u64, pos :Vector3<isize>,
 	res
 }
 
+/// Searches the generated terrain near the origin
+/// for a dry surface block to spawn players on.
+///
+/// Only the first generation phase is run, which is
+/// enough to know the terrain and where trees will grow.
+pub fn find_spawn_pos(seed :u64, params :&ServerGameParamsHdl) -> Option<Vector3<isize>> {
+	// How many chunks away from the origin to search
+	const SEARCH_RADIUS :isize = 8;
+	// The terrain never reaches above this chunk
+	const MAX_CHUNK_Z :isize = 16;
+	// Distance to keep from trees and the chunk borders,
+	// as trees of neighbouring chunks are unknown
+	const TREE_DIST :isize = 3;
+	let role = &params.p.block_roles;
+	let is_solid = |blk :MapBlock| params.p.get_block_params(blk)
+		.map(|bp| bp.solid)
+		.unwrap_or(false);
+	let mut columns = (-SEARCH_RADIUS ..= SEARCH_RADIUS)
+		.flat_map(|x| (-SEARCH_RADIUS ..= SEARCH_RADIUS).map(move |y| (x, y)))
+		.collect::<Vec<_>>();
+	columns.sort_by_key(|(x, y)| x * x + y * y);
+	let mut in_chunk = (TREE_DIST .. CHUNKSIZE - TREE_DIST)
+		.flat_map(|x| (TREE_DIST .. CHUNKSIZE - TREE_DIST).map(move |y| (x, y)))
+		.collect::<Vec<_>>();
+	in_chunk.sort_by_key(|(x, y)| (x - CHUNKSIZE / 2).pow(2) + (y - CHUNKSIZE / 2).pow(2));
+	for (cx, cy) in columns {
+		// Generate the column upwards until the top of a chunk is only air
+		let mut chunks = Vec::new();
+		for cz in 0 .. MAX_CHUNK_Z {
+			let chunk = gen_chunk_phase_one(seed, Vector3::new(cx, cy, cz) * CHUNKSIZE, params);
+			let top_is_air = (0 .. CHUNKSIZE)
+				.flat_map(|x| (0 .. CHUNKSIZE).map(move |y| Vector3::new(x, y, CHUNKSIZE - 1)))
+				.all(|p| *chunk.get_blk(p) == role.air);
+			chunks.push(chunk);
+			if top_is_air {
+				break;
+			}
+		}
+		for &(x, y) in in_chunk.iter() {
+			let blk_at = |z :isize| *chunks[(z / CHUNKSIZE) as usize]
+				.get_blk(Vector3::new(x, y, z % CHUNKSIZE));
+			let top = (0 .. chunks.len() as isize * CHUNKSIZE)
+				.rev()
+				.find(|z| blk_at(*z) != role.air);
+			// Step down from plants onto the ground below them
+			let surface = match top {
+				Some(z) if is_solid(blk_at(z)) => z,
+				Some(z) if z > 0 && blk_at(z) != role.water && is_solid(blk_at(z - 1)) => z - 1,
+				_ => continue,
+			};
+			let pos = Vector3::new(cx * CHUNKSIZE + x, cy * CHUNKSIZE + y, surface);
+			let near_tree = chunks.iter()
+				.flat_map(|chunk| chunk.tree_spawn_points.iter())
+				.any(|(p, _)| (p.x - pos.x).abs() <= TREE_DIST && (p.y - pos.y).abs() <= TREE_DIST);
+			if !near_tree {
+				return Some(pos);
+			}
+		}
+	}
+	None
+}
+
 struct Noise {
 	freq :f64,
 	perlin :Perlin,
@@ -440,6 +502,9 @@ pub enum MapgenMsg {
 	GenArea(Vector3<isize>, Vector3<isize>),
 	SetPlayerKv(PlayerIdPair, String, Vec<u8>),
 	GetPlayerKv(PlayerIdPair, String, u32),
+	SetGlobalKv(String, Vec<u8>),
+	/// Commits the storage, then notifies the sender
+	Flush(Sender<()>),
 }
 
 pub struct MapgenThread {
@@ -476,6 +541,13 @@ impl MapgenThread {
 						let res = mapgen_map.storage.get_player_kv(id, &key).unwrap();
 						result_kv_s.send((id, payload, key, res)).unwrap();
 					},
+					MapgenMsg::SetGlobalKv(key, content) => {
+						mapgen_map.storage.set_global_kv(&key, &content).unwrap();
+					},
+					MapgenMsg::Flush(done_s) => {
+						mapgen_map.storage.tick().unwrap();
+						let _ = done_s.send(());
+					},
 				}
 			}
 		});
@@ -514,9 +586,39 @@ impl MapBackend for MapgenThread {
 			f(id, payload, key, value);
 		}
 	}
+	fn set_global_kv(&mut self, key :&str, value :Vec<u8>) {
+		self.area_s.send(MapgenMsg::SetGlobalKv(key.to_owned(), value)).unwrap();
+	}
+	fn flush(&mut self) {
+		let (done_s, done_r) = channel();
+		self.area_s.send(MapgenMsg::Flush(done_s)).unwrap();
+		let _ = done_r.recv();
+	}
 }
 
 pub fn server_map_new(seed :u64, params :ServerGameParamsHdl,
 		storage :DynStorageBackend) -> ServerMap {
 	Map::from_backend(MapgenThread::new(seed, params, storage))
 }
+
+#[cfg(test)]
+#[test]
+fn test_find_spawn_pos() {
+	use std::sync::Arc;
+	let params = Arc::new(crate::game_params::test_game_params());
+	let seed = 78;
+	let pos = find_spawn_pos(seed, &params).expect("No spawn position found");
+	let role = &params.p.block_roles;
+	let blk_at = |p :Vector3<isize>| {
+		let chunk = gen_chunk_phase_one(seed, btchn(p), &params);
+		*chunk.get_blk(btpic(p))
+	};
+	let ground = blk_at(pos);
+	assert!(params.p.get_block_params(ground).unwrap().solid);
+	assert_ne!(ground, role.water);
+	for z in 1 ..= 2 {
+		let blk = blk_at(pos + Vector3::new(0, 0, z));
+		assert!(!params.p.get_block_params(blk).map(|bp| bp.solid).unwrap_or(false));
+		assert!(params.get_liquid(blk).is_none());
+	}
+}
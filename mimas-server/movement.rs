@@ -0,0 +1,280 @@
+//! Checks of the positions players send against
+//! the elapsed time, their modes and the map.
+
+use mimas_common::map::{Map, MapBackend};
+use mimas_common::game_params::GameParams;
+use mimas_common::collide::{overlaps_blocks, player_collision_pos};
+use mimas_common::player::PlayerMode;
+use nalgebra::Vector3;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// Walking speed of the client in blocks per second
+const WALK_SPEED :f32 = 10.0;
+/// Speed of the client in fast mode in blocks per second
+const FAST_SPEED :f32 = 40.0;
+/// Maximum falling speed of the client in blocks per second
+const MAX_FALLING_SPEED :f32 = 40.0;
+/// Factor on the speeds to allow for timing differences
+/// between client and server
+const SPEED_SLACK :f32 = 1.5;
+/// Distance a player may move on top of the speed limit
+const DISTANCE_TOLERANCE :f32 = 1.0;
+/// How high a player may rise without standing on something.
+/// A jump reaches a bit more than one block.
+const MAX_RISE :f32 = 2.0;
+/// How far below the player a block still supports it
+const SUPPORT_DISTANCE :f32 = 0.2;
+/// How much the player may overlap with solid blocks,
+/// to allow for rounding errors
+const OVERLAP_MARGIN :f32 = 0.1;
+/// The largest distance between two positions on the path of a move
+/// that get checked for collisions. Smaller than the thinnest part
+/// of the player, so that it can't pass through blocks.
+const PATH_STEP :f32 = 0.25;
+
+/// The time over which the distance a player moves is measured.
+/// Measuring single updates would allow the tolerance each time.
+const ANCHOR_INTERVAL :Duration = Duration::from_secs(1);
+/// How long position updates sent before the server
+/// moved the player are ignored if they are invalid
+const GRACE_PERIOD :Duration = Duration::from_secs(1);
+/// Violations within this time of each other count as repeated
+const VIOLATION_WINDOW :Duration = Duration::from_secs(60);
+/// Number of repeated violations after which a player gets flagged
+pub const REPEAT_OFFENDER_VIOLATIONS :u32 = 10;
+
+pub struct MoveState {
+	/// The last accepted position
+	pos :Vector3<f32>,
+	/// Position and time movement is measured from
+	anchor :(Vector3<f32>, Instant),
+	/// The lowest height since the player last stood on something
+	support_z :f32,
+	grace_until :Instant,
+	violations :u32,
+	last_violation :Instant,
+}
+
+pub enum MoveVerdict {
+	Accept,
+	/// The update was sent before the player got moved by the server
+	Ignore,
+	Reject(String),
+}
+
+impl MoveState {
+	pub fn new(pos :Vector3<f32>, now :Instant) -> Self {
+		Self {
+			pos,
+			anchor : (pos, now),
+			support_z : pos.z,
+			grace_until : now + GRACE_PERIOD,
+			violations : 0,
+			last_violation : now,
+		}
+	}
+	/// Resets the state after the server moved the player
+	pub fn moved_by_server(&mut self, pos :Vector3<f32>, now :Instant) {
+		self.pos = pos;
+		self.anchor = (pos, now);
+		self.support_z = pos.z;
+		self.grace_until = now + GRACE_PERIOD;
+	}
+	/// Counts a violation and returns the number
+	/// of violations within the window of each other
+	pub fn add_violation(&mut self, now :Instant) -> u32 {
+		if now.duration_since(self.last_violation) > VIOLATION_WINDOW {
+			self.violations = 0;
+		}
+		self.violations += 1;
+		self.last_violation = now;
+		self.violations
+	}
+	/// Checks whether the player may move from the
+	/// last accepted position to the new one
+	///
+	/// Blocks in chunks that aren't loaded are assumed to neither
+	/// support nor block the player.
+	pub fn check<B :MapBackend>(&mut self, map :&Map<B>, params :&GameParams,
+			modes :&HashSet<PlayerMode>, may_move_fast :bool,
+			new :Vector3<f32>, now :Instant) -> MoveVerdict {
+		match self.check_inner(map, params, modes, may_move_fast, new, now) {
+			Err(_) if now < self.grace_until => MoveVerdict::Ignore,
+			Err(reason) => MoveVerdict::Reject(reason),
+			Ok(support_z) => {
+				self.pos = new;
+				self.support_z = support_z;
+				if now.duration_since(self.anchor.1) >= ANCHOR_INTERVAL {
+					self.anchor = (new, now);
+				}
+				MoveVerdict::Accept
+			},
+		}
+	}
+	/// Returns the new lowest height since the player stood on something
+	fn check_inner<B :MapBackend>(&self, map :&Map<B>, params :&GameParams,
+			modes :&HashSet<PlayerMode>, may_move_fast :bool,
+			new :Vector3<f32>, now :Instant) -> Result<f32, String> {
+		if !new.iter().all(|v| v.is_finite()) {
+			return Err("invalid position".to_owned());
+		}
+		let fly = modes.contains(&PlayerMode::Fly);
+		let noclip = fly && modes.contains(&PlayerMode::Noclip);
+		let speed = if may_move_fast { FAST_SPEED } else { WALK_SPEED };
+
+		// Measure the distance from the anchor, but don't let
+		// players save up time by not sending any updates
+		let (anchor_pos, anchor_time) = self.anchor;
+		let elapsed = now.duration_since(anchor_time)
+			.min(ANCHOR_INTERVAL * 2)
+			.as_secs_f32();
+		let delta = new - anchor_pos;
+		let max_horizontal = speed * SPEED_SLACK * elapsed + DISTANCE_TOLERANCE;
+		if delta.xy().norm() > max_horizontal {
+			return Err(format!("moved {:.1} blocks in {:.2}s", delta.xy().norm(), elapsed));
+		}
+		let max_down = speed.max(MAX_FALLING_SPEED) * SPEED_SLACK * elapsed + DISTANCE_TOLERANCE;
+		let max_up = speed * SPEED_SLACK * elapsed + DISTANCE_TOLERANCE;
+		if delta.z < -max_down || delta.z > max_up {
+			return Err(format!("moved {:.1} blocks vertically in {:.2}s", delta.z, elapsed));
+		}
+
+		let block_params = |p :Vector3<isize>| map.get_blk(p)
+			.map(|blk| params.get_block_params(blk));
+		let solid = |p :Vector3<isize>| match block_params(p) {
+			Some(Some(bp)) => bp.solid,
+			_ => false,
+		};
+		let climbable = |p :Vector3<isize>| match block_params(p) {
+			Some(Some(bp)) => bp.climbable,
+			_ => false,
+		};
+
+		let new_coll = player_collision_pos(new);
+		if !noclip {
+			// Check the whole path, so that players can't pass through
+			// walls. Players stuck inside blocks, e.g. placed by others,
+			// may move out of them, but not into other blocks after that.
+			let old_coll = player_collision_pos(self.pos);
+			let path = new_coll - old_coll;
+			let steps = (path.norm() / PATH_STEP).ceil().max(1.0) as usize;
+			let mut left_blocks = !overlaps_blocks(old_coll, OVERLAP_MARGIN, solid);
+			for i in 1 ..= steps {
+				let pos = old_coll + path * (i as f32 / steps as f32);
+				let in_blocks = overlaps_blocks(pos, OVERLAP_MARGIN, solid);
+				if in_blocks && left_blocks {
+					return Err("moved into a solid block".to_owned());
+				}
+				left_blocks |= !in_blocks;
+			}
+		}
+
+		let below = new_coll - Vector3::new(0.0, 0.0, SUPPORT_DISTANCE);
+		let supported = overlaps_blocks(below, 0.01, solid) ||
+			overlaps_blocks(new_coll, 0.0, climbable);
+		if fly || supported {
+			return Ok(new.z);
+		}
+		let support_z = self.support_z.min(new.z);
+		if new.z - support_z > MAX_RISE {
+			return Err(format!("rose {:.1} blocks without support", new.z - support_z));
+		}
+		Ok(support_z)
+	}
+}
+
+/// A map with a floor of stone at z = 0, and a wall at x = 12
+/// from z = 1 to 3, inside of the chunk at the origin
+#[cfg(test)]
+fn test_map(params :&GameParams) -> mimas_common::map::ClientMap {
+	use mimas_common::map::MapChunkData;
+	let stone = params.search_block_name("default:stone").unwrap();
+	let mut map = mimas_common::map::ClientMap::new();
+	let mut chunk = MapChunkData::filled_with(params.block_roles.air);
+	for x in 0 .. 16 {
+		for y in 0 .. 16 {
+			*chunk.get_blk_mut(Vector3::new(x, y, 0)) = stone;
+		}
+		for z in 1 ..= 3 {
+			*chunk.get_blk_mut(Vector3::new(12, x, z)) = stone;
+		}
+	}
+	map.set_chunk(Vector3::new(0, 0, 0), chunk);
+	map
+}
+
+/// The camera position of a player standing on the block
+#[cfg(test)]
+fn standing_on(x :isize, y :isize, z :isize) -> Vector3<f32> {
+	Vector3::new(x as f32 + 0.35, y as f32 + 0.35, z as f32 + 2.8)
+}
+
+#[cfg(test)]
+#[test]
+fn test_move_check() {
+	let params = crate::game_params::test_game_params();
+	let params = &params.p;
+	let map = test_map(params);
+	let walking = HashSet::new();
+	let flying = [PlayerMode::Fly].iter().copied().collect::<HashSet<_>>();
+	let noclip = [PlayerMode::Fly, PlayerMode::Noclip].iter().copied().collect::<HashSet<_>>();
+	let accepts = |verdict| matches!(verdict, MoveVerdict::Accept);
+	let rejects = |verdict| matches!(verdict, MoveVerdict::Reject(_));
+	let start = Instant::now();
+	let secs = |s :f32| start + Duration::from_secs_f32(s);
+
+	// Walking along the floor
+	let mut state = MoveState::new(standing_on(2, 8, 0), start);
+	assert!(accepts(state.check(&map, params, &walking, false, standing_on(6, 8, 0), secs(2.0))));
+	assert!(accepts(state.check(&map, params, &walking, false, standing_on(7, 8, 0), secs(2.1))));
+
+	// Too fast, unless moving fast is allowed
+	let too_far = standing_on(11, 8, 0);
+	assert!(rejects(state.check(&map, params, &walking, false, too_far, secs(2.2))));
+	assert!(accepts(state.check(&map, params, &walking, true, too_far, secs(2.2))));
+
+	// Rising without support, unless flying
+	let mut state = MoveState::new(standing_on(4, 8, 0), start);
+	let in_air = standing_on(4, 8, 3);
+	assert!(rejects(state.check(&map, params, &walking, false, in_air, secs(2.0))));
+	assert!(accepts(state.check(&map, params, &flying, false, in_air, secs(2.0))));
+	// A jump is fine
+	let mut state = MoveState::new(standing_on(4, 8, 0), start);
+	let jump = standing_on(4, 8, 0) + Vector3::new(0.0, 0.0, 1.2);
+	assert!(accepts(state.check(&map, params, &walking, false, jump, secs(2.0))));
+
+	// Walking into the wall, unless in noclip mode
+	let mut state = MoveState::new(standing_on(10, 8, 0), start);
+	let in_wall = standing_on(12, 8, 0);
+	assert!(rejects(state.check(&map, params, &walking, false, in_wall, secs(2.0))));
+	assert!(rejects(state.check(&map, params, &flying, false, in_wall, secs(2.0))));
+	assert!(accepts(state.check(&map, params, &noclip, false, in_wall, secs(2.0))));
+	// Players inside of blocks may move out of them
+	let mut state = MoveState::new(in_wall, start);
+	assert!(accepts(state.check(&map, params, &walking, false, standing_on(11, 8, 0), secs(2.0))));
+	// Jumping through the wall within the speed limit
+	let mut state = MoveState::new(standing_on(11, 8, 0), start);
+	let behind_wall = standing_on(13, 8, 0);
+	assert!(rejects(state.check(&map, params, &walking, false, behind_wall, secs(2.0))));
+	assert!(rejects(state.check(&map, params, &walking, true, behind_wall, secs(2.0))));
+	assert!(accepts(state.check(&map, params, &noclip, false, behind_wall, secs(2.0))));
+
+	// Invalid updates sent before the server moved
+	// the player are ignored for a while
+	let far_away = standing_on(2, 40, 0);
+	let mut state = MoveState::new(standing_on(2, 8, 0), start);
+	assert!(matches!(state.check(&map, params, &walking, false, far_away, secs(0.5)),
+		MoveVerdict::Ignore));
+	state.moved_by_server(standing_on(2, 8, 0), secs(5.0));
+	assert!(matches!(state.check(&map, params, &walking, false, far_away, secs(5.1)),
+		MoveVerdict::Ignore));
+	assert!(rejects(state.check(&map, params, &walking, false, far_away, secs(6.1))));
+
+	// Blocks in unloaded chunks neither support nor block the player
+	let mut state = MoveState::new(standing_on(-4, 8, 0), start);
+	let above_unloaded = standing_on(-4, 8, 5);
+	assert!(rejects(state.check(&map, params, &walking, false, above_unloaded, secs(2.0))));
+	let mut state = MoveState::new(standing_on(-4, 8, 0), start);
+	assert!(accepts(state.check(&map, params, &walking, false, standing_on(-8, 8, 0), secs(2.0))));
+}
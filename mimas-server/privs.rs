@@ -0,0 +1,60 @@
+use mimas_common::player::PlayerMode;
+
+/// Allows giving items to oneself and clearing inventories
+pub const GIVE :&str = "give";
+/// Allows teleporting oneself, e.g. to spawn,
+/// and looking up the positions of other players
+pub const TELEPORT :&str = "teleport";
+/// Allows teleporting other players
+pub const BRING :&str = "bring";
+/// Allows setting a home and teleporting to it
+pub const HOME :&str = "home";
+/// Allows enabling fly mode
+pub const FLY :&str = "fly";
+/// Allows enabling fast mode
+pub const FAST :&str = "fast";
+/// Allows enabling noclip mode
+pub const NOCLIP :&str = "noclip";
+/// Allows granting and revoking privileges of other players
+pub const PRIVS :&str = "privs";
+/// Allows setting the time of day
+pub const SETTIME :&str = "settime";
+/// Allows editing the text of signs
+pub const SIGN :&str = "sign";
+/// Allows muting and unmuting players
+pub const MUTE :&str = "mute";
+/// Allows broadcasting messages, kicking players
+/// and shutting down the server
+pub const SERVER :&str = "server";
+/// Allows bypassing area protection and managing all claims
+pub const PROTECTION :&str = "protection";
+
+/// List of all privileges known to the server, with descriptions
+pub const KNOWN_PRIVS :&[(&str, &str)] = &[
+	(GIVE, "Give items to oneself and clear inventories"),
+	(TELEPORT, "Teleport oneself and locate other players"),
+	(BRING, "Teleport other players"),
+	(HOME, "Set a home and teleport to it"),
+	(FLY, "Enable fly mode"),
+	(FAST, "Enable fast mode"),
+	(NOCLIP, "Enable noclip mode"),
+	(PRIVS, "Grant and revoke privileges"),
+	(SETTIME, "Set the time of day"),
+	(SIGN, "Edit the text of signs"),
+	(MUTE, "Mute and unmute players"),
+	(SERVER, "Broadcast messages, kick players and shut down the server"),
+	(PROTECTION, "Bypass area protection and manage all claims"),
+];
+
+pub fn is_known_priv(name :&str) -> bool {
+	KNOWN_PRIVS.iter().any(|(n, _desc)| *n == name)
+}
+
+/// Returns the privilege needed to enable the given mode
+pub fn priv_for_mode(mode :PlayerMode) -> &'static str {
+	match mode {
+		PlayerMode::Fly => FLY,
+		PlayerMode::Fast => FAST,
+		PlayerMode::Noclip => NOCLIP,
+	}
+}
@@ -1,22 +1,30 @@
 use mimas_common::generic_net::{NetworkServerSocket, NetworkServerConn, NetErr};
 use mimas_common::config::Config;
-use mimas_common::crafting::get_matching_recipe;
+use mimas_common::crafting;
+use mimas_common::interaction;
 use mimas_common::map::{self, Map, MapBackend,
-	CHUNKSIZE, MetadataEntry};
+	CHUNKSIZE, MapBlock, MetadataEntry, FurnaceState, LockedChestState, ItemEntity,
+	FURNACE_OUTPUT_SLOT, SIGN_TEXT_MAX_LEN};
 use mimas_common::map_storage::{PlayerIdPair, PlayerPosition};
 use mimas_common::inventory::{self, SelectableInventory, Stack, InventoryPos,
 	InventoryLocation, InvRef};
 use mimas_common::local_auth::{SqliteLocalAuth, AuthBackend};
-use mimas_common::game_params::ServerGameParamsHdl;
-use mimas_common::protocol::{ClientToServerMsg, ServerToClientMsg};
-use mimas_common::player::PlayerMode;
-use mimas_common::btchn;
+use mimas_common::game_params::{ServerGameParamsHdl, BlockParams, AbmAction,
+	asset_dir_relative};
+use mimas_common::protocol::{ClientToServerMsg, ServerToClientMsg, ChatKind};
+use mimas_common::player::{PlayerMode, MAX_HP};
+use mimas_common::{btchn, btpic, time_of_day};
+use mimas_common::facedir::Facedir;
 use anyhow::Result;
+use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
 use nalgebra::Vector3;
 use std::time::{Instant, Duration};
+use std::io::BufRead;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
 use std::thread;
 use std::cell::RefCell;
-use std::collections::{HashSet, HashMap, hash_map};
+use std::collections::{HashSet, HashMap, VecDeque, hash_map};
 use std::rc::Rc;
 use srp::server::{SrpServer, UserRecord};
 use srp::client::SrpClient;
@@ -24,9 +32,19 @@ use srp::groups::G_4096;
 use sha2::Sha256;
 use rand::RngCore;
 
-use crate::mapgen::{ServerMap, server_map_new};
+use crate::mapgen::{self, ServerMap, server_map_new};
 use crate::game_params::load_server_game_params;
 use crate::map_storage;
+use crate::privs;
+use crate::block_update::UpdateQueue;
+use crate::{liquid, falling, furnace};
+use crate::abm::{self, AbmTimer, AbmIndex};
+use crate::commands::{self, Arg, Command, CommandRegistry, Handler, ArgKind, arg, opt};
+use crate::chat_log::ChatLog;
+use crate::clock::{Clock, SystemClock, TickScheduler};
+use crate::movement::{MoveState, MoveVerdict, REPEAT_OFFENDER_VIOLATIONS};
+use crate::console::{self, console_id};
+use crate::claims::{Claims, ClaimFlag, check_claim_size};
 
 enum AuthState {
 	Unauthenticated,
@@ -42,12 +60,52 @@ fn chunk_positions_around(pos :Vector3<isize>, xyradius :isize, zradius :isize)
 	(chunk_pos_min, chunk_pos_max)
 }
 
+
+/// Adds the wear of digging the given block to the selected tool
+fn add_tool_wear(params :&ServerGameParamsHdl, inv :&mut SelectableInventory, blk :MapBlock) {
+	let sel_idx = match inv.selection() {
+		Some(idx) => idx,
+		None => return,
+	};
+	let stack = &mut inv.stacks_mut()[sel_idx];
+	let tool = match stack.content() {
+		Some((tool, _count)) => tool,
+		None => return,
+	};
+	if let Some((wear, max_wear)) = interaction::tool_wear(&params.p, blk, tool) {
+		stack.add_wear(wear, max_wear);
+	}
+}
+
 fn gen_chunks_around<B :MapBackend>(map :&mut Map<B>, pos :Vector3<isize>, xyradius :isize, zradius :isize) {
 	let (chunk_pos_min, chunk_pos_max) = chunk_positions_around(pos, xyradius, zradius);
 	map.gen_chunks_in_area(chunk_pos_min, chunk_pos_max);
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Clone)]
+/// Finds a spawn position on the terrain of fresh worlds
+fn find_spawn_pos(seed :u64, params :&ServerGameParamsHdl) -> PlayerPosition {
+	if let Some(p) = mapgen::find_spawn_pos(seed, params) {
+		println!("Found spawn position at ({}, {}, {})", p.x, p.y, p.z);
+		let pos = p.map(|v| v as f32) +
+			Vector3::new(0.5, 0.5, 1.0 + PLAYER_EYE_HEIGHT);
+		PlayerPosition::from_pos(pos)
+	} else {
+		println!("Couldn't find a dry spawn position, using the default one");
+		PlayerPosition::default()
+	}
+}
+
+/// Searches the spawn position in a thread of its own,
+/// as the search can take a while
+fn start_spawn_search(seed :u64, params :ServerGameParamsHdl) -> Receiver<PlayerPosition> {
+	let (sender, receiver) = channel();
+	thread::spawn(move || {
+		let _ = sender.send(find_spawn_pos(seed, &params));
+	});
+	receiver
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
 struct PlayerSlowStates {
 	// Nick of the player.
 	// This is NOT the authoritative location,
@@ -55,15 +113,49 @@ struct PlayerSlowStates {
 	// who haven't gotten a copy of the auth db.
 	nick :String,
 	modes :HashSet<PlayerMode>,
+	// Privileges of the player.
+	// None if the states were stored before
+	// privileges were introduced.
+	#[serde(default)]
+	privs :Option<HashSet<String>>,
+	// Position of the bed the player has placed last
+	#[serde(default)]
+	bed :Option<Vector3<isize>>,
+	// Whether the player has been muted, and if
+	// only temporarily, the unix time until when
+	#[serde(default)]
+	muted :bool,
+	#[serde(default)]
+	muted_until :Option<u64>,
+	// Position set with the /sethome command.
+	// Needs to stay the last field as it's a toml table.
+	#[serde(default)]
+	home :Option<PlayerPosition>,
 }
 
 impl PlayerSlowStates {
-	fn new(nick :&str) -> Self {
+	fn new(nick :&str, default_privs :&[String]) -> Self {
 		Self {
 			nick : nick.to_owned(),
 			modes : HashSet::new(),
+			privs : Some(default_privs.iter().cloned().collect()),
+			bed : None,
+			muted : false,
+			muted_until : None,
+			home : None,
 		}
 	}
+	fn privs(&self) -> impl Iterator<Item = &str> {
+		self.privs.iter().flat_map(|p| p.iter()).map(|p| p.as_str())
+	}
+	fn privs_mut(&mut self) -> &mut HashSet<String> {
+		self.privs.get_or_insert_with(HashSet::new)
+	}
+	fn is_muted(&self, unix_secs :u64) -> bool {
+		self.muted && self.muted_until
+			.map(|until| unix_secs < until)
+			.unwrap_or(true)
+	}
 }
 
 struct Player<C: NetworkServerConn> {
@@ -78,13 +170,34 @@ struct Player<C: NetworkServerConn> {
 	craft_inventory_last_ser :SelectableInventory,
 	slow_states :PlayerSlowStates,
 	slow_states_last_ser :PlayerSlowStates,
+	hp :u16,
+	hp_last_ser :u16,
+
+	// The z position at which the player has started falling
+	fall_start_z :Option<f32>,
+	// Until when position updates are not considered
+	// for fall damage, e.g. after teleporting
+	fall_grace_until :Instant,
+	// Seconds the player can still stay under water
+	breath :u16,
+	movement :MoveState,
 
 	sent_chunks :HashSet<Vector3<isize>>,
 	last_chunk_pos :Vector3<isize>,
+
+	// Position of the block the player is digging,
+	// and when the digging has been started
+	digging :Option<(Vector3<isize>, Instant)>,
+	// Corners of the claim the player is about to create
+	claim_corners :[Option<Vector3<isize>>; 2],
+	// Positions of the chests and furnaces the player has opened
+	open_chests :HashSet<Vector3<isize>>,
+	// When the player has sent the recent chat messages
+	chat_times :VecDeque<Instant>,
 }
 
 impl<C: NetworkServerConn> Player<C> {
-	pub fn from_waiting(waiting :KvWaitingPlayer<C>) -> Self {
+	pub fn from_waiting(waiting :KvWaitingPlayer<C>, default_privs :&[String], now :Instant) -> Self {
 		let mut slow_states = waiting.slow_states.clone().unwrap();
 		// The nick field in slow_states is only a map db cache of
 		// the real nick stored in the auth db, helpful e.g. when
@@ -96,26 +209,59 @@ impl<C: NetworkServerConn> Player<C> {
 		if slow_states.nick != waiting.nick {
 			slow_states.nick = waiting.nick.clone();
 		}
+		// Same goes for players who have been stored
+		// before privileges were introduced: they get
+		// the default privileges.
+		if slow_states.privs.is_none() {
+			*slow_states.privs_mut() = default_privs.iter().cloned().collect();
+		}
 		Player {
 			conn : waiting.conn,
 			ids : waiting.ids,
 			nick : waiting.nick,
-			pos : PlayerPosition::default(),
+			pos : waiting.pos.unwrap(),
 			inventory : waiting.inv.clone().unwrap(),
 			inventory_last_ser : waiting.inv.unwrap(),
 			craft_inventory : waiting.craft_inv.clone().unwrap(),
 			craft_inventory_last_ser : waiting.craft_inv.clone().unwrap(),
 			slow_states,
 			slow_states_last_ser : waiting.slow_states.clone().unwrap(),
+			hp : waiting.hp.unwrap(),
+			hp_last_ser : waiting.hp.unwrap(),
+			fall_start_z : None,
+			fall_grace_until : now,
+			movement : MoveState::new(waiting.pos.unwrap().pos(), now),
+			breath : MAX_BREATH,
 			sent_chunks : HashSet::new(),
 			last_chunk_pos : Vector3::new(0, 0, 0),
+			digging : None,
+			claim_corners : [None, None],
+			open_chests : HashSet::new(),
+			chat_times : VecDeque::new(),
 		}
 	}
 	fn pos(&self) -> Vector3<f32> {
 		self.pos.pos()
 	}
+	/// Updates the fall tracking with a new position sent by the client
+	fn track_fall(&mut self, new_z :f32, now :Instant) {
+		let old_z = self.pos().z;
+		if now < self.fall_grace_until || new_z > old_z {
+			self.fall_start_z = None;
+		} else if new_z < old_z && self.fall_start_z.is_none() {
+			self.fall_start_z = Some(old_z);
+		}
+	}
 }
 
+/// Number of seconds a player can stay under water before drowning
+const MAX_BREATH :u16 = 10;
+
+/// The distance between the eyes and the feet of a player
+const PLAYER_EYE_HEIGHT :f32 = 1.8;
+/// How far from the origin players can teleport to
+const MAX_TELEPORT_COORD :f32 = 1_000_000.0;
+
 struct KvWaitingPlayer<C: NetworkServerConn> {
 	conn :C,
 	ids :PlayerIdPair,
@@ -124,6 +270,7 @@ struct KvWaitingPlayer<C: NetworkServerConn> {
 	inv :Option<SelectableInventory>,
 	craft_inv :Option<SelectableInventory>,
 	slow_states :Option<PlayerSlowStates>,
+	hp :Option<u16>,
 }
 
 impl<C: NetworkServerConn> KvWaitingPlayer<C> {
@@ -136,12 +283,14 @@ impl<C: NetworkServerConn> KvWaitingPlayer<C> {
 			inv : None,
 			craft_inv : None,
 			slow_states : None,
+			hp : None,
 		}
 	}
 	fn ready(&self) -> bool {
 		self.pos.is_some() && self.inv.is_some()
 			&& self.craft_inv.is_some()
 			&& self.slow_states.is_some()
+			&& self.hp.is_some()
 	}
 }
 
@@ -155,28 +304,81 @@ pub struct Server<S :NetworkServerSocket> {
 	players_waiting_for_kv :HashMap<PlayerIdPair, KvWaitingPlayer<S::Conn>>,
 	players :Rc<RefCell<HashMap<PlayerIdPair, Player<S::Conn>>>>,
 
-	last_frame_time :Instant,
+	clock :Box<dyn Clock>,
 	last_pos_storage_time :Instant,
-	last_fps :f32,
+	last_map_storage_time :Instant,
+	last_env_damage_time :Instant,
+	last_furnace_step :Instant,
+	time_of_day :f32,
+	last_time_sync :Instant,
+	ticks :TickScheduler,
 
 	map :ServerMap,
+	liquid_updates :UpdateQueue,
+	falling_updates :UpdateQueue,
+	abm_timer :AbmTimer,
+	/// Shared with the map's on_change handler, which invalidates it
+	abm_index :Rc<RefCell<AbmIndex>>,
+	claims :Claims,
+	commands :CommandRegistry<Self>,
+	chat_log :ChatLog,
+	console :Option<Receiver<String>>,
+	shutdown_requested :bool,
+	/// Spawn position found on the map or set with /setspawn.
+	/// The one from the config takes precedence.
+	spawn_pos :PlayerPosition,
+	/// The search for the spawn position of a fresh world, while it runs.
+	/// Players only join once it is done.
+	spawn_search :Option<Receiver<PlayerPosition>>,
+	/// Teleports waiting for the map at their destination
+	pending_teleports :HashMap<PlayerIdPair, PlayerPosition>,
 }
 
 impl<S :NetworkServerSocket> Server<S> {
 	pub fn new(srv_socket :S,
-			singleplayer :bool, mut config :Config) -> Self {
+			singleplayer :bool, config :Config) -> Self {
+		Self::new_with_clock(srv_socket, singleplayer, config, SystemClock)
+	}
+	/// Creates a server whose time based logic uses the clock
+	pub fn new_with_clock(srv_socket :S,
+			singleplayer :bool, config :Config, clock :impl Clock + 'static) -> Self {
+		let asset_dir = asset_dir_relative().expect("Could not obtain asset directory");
+		Self::with_asset_dir(srv_socket, singleplayer, config, clock, &asset_dir)
+	}
+	/// Creates a server that loads the assets of the game params from the directory
+	pub(crate) fn with_asset_dir(srv_socket :S, singleplayer :bool, mut config :Config,
+			clock :impl Clock + 'static, asset_dir :&Path) -> Self {
+		let now = clock.now();
 		let backends = map_storage::backends_from_config(&mut config, !singleplayer);
 		let (mut storage_back, auth_back) = backends;
 		let nm = map_storage::load_name_id_map(&mut storage_back).unwrap();
-		let params = load_server_game_params(nm);
+		// Fresh worlds start in the morning
+		let time_of_day = map_storage::load_time_of_day(&mut storage_back).unwrap()
+			.unwrap_or(0.3);
+		let claims = map_storage::load_claims(&mut storage_back).unwrap();
+		let params = load_server_game_params(nm, asset_dir);
 		map_storage::save_name_id_map(&mut storage_back, &params.p.name_id_map).unwrap();
+		let stored_spawn_pos = map_storage::load_spawn_pos(&mut storage_back).unwrap();
+		let (spawn_pos, spawn_search) = match stored_spawn_pos {
+			Some(pos) => (pos, None),
+			// The spawn position from the config is used instead
+			None if config.spawn_pos.is_some() => (PlayerPosition::default(), None),
+			None => {
+				// Fresh worlds get their spawn position from the terrain
+				let search = start_spawn_search(config.mapgen_seed, params.clone());
+				(PlayerPosition::default(), Some(search))
+			},
+		};
 		let mut map = server_map_new(config.mapgen_seed,
 			params.clone(), storage_back);
 
 		let unauthenticated_players = Vec::<_>::new();
 		let players = Rc::new(RefCell::new(HashMap::<_, Player<S::Conn>>::new()));
 		let playersc = players.clone();
+		let abm_index = Rc::new(RefCell::new(AbmIndex::default()));
+		let abm_indexc = abm_index.clone();
 		map.register_on_change(Box::new(move |chunk_pos, chunk| {
+			abm_indexc.borrow_mut().invalidate(chunk_pos);
 			let mut players = playersc.borrow_mut();
 			let msg = ServerToClientMsg::ChunkUpdated(chunk_pos, chunk.clone());
 			let mut conns_to_close = Vec::new();
@@ -186,10 +388,45 @@ impl<S :NetworkServerSocket> Server<S> {
 					Ok(_) => (),
 					Err(_) => conns_to_close.push(*id),
 				}
+				// Keep the chests the player views in sync
+				let open_in_chunk = player.open_chests.iter()
+					.filter(|p| btchn(**p) == chunk_pos)
+					.copied()
+					.collect::<Vec<_>>();
+				for p in open_in_chunk {
+					let meta = chunk.get_blk_meta(btpic(p))
+						.filter(|entry| entry.inventory().is_some())
+						.cloned();
+					if meta.is_none() {
+						player.open_chests.remove(&p);
+					}
+					let msg = ServerToClientMsg::SetChestMeta(p, meta);
+					if player.conn.send(msg).is_err() {
+						conns_to_close.push(*id);
+					}
+				}
+			}
+			close_connections(&conns_to_close, &mut *players);
+		}));
+		let playersc = players.clone();
+		map.register_on_item_entities_change(Box::new(move |chunk_pos, entities| {
+			let mut players = playersc.borrow_mut();
+			let msg = ServerToClientMsg::ItemEntitiesUpdated(chunk_pos, entities.to_vec());
+			let mut conns_to_close = Vec::new();
+			for (id, player) in players.iter_mut() {
+				if !player.sent_chunks.contains(&chunk_pos) {
+					continue;
+				}
+				if player.conn.send(msg.clone()).is_err() {
+					conns_to_close.push(*id);
+				}
 			}
 			close_connections(&conns_to_close, &mut *players);
 		}));
 
+		let abm_timer = AbmTimer::new(&params, now);
+		let ticks = TickScheduler::new(config.tps, now);
+		let chat_log = ChatLog::open(config.chat_log_path.as_deref());
 		let srv = Server {
 			srv_socket,
 			params,
@@ -200,49 +437,30 @@ impl<S :NetworkServerSocket> Server<S> {
 			players_waiting_for_kv : HashMap::new(),
 			players,
 
-			last_frame_time : Instant::now(),
-			last_pos_storage_time : Instant::now(),
-			last_fps : 0.0,
+			clock : Box::new(clock),
+			last_pos_storage_time : now,
+			last_map_storage_time : now,
+			last_env_damage_time : now,
+			last_furnace_step : now,
+			time_of_day,
+			last_time_sync : now,
+			ticks,
 			map,
+			liquid_updates : liquid::update_queue(),
+			falling_updates : falling::update_queue(),
+			abm_timer,
+			abm_index,
+			claims,
+			commands : server_commands(),
+			chat_log,
+			console : None,
+			shutdown_requested : false,
+			spawn_pos,
+			spawn_search,
+			pending_teleports : HashMap::new(),
 		};
 		srv
 	}
-	/// Update the stored fps value and return the delta time
-	fn update_fps(&mut self) -> f32 {
-		let cur_time = Instant::now();
-		let float_delta = (cur_time - self.last_frame_time).as_secs_f32();
-		self.last_frame_time = cur_time;
-
-		const EPS :f32 = 0.1;
-		const FPS_TGT :f32 = 60.0;
-		let fps_cur_term = if float_delta > 0.0 {
-			1.0 / float_delta
-		} else {
-			// At the beginning float_delta can be zero
-			// and 1/0 would fuck up the last_fps value.
-			// Also, a value of 0.0 would mean that FPS
-			// limiting couldn't kick in. Thus, set
-			// it to something highly above the target.
-			FPS_TGT * 100.0
-		};
-		let fps = self.last_fps * (1.0 - EPS) + fps_cur_term * EPS;
-		self.last_fps = fps;
-
-		// If we exceed our target FPS by a too high
-		// amount, slow a little bit down
-		// to avoid 100% CPU
-		if fps > 1.5 * FPS_TGT {
-			let smooth_delta = 1.0 / fps;
-			let delta_tgt = 1.0 / FPS_TGT;
-			let time_too_fast = delta_tgt - smooth_delta;
-			// Don't slow down by the full time that we were too fast,
-			// because then we are guaranteed to undershoot
-			// the FPS target in this frame. That's not our goal!
-			let micros_to_wait = (time_too_fast * 0.7 * 1_000_000.0) as u64;
-			thread::sleep(Duration::from_micros(micros_to_wait));
-		}
-		float_delta
-	}
 	fn handle_auth_msgs(&mut self) {
 		// TODO do SRP based auth or spake2 or sth
 		let mut players_to_add = Vec::new();
@@ -423,10 +641,30 @@ impl<S :NetworkServerSocket> Server<S> {
 			self.add_player_waiting(conn, id, nick);
 		}
 	}
+	/// Stores the spawn position once its search is done
+	fn handle_spawn_search(&mut self) {
+		let pos = match self.spawn_search.as_ref().map(|search| search.try_recv()) {
+			Some(Ok(pos)) => pos,
+			Some(Err(TryRecvError::Disconnected)) => PlayerPosition::default(),
+			Some(Err(TryRecvError::Empty)) | None => return,
+		};
+		self.spawn_search = None;
+		self.spawn_pos = pos;
+		match toml::to_string(&pos) {
+			Ok(s) => self.map.set_global_kv("spawn_pos", s.into()),
+			Err(e) => println!("Couldn't store the spawn position: {}", e),
+		}
+	}
 	fn handle_players_waiting_for_kv(&mut self) {
+		if self.spawn_search.is_some() {
+			// New players need the spawn position
+			return;
+		}
 		let mut players_to_add = Vec::new();
+		let spawn_pos = self.spawn_pos();
 		let pwfk = &mut self.players_waiting_for_kv;
 		let nm = &self.params.p.name_id_map;
+		let default_privs = &self.config.default_privs;
 		self.map.run_for_kv_results(&mut |id, _payload, key, value| {
 			if let hash_map::Entry::Occupied(mut kvw) = pwfk.entry(id) {
 				let mut check = false;
@@ -435,10 +673,10 @@ impl<S :NetworkServerSocket> Server<S> {
 					*pos = Some(if let Some(buf) = value {
 						PlayerPosition::deserialize(&buf)
 							.ok()
-							.unwrap_or_else(PlayerPosition::default)
+							.unwrap_or(spawn_pos)
 					} else {
 						// No value could be found
-						PlayerPosition::default()
+						spawn_pos
 					});
 					check = true;
 				} else if key == "inventory" {
@@ -468,12 +706,21 @@ impl<S :NetworkServerSocket> Server<S> {
 					*slow_states = Some(if let Some(buf) = value {
 						toml::from_slice(&buf)
 							.ok()
-							.unwrap_or_else(|| PlayerSlowStates::new(nick))
+							.unwrap_or_else(|| PlayerSlowStates::new(nick, default_privs))
 					} else {
 						// No value could be found
-						PlayerSlowStates::new(nick)
+						PlayerSlowStates::new(nick, default_privs)
 					});
 					check = true;
+				} else if key == "hp" {
+					let KvWaitingPlayer { hp, .. } = kvw.get_mut();
+					*hp = Some(value
+						.and_then(|buf| (&buf[..]).read_u16::<BigEndian>().ok())
+						// Dead players get revived
+						.filter(|hp| *hp > 0)
+						// No value could be found
+						.unwrap_or(MAX_HP));
+					check = true;
 				}
 				if check && kvw.get().ready() {
 					players_to_add.push(kvw.remove());
@@ -488,12 +735,15 @@ impl<S :NetworkServerSocket> Server<S> {
 		let mut msgs = Vec::new();
 		let mut players = self.players.borrow_mut();
 		let mut conns_to_close = Vec::new();
+		let now = self.clock.now();
 		for (id, player) in players.iter_mut() {
+			// Only the latest position gets checked
+			let mut new_pos = None;
 			loop {
 				let msg = player.conn.try_recv();
 				match msg {
 					Ok(Some(ClientToServerMsg::SetPos(p))) => {
-						player.pos = p;
+						new_pos = Some(p);
 					},
 					Ok(Some(msg)) => {
 						msgs.push((*id, msg));
@@ -511,10 +761,48 @@ impl<S :NetworkServerSocket> Server<S> {
 					},
 				}
 			}
+			if let Some(p) = new_pos {
+				if self.handle_player_move(player, p, now).is_err() {
+					conns_to_close.push(*id);
+				}
+			}
 		}
 		close_connections(&conns_to_close, &mut *players);
 		msgs
 	}
+	/// Accepts the position the player has sent if the movement is
+	/// possible, otherwise moves the player back to where it was
+	fn handle_player_move(&self, player :&mut Player<S::Conn>,
+			p :PlayerPosition, now :Instant) -> Result<(), NetErr> {
+		let verdict = if self.is_singleplayer {
+			MoveVerdict::Accept
+		} else {
+			// The fast key of the client works without the fast mode
+			let may_move_fast = self.player_has_priv(player, privs::FAST);
+			player.movement.check(&self.map, &self.params.p,
+				&player.slow_states.modes, may_move_fast, p.pos(), now)
+		};
+		match verdict {
+			MoveVerdict::Accept => {
+				player.track_fall(p.pos().z, now);
+				player.pos = p;
+			},
+			MoveVerdict::Ignore => (),
+			MoveVerdict::Reject(reason) => {
+				println!("Rejected movement of {} to {:?}: {}", player.nick, p.pos(), reason);
+				let violations = player.movement.add_violation(now);
+				if violations % REPEAT_OFFENDER_VIOLATIONS == 0 {
+					println!("Player {} keeps moving illegally ({} violations in a short time)",
+						player.nick, violations);
+				}
+				let pos = player.pos.pos();
+				player.movement.moved_by_server(pos, now);
+				let corrected = PlayerPosition::from_pos_pitch_yaw(pos, p.pitch(), p.yaw());
+				player.conn.send(ServerToClientMsg::SetPos(corrected))?;
+			},
+		}
+		Ok(())
+	}
 	fn send_chunks_to_player(&mut self, player :&mut Player<S::Conn>) -> Result<(), NetErr> {
 		let isize_pos = player.pos().map(|v| v as isize);
 		let (pmin, pmax) = chunk_positions_around(isize_pos,
@@ -562,6 +850,12 @@ impl<S :NetworkServerSocket> Server<S> {
 				self.map.set_player_kv(player.ids, "slow_states", serialized_states);
 				player.slow_states_last_ser = player.slow_states.clone();
 			}
+			if player.hp_last_ser != player.hp {
+				let mut serialized_hp = Vec::new();
+				serialized_hp.write_u16::<BigEndian>(player.hp).unwrap();
+				self.map.set_player_kv(player.ids, "hp", serialized_hp);
+				player.hp_last_ser = player.hp;
+			}
 		}
 		Ok(())
 	}
@@ -570,11 +864,25 @@ impl<S :NetworkServerSocket> Server<S> {
 		// too often as that would mean too much wear on the hdd
 		// and cause massive mapgen thread lag.
 		const INTERVAL_MILLIS :u128 = 1_500;
-		let now = Instant::now();
+		let now = self.clock.now();
 		if (now - self.last_pos_storage_time).as_millis() < INTERVAL_MILLIS {
 			return Ok(());
 		}
 		self.last_pos_storage_time = now;
+		self.store_player_positions_now()
+	}
+	/// Stores the changes of the map that aren't stored right away,
+	/// like those of item entities and furnaces
+	fn store_map_changes(&mut self) {
+		const INTERVAL :Duration = Duration::from_secs(10);
+		let now = self.clock.now();
+		if now.duration_since(self.last_map_storage_time) < INTERVAL {
+			return;
+		}
+		self.last_map_storage_time = now;
+		self.map.store_changes();
+	}
+	fn store_player_positions_now(&mut self) -> Result<()> {
 		let players = self.players.clone();
 		for (_, player) in players.borrow().iter() {
 			let serialized_str = toml::to_string(&player.pos)?;
@@ -618,6 +926,7 @@ impl<S :NetworkServerSocket> Server<S> {
 		self.map.get_player_kv(id, "inventory", PAYLOAD);
 		self.map.get_player_kv(id, "craft_inventory", PAYLOAD);
 		self.map.get_player_kv(id, "slow_states", PAYLOAD);
+		self.map.get_player_kv(id, "hp", PAYLOAD);
 		self.players_waiting_for_kv.insert(id, KvWaitingPlayer::new(conn, id, nick));
 	}
 	fn add_player(&mut self, pl :KvWaitingPlayer<S::Conn>) {
@@ -639,14 +948,28 @@ impl<S :NetworkServerSocket> Server<S> {
 			// TODO get rid of unwrap
 			pl.conn.send(msg).unwrap();
 
-			let modes = pl.slow_states.as_ref().unwrap().modes.clone();
-			let msg = ServerToClientMsg::SetModes(modes);
+			let msg = ServerToClientMsg::SetHp(pl.hp.unwrap());
+			// TODO get rid of unwrap
+			pl.conn.send(msg).unwrap();
+
+			let msg = ServerToClientMsg::SetTimeOfDay(self.time_of_day, self.config.time_speed);
 			// TODO get rid of unwrap
 			pl.conn.send(msg).unwrap();
 
-			let mut players = self.players.borrow_mut();
 			let id = pl.ids;
-			let player = Player::from_waiting(pl);
+			let mut player = Player::from_waiting(pl, &self.config.default_privs, self.clock.now());
+
+			// Drop any modes the player isn't privileged for (any more)
+			let modes = player.slow_states.modes.iter()
+				.filter(|m| self.player_has_priv(&player, privs::priv_for_mode(**m)))
+				.copied()
+				.collect::<HashSet<_>>();
+			player.slow_states.modes = modes.clone();
+			let msg = ServerToClientMsg::SetModes(modes);
+			// TODO get rid of unwrap
+			player.conn.send(msg).unwrap();
+
+			let mut players = self.players.borrow_mut();
 			players.insert(id, player);
 			players.len()
 		};
@@ -654,206 +977,1556 @@ impl<S :NetworkServerSocket> Server<S> {
 		if !self.is_singleplayer {
 			let msg = format!("New player {} joined. Number of players: {}",
 				nick, player_count);
-			self.handle_chat_msg(msg);
+			self.handle_chat_msg(ChatKind::System, msg);
 		}
 	}
-	fn handle_command(&mut self, issuer_id :PlayerIdPair, msg :String) {
-		println!("Command: {}", msg);
-		let mut it = msg[1..].split(" ");
-		let command = it.next().unwrap();
-		let params = it.collect::<Vec<&str>>();
-		match command {
-			"info" => {
-				self.chat_msg_for(issuer_id, format!(
-					"{} {}",
-					env!("CARGO_PKG_NAME"),
-					env!("CARGO_PKG_VERSION")));
+	fn player_has_priv(&self, player :&Player<S::Conn>, pr :&str) -> bool {
+		if self.is_singleplayer {
+			// In singleplayer, the player may do anything
+			return true;
+		}
+		// Nicks are compared like in player_id_by_nick
+		if matches!(&self.config.admin_nick, Some(admin) if admin.eq_ignore_ascii_case(&player.nick)) {
+			return true;
+		}
+		player.slow_states.privs().any(|p| p == pr)
+	}
+	fn has_priv(&self, id :PlayerIdPair, pr :&str) -> bool {
+		if id == console_id() {
+			return true;
+		}
+		let players = self.players.borrow();
+		players.get(&id)
+			.map(|player| self.player_has_priv(player, pr))
+			.unwrap_or(false)
+	}
+	/// Checks whether the player has the given privilege and
+	/// sends the player a message if not
+	fn check_priv(&mut self, id :PlayerIdPair, pr :&str) -> bool {
+		if self.has_priv(id, pr) {
+			return true;
+		}
+		self.chat_msg_for(id, format!("Missing privilege: {}", pr));
+		false
+	}
+	/// The nick of the issuer of a command, which might be the console
+	fn issuer_nick(&self, id :PlayerIdPair) -> String {
+		if id == console_id() {
+			return "console".to_owned();
+		}
+		self.players.borrow()[&id].nick.clone()
+	}
+	fn player_id_by_nick(&self, nick :&str) -> Option<PlayerIdPair> {
+		self.players.borrow().iter()
+			.find(|(_id, player)| player.nick.eq_ignore_ascii_case(nick))
+			.map(|(id, _player)| *id)
+	}
+	/// Looks up the id of a player who is either online
+	/// or known to the auth database
+	fn player_id_by_nick_offline(&mut self, nick :&str) -> Option<PlayerIdPair> {
+		if let Some(id) = self.player_id_by_nick(nick) {
+			return Some(id);
+		}
+		let la = self.auth_back.as_mut()?;
+		la.get_player_id(nick, 1).ok().flatten()
+	}
+	fn player_nick_offline(&mut self, id :PlayerIdPair) -> String {
+		if let Some(player) = self.players.borrow().get(&id) {
+			return player.nick.clone();
+		}
+		self.auth_back.as_mut()
+			.and_then(|la| la.get_player_name(id).ok().flatten())
+			.unwrap_or_else(|| "<unknown>".to_owned())
+	}
+	/// Returns the position of the block the player points at
+	fn pointed_block(&self, id :PlayerIdPair) -> Option<Vector3<isize>> {
+		let pos = self.players.borrow()[&id].pos;
+		let dir = interaction::look_direction(pos.pitch(), pos.yaw());
+		interaction::pointed_block(&self.map, &self.params.p, pos.pos(), dir)
+			.map(|(p, _before)| p)
+	}
+	/// Whether the claims at the position allow the player
+	/// the action of the flag
+	fn claims_allow(&self, id :PlayerIdPair, p :Vector3<isize>, flag :ClaimFlag) -> bool {
+		self.claims.allows(id, p, flag) || self.has_priv(id, privs::PROTECTION)
+	}
+	/// Whether the player may access the chest at the position,
+	/// taking both locks and claims into account
+	fn may_access_chest(&self, id :PlayerIdPair, p :Vector3<isize>) -> bool {
+		if self.has_priv(id, privs::PROTECTION) {
+			return true;
+		}
+		let unlocked = match self.map.get_blk_meta(p) {
+			Some(Some(entry)) => entry.may_access(id),
+			_ => true,
+		};
+		unlocked && self.claims.allows(id, p, ClaimFlag::Chests)
+	}
+	fn save_claims(&mut self) {
+		let serialized = self.claims.serialize();
+		self.map.set_global_kv("claims", serialized);
+	}
+	fn handle_claim_corner_command(&mut self, issuer_id :PlayerIdPair, params :&[&str]) {
+		let pos = self.pointed_block(issuer_id).unwrap_or_else(|| {
+			self.players.borrow()[&issuer_id].pos().map(|v| v.floor() as isize)
+		});
+		let idx = if params[0] == "pos1" { 0 } else { 1 };
+		self.players.borrow_mut().get_mut(&issuer_id).unwrap()
+			.claim_corners[idx] = Some(pos);
+		self.chat_msg_for(issuer_id, format!("Corner {} set to ({}, {}, {})",
+			idx + 1, pos.x, pos.y, pos.z));
+	}
+	fn handle_claim_create_command(&mut self, issuer_id :PlayerIdPair, _params :&[&str]) {
+		let is_admin = self.has_priv(issuer_id, privs::PROTECTION);
+		let corners = self.players.borrow()[&issuer_id].claim_corners;
+		let (c1, c2) = if let [Some(c1), Some(c2)] = corners {
+			(c1, c2)
+		} else {
+			self.chat_msg_for(issuer_id, "Set both corners first with /claim pos1 and /claim pos2");
+			return;
+		};
+		let min = Vector3::new(c1.x.min(c2.x), c1.y.min(c2.y), c1.z.min(c2.z));
+		let max = Vector3::new(c1.x.max(c2.x), c1.y.max(c2.y), c1.z.max(c2.z));
+		if let Err(reason) = check_claim_size(min, max, is_admin) {
+			self.chat_msg_for(issuer_id, reason);
+			return;
+		}
+		let overlaps_others = self.claims.intersecting(min, max).iter()
+			.any(|claim| claim.owner != issuer_id);
+		if !is_admin && overlaps_others {
+			self.chat_msg_for(issuer_id, "The area overlaps with claims of other players");
+			return;
+		}
+		let id = self.claims.add(issuer_id, min, max);
+		self.save_claims();
+		self.players.borrow_mut().get_mut(&issuer_id).unwrap()
+			.claim_corners = [None, None];
+		self.chat_msg_for(issuer_id, format!("Created claim {}", id));
+	}
+	fn handle_claim_list_command(&mut self, issuer_id :PlayerIdPair, _params :&[&str]) {
+		let pos = self.players.borrow()[&issuer_id].pos().map(|v| v.floor() as isize);
+		let listed = self.claims.iter()
+			.filter(|claim| claim.owner == issuer_id || claim.contains(pos))
+			.cloned()
+			.collect::<Vec<_>>();
+		if listed.is_empty() {
+			self.chat_msg_for(issuer_id, "You don't own any claims and aren't inside of one");
+		}
+		for claim in listed {
+			let owner = self.player_nick_offline(claim.owner);
+			let members = claim.members.iter()
+				.map(|id| self.player_nick_offline(*id))
+				.collect::<Vec<_>>();
+			let flags = ClaimFlag::ALL.iter()
+				.filter(|f| claim.has_flag(**f))
+				.map(|f| f.name())
+				.collect::<Vec<_>>();
+			self.chat_msg_for(issuer_id, format!(
+				"Claim {}: ({}, {}, {}) to ({}, {}, {}), owner: {}, members: {}, flags: {}",
+				claim.id, claim.min.x, claim.min.y, claim.min.z,
+				claim.max.x, claim.max.y, claim.max.z,
+				owner, members.join(", "), flags.join(", ")));
+		}
+	}
+	/// Returns the id of the claim if the issuer may change it
+	fn changeable_claim(&mut self, issuer_id :PlayerIdPair, id :&str) -> Option<u32> {
+		let is_admin = self.has_priv(issuer_id, privs::PROTECTION);
+		// The id is a valid number, checked with the params
+		let claim_id = id.parse::<u32>().ok()?;
+		match self.claims.get(claim_id) {
+			Some(claim) if claim.owner == issuer_id || is_admin => Some(claim_id),
+			Some(_) => {
+				self.chat_msg_for(issuer_id, "Only the owner can change the claim");
+				None
 			},
-			"spawn" => {
-				let players = self.players.clone();
-				let msg = ServerToClientMsg::SetPos(PlayerPosition::default());
-				let remove_player = {
-					let player = &players.borrow_mut()[&issuer_id];
-					player.conn.send(msg.clone()).is_err()
-				};
-				if remove_player {
-					close_connections(&[issuer_id], &mut *players.borrow_mut());
-				}
+			None => {
+				self.chat_msg_for(issuer_id, format!("Unknown claim {}", claim_id));
+				None
 			},
-			"gime" => {
-				let content = params.get(0);
-				let content = if let Some(content) = content {
-					if let Some(mb) = self.params.p.search_block_name(*content) {
-						mb
-					} else {
-						self.chat_msg_for(issuer_id, format!("Invalid item {}", content));
-						return;
-					}
-				} else {
-					self.chat_msg_for(issuer_id, "No content to give specified");
-					return;
-				};
-				// TODO print an error if parsing fails. Or maybe not? IDK.
-				let count = params.get(1)
-					.and_then(|v| v.parse().ok())
-					.unwrap_or(1);
-				let content_disp = self.params.p.block_display_name(content);
-				self.chat_msg_for(issuer_id, format!("Giving {} of {}", count, content_disp));
-				let mut players = self.players.borrow_mut();
-				let remove_player = {
-					let player = players.get_mut(&issuer_id).unwrap();
-					player.inventory.put(Stack::with(content, count));
-					let msg = ServerToClientMsg::SetInventory(player.inventory.clone());
-					player.conn.send(msg).is_err()
-				};
-				if remove_player {
-					close_connections(&[issuer_id], &mut *players);
-				}
+		}
+	}
+	fn handle_claim_remove_command(&mut self, issuer_id :PlayerIdPair, params :&[&str]) {
+		let claim_id = if let Some(id) = self.changeable_claim(issuer_id, params[1]) {
+			id
+		} else {
+			return;
+		};
+		self.claims.remove(claim_id);
+		self.save_claims();
+		self.chat_msg_for(issuer_id, format!("Removed claim {}", claim_id));
+	}
+	fn handle_claim_share_command(&mut self, issuer_id :PlayerIdPair, params :&[&str]) {
+		let claim_id = if let Some(id) = self.changeable_claim(issuer_id, params[1]) {
+			id
+		} else {
+			return;
+		};
+		let nick = params[2];
+		let member_id = if let Some(id) = self.player_id_by_nick_offline(nick) {
+			id
+		} else {
+			self.chat_msg_for(issuer_id, format!("Unknown player {}", nick));
+			return;
+		};
+		let claim = self.claims.get_mut(claim_id).unwrap();
+		claim.members.retain(|id| *id != member_id);
+		if params[0] == "share" {
+			claim.members.push(member_id);
+		}
+		self.save_claims();
+		self.chat_msg_for(issuer_id, format!("Updated the members of claim {}", claim_id));
+	}
+	fn handle_claim_flag_command(&mut self, issuer_id :PlayerIdPair, params :&[&str]) {
+		let claim_id = if let Some(id) = self.changeable_claim(issuer_id, params[1]) {
+			id
+		} else {
+			return;
+		};
+		// The flag and the value are valid choices, checked with the params
+		let flag = ClaimFlag::from_name(params[2]).unwrap();
+		let enabled = params[3] == "on";
+		self.claims.get_mut(claim_id).unwrap().set_flag(flag, enabled);
+		self.save_claims();
+		self.chat_msg_for(issuer_id, format!("Updated the flags of claim {}", claim_id));
+	}
+	fn handle_chest_command(&mut self, issuer_id :PlayerIdPair, params :&[&str]) {
+		let p = self.pointed_block(issuer_id);
+		let state = match p.and_then(|p| self.map.get_blk_meta(p)) {
+			Some(Some(MetadataEntry::LockedChest(state))) => state.clone(),
+			_ => {
+				self.chat_msg_for(issuer_id, "You need to point at a locked chest");
+				return;
 			},
-			"clear" => {
-				let to_clear = params.get(0);
-				enum Cmd {
-					Selection,
-					Inventory,
-				}
-				let cmd = match to_clear {
-					Some(&"selection") | Some(&"sel") => {
-						self.chat_msg_for(issuer_id, "Clearing selection");
-						Cmd::Selection
-					},
-					Some(&"inventory") | Some(&"inv") => {
-						self.chat_msg_for(issuer_id, "Clearing inventory");
-						Cmd::Inventory
-					},
-					_ => {
-						self.chat_msg_for(issuer_id, "Invalid clearing command.");
-						return;
-					},
-				};
-
-				let mut players = self.players.borrow_mut();
-				let remove_player = {
-					let player = players.get_mut(&issuer_id).unwrap();
-					match cmd {
-						Cmd::Selection => {
-							let sel = player.inventory.selection();
-							let sel_stack = sel.and_then(|s|player.inventory.stacks_mut().get_mut(s));
-							if let Some(sel_stack) = sel_stack {
-								*sel_stack = Stack::Empty;
-							} else {
-								return;
-							}
-						},
-						Cmd::Inventory => {
-							player.inventory.stacks_mut().iter_mut()
-								.for_each(|i| *i = Stack::Empty);
-						},
-					}
-					let msg = ServerToClientMsg::SetInventory(player.inventory.clone());
-					player.conn.send(msg).is_err()
-				};
-				if remove_player {
-					close_connections(&[issuer_id], &mut *players);
-				}
+		};
+		let p = p.unwrap();
+		let (add, nick) = match (params.get(0), params.get(1)) {
+			(None, _) => {
+				let owner = self.player_nick_offline(state.owner);
+				let members = state.members.iter()
+					.map(|id| self.player_nick_offline(*id))
+					.collect::<Vec<_>>();
+				self.chat_msg_for(issuer_id, format!("Owner: {}, members: {}",
+					owner, members.join(", ")));
+				return;
 			},
+			(Some(&"add"), Some(nick)) => (true, nick),
+			(Some(&"remove"), Some(nick)) => (false, nick),
 			_ => {
-				self.chat_msg_for(issuer_id, format!("Unknown command {}", command));
+				self.chat_msg_for(issuer_id, "Usage: /chest [add|remove <player>]");
+				return;
 			},
+		};
+		if state.owner != issuer_id {
+			self.chat_msg_for(issuer_id, "Only the owner can change the members of the chest");
+			return;
 		}
-	}
-	fn handle_chat_msg(&mut self, msg :String) {
-		println!("Chat: {}", msg);
-		let players = self.players.clone();
-		let mut players_to_remove = Vec::new();
-		for (id, player) in players.borrow_mut().iter_mut() {
-			let msg = ServerToClientMsg::Chat(msg.clone());
-			if player.conn.send(msg).is_err() {
-				players_to_remove.push(*id);
-			}
+		let member_id = if let Some(id) = self.player_id_by_nick_offline(nick) {
+			id
+		} else {
+			self.chat_msg_for(issuer_id, format!("Unknown player {}", nick));
+			return;
+		};
+		let mut state = state;
+		state.members.retain(|id| *id != member_id);
+		if add {
+			state.members.push(member_id);
 		}
-		close_connections(&players_to_remove, &mut *players.borrow_mut());
+		self.map.get_blk_meta_mut(p).unwrap()
+			.set(MetadataEntry::LockedChest(state));
+		let msg = if add {
+			format!("Added {} to the chest's members", nick)
+		} else {
+			format!("Removed {} from the chest's members", nick)
+		};
+		self.chat_msg_for(issuer_id, msg);
 	}
-	fn chat_msg_for(&mut self, for_id :PlayerIdPair, msg :impl Into<String>) {
-		let players = self.players.clone();
-		let mut players_to_remove = Vec::new();
-		let msg = msg.into();
-		for (id, player) in players.borrow_mut().iter_mut() {
-			if *id != for_id {
-				continue;
-			}
-			let msg = ServerToClientMsg::Chat(msg.clone());
-			if player.conn.send(msg).is_err() {
-				players_to_remove.push(*id);
+	fn handle_privs_command(&mut self, issuer_id :PlayerIdPair, params :&[&str]) {
+		let id = if let Some(nick) = params.get(0) {
+			if let Some(id) = self.player_id_by_nick(nick) {
+				id
+			} else {
+				self.chat_msg_for(issuer_id, format!("Player {} is not online", nick));
+				return;
 			}
-		}
-		close_connections(&players_to_remove, &mut *players.borrow_mut());
+		} else if issuer_id == console_id() {
+			self.chat_msg_for(issuer_id, "Usage: /privs <player>");
+			return;
+		} else {
+			issuer_id
+		};
+		let msg = {
+			let players = self.players.borrow();
+			let player = &players[&id];
+			let mut privs = privs::KNOWN_PRIVS.iter()
+				.map(|(n, _desc)| *n)
+				.filter(|p| self.player_has_priv(player, p))
+				.collect::<Vec<_>>();
+			// Also list unknown privileges, e.g. added by
+			// an earlier or a later version of the game
+			privs.extend(player.slow_states.privs()
+				.filter(|p| !privs::is_known_priv(p)));
+			privs.sort();
+			format!("Privileges of {}: {}", player.nick, privs.join(", "))
+		};
+		self.chat_msg_for(issuer_id, msg);
 	}
-	pub fn handle_dig(&mut self, id :PlayerIdPair, p :Vector3<isize>) {
-		let mut remove = true;
-		if let Some(chest_meta) = self.map.get_blk_meta(p) {
-			if let Some(MetadataEntry::Inventory(inv)) = chest_meta {
-				if !inv.is_empty() {
-					remove = false;
+	fn handle_grant_revoke_command(&mut self, issuer_id :PlayerIdPair,
+			params :&[&str], grant :bool) {
+		let (nick, pr) = (params[0], params[1]);
+		let privs_to_change = if pr == "all" {
+			privs::KNOWN_PRIVS.iter()
+				.map(|(n, _desc)| n.to_string())
+				.collect::<Vec<_>>()
+		} else if privs::is_known_priv(pr) || !grant {
+			vec![pr.to_string()]
+		} else {
+			self.chat_msg_for(issuer_id, format!("Unknown privilege {}", pr));
+			return;
+		};
+		let id = if let Some(id) = self.player_id_by_nick(nick) {
+			id
+		} else {
+			self.chat_msg_for(issuer_id, format!("Player {} is not online", nick));
+			return;
+		};
+		let issuer_nick = self.issuer_nick(issuer_id);
+		let (target_nick, modes_changed) = {
+			let mut players = self.players.borrow_mut();
+			let player = players.get_mut(&id).unwrap();
+			let privs = player.slow_states.privs_mut();
+			for pr in privs_to_change.iter() {
+				if grant {
+					privs.insert(pr.clone());
+				} else {
+					privs.remove(pr);
 				}
 			}
+			// Disable any modes the player lost the privilege for
+			let modes = player.slow_states.modes.iter()
+				.filter(|m| self.player_has_priv(player, privs::priv_for_mode(**m)))
+				.copied()
+				.collect::<HashSet<_>>();
+			let modes_changed = modes != player.slow_states.modes;
+			player.slow_states.modes = modes;
+			(player.nick.clone(), modes_changed)
+		};
+		if modes_changed {
+			let modes = self.players.borrow()[&id].slow_states.modes.clone();
+			self.send_msg_to(id, ServerToClientMsg::SetModes(modes));
+		}
+		let privs_str = privs_to_change.join(", ");
+		if grant {
+			self.chat_msg_for(issuer_id, format!("Granted {} to {}", privs_str, target_nick));
+			self.chat_msg_for(id, format!("{} granted you privileges: {}", issuer_nick, privs_str));
 		} else {
-			// TODO log something about an attempted action in an unloaded chunk
-			remove = false;
+			self.chat_msg_for(issuer_id, format!("Revoked {} from {}", privs_str, target_nick));
+			self.chat_msg_for(id, format!("{} revoked your privileges: {}", issuer_nick, privs_str));
 		}
-		let mut drops = None;
-		if remove {
-			{
-				// We can unwrap here as above we set remove to false if
-				// the result is None
-				let mut hdl = self.map.get_blk_mut(p).unwrap();
-				drops = Some(self.params.p.get_block_params(hdl.get()).unwrap().drops);
-				let air_bl = self.params.p.block_roles.air;
-				hdl.set(air_bl);
-			}
-			let mut hdl = self.map.get_blk_meta_mut(p).unwrap();
-			hdl.clear();
+	}
+	fn handle_time_command(&mut self, issuer_id :PlayerIdPair, params :&[&str]) {
+		let time_str = if let Some(time_str) = params.get(0) {
+			time_str
 		} else {
-			// Send the unchanged block to the client
-			if let Some(mut hdl) = self.map.get_blk_mut(p) {
-				hdl.fake_change();
+			let time = time_of_day::format_hhmm(self.time_of_day);
+			self.chat_msg_for(issuer_id, format!("Time of day: {}", time));
+			return;
+		};
+		if !self.check_priv(issuer_id, privs::SETTIME) {
+			return;
+		}
+		let time = if let Some(time) = time_of_day::parse_hhmm(time_str) {
+			time
+		} else {
+			self.chat_msg_for(issuer_id, format!("Invalid time {}, expected HH:MM", time_str));
+			return;
+		};
+		self.time_of_day = time;
+		self.sync_time_of_day();
+		let time = time_of_day::format_hhmm(time);
+		self.chat_msg_for(issuer_id, format!("Time of day set to {}", time));
+	}
+	fn handle_command(&mut self, issuer_id :PlayerIdPair, msg :String) {
+		println!("Command: {}", msg);
+		let (command, rest) = match commands::split_first_words(&msg[1..], 1) {
+			Ok((words, rest)) => (words.into_iter().next(), rest),
+			Err(e) => {
+				self.chat_msg_for(issuer_id, e);
+				return;
+			},
+		};
+		let command = if let Some(command) = command {
+			command
+		} else {
+			self.chat_msg_for(issuer_id, "Empty command. Use /help to list the commands");
+			return;
+		};
+		let (handler, privilege, needs_player, params) = match self.commands.parse(&command, rest) {
+			Some(Ok((cmd, params))) => (cmd.handler, cmd.privilege, cmd.needs_player, params),
+			Some(Err(e)) => {
+				self.chat_msg_for(issuer_id, e);
+				return;
+			},
+			None => {
+				self.chat_msg_for(issuer_id, format!("Unknown command {}. Use /help to list the commands", command));
+				return;
+			},
+		};
+		if needs_player && issuer_id == console_id() {
+			self.chat_msg_for(issuer_id, format!("Only players can use /{}", command));
+			return;
+		}
+		if let Some(pr) = privilege {
+			if !self.check_priv(issuer_id, pr) {
+				return;
 			}
 		}
-		let remove_player = {
-			let mut players = self.players.borrow_mut();
-			let player = &mut players.get_mut(&id).unwrap();
-			if remove {
-				// If we remove the block, put the dropped item
-				// into the inventory. Send the new inventory to
-				// the client in any case to override any
-				// possibly mistaken local prediction.
-				player.inventory.put(drops.unwrap());
+		let params = params.iter().map(|p| p.as_str()).collect::<Vec<_>>();
+		handler(self, issuer_id, &params);
+	}
+	fn may_run_command(&self, id :PlayerIdPair, cmd :&Command<Self>) -> bool {
+		cmd.privilege.map(|pr| self.has_priv(id, pr)).unwrap_or(true)
+	}
+	fn handle_help_command(&mut self, issuer_id :PlayerIdPair, params :&[&str]) {
+		if let Some(name) = params.get(0) {
+			let msgs = self.commands.forms(name)
+				.map(|cmd| {
+					let mut msg = format!("{}: {}", cmd.usage(), cmd.description);
+					if let Some(pr) = cmd.privilege {
+						msg += &format!(" Needs the {} privilege.", pr);
+					}
+					msg
+				})
+				.collect::<Vec<_>>();
+			if msgs.is_empty() {
+				self.chat_msg_for(issuer_id, format!("Unknown command {}", name));
 			}
-			let msg = ServerToClientMsg::SetInventory(player.inventory.clone());
-			player.conn.send(msg).is_err()
-		};
-		if remove_player {
-			close_connections(&[id], &mut *self.players.borrow_mut());
+			for msg in msgs {
+				self.chat_msg_for(issuer_id, msg);
+			}
+			return;
 		}
+		let mut names = self.commands.iter()
+			.filter(|cmd| self.may_run_command(issuer_id, cmd))
+			.map(|cmd| cmd.name)
+			.collect::<Vec<_>>();
+		names.dedup();
+		self.chat_msg_for(issuer_id, format!("Commands: {}. Use /help <command> for details",
+			names.join(", ")));
 	}
-	pub fn handle_inv_move_or_swap(&mut self, id :PlayerIdPair, from_pos :InventoryPos,
-			to_pos :InventoryPos, only_move_one :bool) {
-		// Create a temporary RefCell so that we can have code that
-		// seems to access self.map twice.
-		// Note though that we forbid moving between two different
-		// chests in the map by a manual check, so there won't be
-		// an instance where the RefCell is borrowed twice at the same time.
-		let map_cell = RefCell::new(&mut self.map);
-
-		// Helper macro to save us some repetitive code
-		macro_rules! do_for_inv_ref {
-			($location:expr, $name:ident, $thing:expr) => {
-				if let InventoryLocation::WorldMeta(p) = $location {
+	fn handle_complete_command(&mut self, issuer_id :PlayerIdPair, line :String) {
+		let completions = self.commands.complete(&line,
+			|cmd| self.may_run_command(issuer_id, cmd),
+			|kind| match kind {
+				ArgKind::Player => self.players.borrow().values()
+					.map(|player| player.nick.clone())
+					.collect(),
+				ArgKind::Item => self.params.p.name_id_map.names().to_vec(),
+				ArgKind::Privilege => privs::KNOWN_PRIVS.iter()
+					.map(|(n, _desc)| n.to_string())
+					.collect(),
+				_ => Vec::new(),
+			});
+		self.send_msg_to(issuer_id, ServerToClientMsg::CommandCompletions(line, completions));
+	}
+	fn handle_info_command(&mut self, issuer_id :PlayerIdPair, _params :&[&str]) {
+		self.chat_msg_for(issuer_id, format!(
+			"{} {}",
+			env!("CARGO_PKG_NAME"),
+			env!("CARGO_PKG_VERSION")));
+	}
+	fn spawn_pos(&self) -> PlayerPosition {
+		if let Some([x, y, z]) = self.config.spawn_pos {
+			PlayerPosition::from_pos(Vector3::new(x, y, z))
+		} else {
+			self.spawn_pos
+		}
+	}
+	fn handle_spawn_command(&mut self, issuer_id :PlayerIdPair, _params :&[&str]) {
+		let pos = self.spawn_pos();
+		self.teleport_player(issuer_id, pos);
+	}
+	fn handle_setspawn_command(&mut self, issuer_id :PlayerIdPair, _params :&[&str]) {
+		if self.config.spawn_pos.is_some() {
+			self.chat_msg_for(issuer_id, "The spawn position is set in the server configuration");
+			return;
+		}
+		let pos = self.players.borrow()[&issuer_id].pos;
+		let serialized_str = match toml::to_string(&pos) {
+			Ok(s) => s,
+			Err(e) => {
+				self.chat_msg_for(issuer_id, format!("Couldn't store the spawn position: {}", e));
+				return;
+			},
+		};
+		self.map.set_global_kv("spawn_pos", serialized_str.into());
+		self.spawn_pos = pos;
+		let p = pos.pos();
+		self.chat_msg_for(issuer_id, format!("Spawn set to ({:.1}, {:.1}, {:.1})",
+			p.x, p.y, p.z));
+	}
+	fn handle_tp_pos_command(&mut self, issuer_id :PlayerIdPair, params :&[&str]) {
+		// The coordinates are valid numbers, checked with the params
+		let coords = params.iter()
+			.map(|v| v.parse::<f32>().unwrap_or(0.0))
+			.collect::<Vec<_>>();
+		let pos = Vector3::new(coords[0], coords[1], coords[2])
+			.map(|v| v.clamp(-MAX_TELEPORT_COORD, MAX_TELEPORT_COORD));
+		let player_pos = self.players.borrow()[&issuer_id].pos;
+		let dest = PlayerPosition::from_pos_pitch_yaw(pos,
+			player_pos.pitch(), player_pos.yaw());
+		self.teleport_with_msg(issuer_id, issuer_id, dest);
+	}
+	fn handle_tp_command(&mut self, issuer_id :PlayerIdPair, params :&[&str]) {
+		let (nick, target) = match params {
+			[target] => (None, target),
+			[nick, target] => (Some(nick), target),
+			_ => return,
+		};
+		let id = if let Some(nick) = nick {
+			if !self.check_priv(issuer_id, privs::BRING) {
+				return;
+			}
+			match self.player_id_by_nick(nick) {
+				Some(id) => id,
+				None => {
+					self.chat_msg_for(issuer_id, format!("Player {} is not online", nick));
+					return;
+				},
+			}
+		} else {
+			issuer_id
+		};
+		let dest = match self.player_id_by_nick(target) {
+			Some(target_id) => self.players.borrow()[&target_id].pos,
+			None => {
+				self.chat_msg_for(issuer_id, format!("Player {} is not online", target));
+				return;
+			},
+		};
+		self.teleport_with_msg(issuer_id, id, dest);
+	}
+	fn teleport_with_msg(&mut self, issuer_id :PlayerIdPair, id :PlayerIdPair, dest :PlayerPosition) {
+		let nick = self.players.borrow()[&id].nick.clone();
+		let p = dest.pos();
+		self.chat_msg_for(issuer_id, format!("Teleporting {} to ({:.1}, {:.1}, {:.1})",
+			nick, p.x, p.y, p.z));
+		self.teleport_player(id, dest);
+	}
+	fn handle_sethome_command(&mut self, issuer_id :PlayerIdPair, _params :&[&str]) {
+		{
+			let mut players = self.players.borrow_mut();
+			let player = players.get_mut(&issuer_id).unwrap();
+			player.slow_states.home = Some(player.pos);
+		}
+		self.chat_msg_for(issuer_id, "Home set");
+	}
+	fn handle_home_command(&mut self, issuer_id :PlayerIdPair, _params :&[&str]) {
+		let home = self.players.borrow()[&issuer_id].slow_states.home;
+		if let Some(home) = home {
+			self.teleport_player(issuer_id, home);
+		} else {
+			self.chat_msg_for(issuer_id, "No home set. Set it with /sethome");
+		}
+	}
+	fn handle_msg_command(&mut self, issuer_id :PlayerIdPair, params :&[&str]) {
+		let (nick, text) = (params[0], params[1]);
+		let id = if let Some(id) = self.player_id_by_nick(nick) {
+			id
+		} else {
+			self.chat_msg_for(issuer_id, format!("Player {} is not online", nick));
+			return;
+		};
+		if !self.check_may_chat(issuer_id) {
+			return;
+		}
+		let (issuer_nick, nick) = (self.issuer_nick(issuer_id), self.players.borrow()[&id].nick.clone());
+		self.send_chat_to(id, ChatKind::Private, format!("[{} -> you] {}", issuer_nick, text));
+		self.send_chat_to(issuer_id, ChatKind::Private, format!("[you -> {}] {}", nick, text));
+	}
+	fn handle_who_command(&mut self, issuer_id :PlayerIdPair, _params :&[&str]) {
+		let mut nicks = self.players.borrow().values()
+			.map(|player| player.nick.clone())
+			.collect::<Vec<_>>();
+		nicks.sort();
+		self.chat_msg_for(issuer_id, format!("Online players ({}): {}",
+			nicks.len(), nicks.join(", ")));
+	}
+	fn handle_mute_command(&mut self, issuer_id :PlayerIdPair, params :&[&str], mute :bool) {
+		let id = if let Some(id) = self.player_id_by_nick(params[0]) {
+			id
+		} else {
+			self.chat_msg_for(issuer_id, format!("Player {} is not online", params[0]));
+			return;
+		};
+		let duration = params.get(1).and_then(|d| commands::parse_duration(d));
+		let unix_secs = self.clock.unix_secs();
+		let nick = {
+			let mut players = self.players.borrow_mut();
+			let player = players.get_mut(&id).unwrap();
+			player.slow_states.muted = mute;
+			player.slow_states.muted_until = duration
+				.map(|d| unix_secs.saturating_add(d.as_secs()));
+			player.nick.clone()
+		};
+		let issuer_nick = self.issuer_nick(issuer_id);
+		let msg = match (mute, params.get(1)) {
+			(true, Some(duration)) => format!("{} has been muted by {} for {}",
+				nick, issuer_nick, duration),
+			(true, None) => format!("{} has been muted by {}", nick, issuer_nick),
+			(false, _) => format!("{} has been unmuted by {}", nick, issuer_nick),
+		};
+		println!("{}", msg);
+		self.chat_log.log(self.clock.unix_secs(), &msg);
+		self.chat_msg_for(id, msg.clone());
+		if id != issuer_id {
+			self.chat_msg_for(issuer_id, msg);
+		}
+	}
+	fn handle_say_command(&mut self, issuer_id :PlayerIdPair, params :&[&str]) {
+		let msg = format!("[{}] {}", self.issuer_nick(issuer_id), params[0]);
+		self.handle_chat_msg(ChatKind::System, msg);
+	}
+	fn handle_kick_command(&mut self, issuer_id :PlayerIdPair, params :&[&str]) {
+		let id = if let Some(id) = self.player_id_by_nick(params[0]) {
+			id
+		} else {
+			self.chat_msg_for(issuer_id, format!("Player {} is not online", params[0]));
+			return;
+		};
+		let reason = params.get(1).unwrap_or(&"Kicked").to_string();
+		let nick = self.players.borrow()[&id].nick.clone();
+		// Obtain the nick before the issuer might kick themselves
+		let issuer_nick = self.issuer_nick(issuer_id);
+		self.send_msg_to(id, ServerToClientMsg::Disconnect(reason.clone()));
+		close_connections(&[id], &mut *self.players.borrow_mut());
+		let msg = format!("{} has been kicked by {}: {}", nick, issuer_nick, reason);
+		self.handle_chat_msg(ChatKind::System, msg);
+	}
+	fn handle_shutdown_command(&mut self, issuer_id :PlayerIdPair, _params :&[&str]) {
+		let msg = format!("Server shutting down (requested by {})", self.issuer_nick(issuer_id));
+		self.handle_chat_msg(ChatKind::System, msg);
+		self.shutdown_requested = true;
+	}
+	fn handle_where_command(&mut self, issuer_id :PlayerIdPair, params :&[&str]) {
+		let id = if let Some(nick) = params.get(0) {
+			if !self.check_priv(issuer_id, privs::TELEPORT) {
+				return;
+			}
+			if let Some(id) = self.player_id_by_nick(nick) {
+				id
+			} else {
+				self.chat_msg_for(issuer_id, format!("Player {} is not online", nick));
+				return;
+			}
+		} else if issuer_id == console_id() {
+			self.chat_msg_for(issuer_id, "Usage: /where <player>");
+			return;
+		} else {
+			issuer_id
+		};
+		let msg = {
+			let player = &self.players.borrow()[&id];
+			let p = player.pos();
+			format!("{} is at ({:.1}, {:.1}, {:.1})", player.nick, p.x, p.y, p.z)
+		};
+		self.chat_msg_for(issuer_id, msg);
+	}
+	fn handle_gime_command(&mut self, issuer_id :PlayerIdPair, params :&[&str]) {
+		let content = if let Some(mb) = self.params.p.search_block_name(params[0]) {
+			mb
+		} else {
+			self.chat_msg_for(issuer_id, format!("Invalid item {}", params[0]));
+			return;
+		};
+		let count = match params.get(1).map(|v| v.parse::<u16>()) {
+			None => 1,
+			Some(Ok(count)) if count > 0 => count,
+			Some(_) => {
+				self.chat_msg_for(issuer_id, format!("Invalid count {}", params[1]));
+				return;
+			},
+		};
+		let content_disp = self.params.p.block_display_name(content);
+		self.chat_msg_for(issuer_id, format!("Giving {} of {}", count, content_disp));
+		let (leftover, pos) = {
+			let mut players = self.players.borrow_mut();
+			let player = players.get_mut(&issuer_id).unwrap();
+			let leftover = player.inventory.put(Stack::with(content, count));
+			(leftover, player.pos())
+		};
+		let inv = self.players.borrow()[&issuer_id].inventory.clone();
+		self.send_msg_to(issuer_id, ServerToClientMsg::SetInventory(inv));
+		self.drop_stack(pos, leftover);
+	}
+	fn handle_clear_command(&mut self, issuer_id :PlayerIdPair, params :&[&str]) {
+		let selection = matches!(params[0], "selection" | "sel");
+		if selection {
+			self.chat_msg_for(issuer_id, "Clearing selection");
+		} else {
+			self.chat_msg_for(issuer_id, "Clearing inventory");
+		}
+
+		let mut players = self.players.borrow_mut();
+		let remove_player = {
+			let player = players.get_mut(&issuer_id).unwrap();
+			if selection {
+				let sel = player.inventory.selection();
+				let sel_stack = sel.and_then(|s|player.inventory.stacks_mut().get_mut(s));
+				if let Some(sel_stack) = sel_stack {
+					*sel_stack = Stack::Empty;
+				} else {
+					return;
+				}
+			} else {
+				player.inventory.stacks_mut().iter_mut()
+					.for_each(|i| *i = Stack::Empty);
+			}
+			let msg = ServerToClientMsg::SetInventory(player.inventory.clone());
+			player.conn.send(msg).is_err()
+		};
+		if remove_player {
+			close_connections(&[issuer_id], &mut *players);
+		}
+	}
+	/// Checks whether the player may send a chat message now,
+	/// and sends the player a message if not
+	fn check_may_chat(&mut self, id :PlayerIdPair) -> bool {
+		// At most this many messages per interval
+		const FLOOD_MAX_MSGS :usize = 5;
+		const FLOOD_INTERVAL :Duration = Duration::from_secs(10);
+		if id == console_id() {
+			return true;
+		}
+		let now = self.clock.now();
+		let unix_secs = self.clock.unix_secs();
+		let (muted, flooding) = {
+			let mut players = self.players.borrow_mut();
+			let player = players.get_mut(&id).unwrap();
+			while let Some(time) = player.chat_times.front() {
+				if now.duration_since(*time) < FLOOD_INTERVAL {
+					break;
+				}
+				player.chat_times.pop_front();
+			}
+			let flooding = player.chat_times.len() >= FLOOD_MAX_MSGS;
+			if !flooding {
+				player.chat_times.push_back(now);
+			}
+			(player.slow_states.is_muted(unix_secs), flooding)
+		};
+		if muted {
+			self.chat_msg_for(id, "You are muted");
+		} else if flooding {
+			self.chat_msg_for(id, "You are sending messages too quickly");
+		}
+		!muted && !flooding
+	}
+	fn handle_chat_msg(&mut self, kind :ChatKind, msg :String) {
+		println!("Chat: {}", msg);
+		self.chat_log.log(self.clock.unix_secs(), &msg);
+		let players = self.players.clone();
+		let mut players_to_remove = Vec::new();
+		for (id, player) in players.borrow_mut().iter_mut() {
+			let msg = ServerToClientMsg::Chat(kind, msg.clone());
+			if player.conn.send(msg).is_err() {
+				players_to_remove.push(*id);
+			}
+		}
+		close_connections(&players_to_remove, &mut *players.borrow_mut());
+	}
+	fn send_msg_to_all(&mut self, msg :ServerToClientMsg) {
+		let players = self.players.clone();
+		let mut players_to_remove = Vec::new();
+		for (id, player) in players.borrow_mut().iter_mut() {
+			if player.conn.send(msg.clone()).is_err() {
+				players_to_remove.push(*id);
+			}
+		}
+		close_connections(&players_to_remove, &mut *players.borrow_mut());
+	}
+	fn send_msg_to(&mut self, id :PlayerIdPair, msg :ServerToClientMsg) {
+		let mut players = self.players.borrow_mut();
+		let remove_player = if let Some(player) = players.get(&id) {
+			player.conn.send(msg).is_err()
+		} else {
+			false
+		};
+		if remove_player {
+			close_connections(&[id], &mut *players);
+		}
+	}
+	fn chat_msg_for(&mut self, for_id :PlayerIdPair, msg :impl Into<String>) {
+		self.send_chat_to(for_id, ChatKind::System, msg);
+	}
+	fn send_chat_to(&mut self, for_id :PlayerIdPair, kind :ChatKind, msg :impl Into<String>) {
+		let players = self.players.clone();
+		let mut players_to_remove = Vec::new();
+		let msg = msg.into();
+		if for_id == console_id() {
+			println!("{}", msg);
+			return;
+		}
+		for (id, player) in players.borrow_mut().iter_mut() {
+			if *id != for_id {
+				continue;
+			}
+			let msg = ServerToClientMsg::Chat(kind, msg.clone());
+			if player.conn.send(msg).is_err() {
+				players_to_remove.push(*id);
+			}
+		}
+		close_connections(&players_to_remove, &mut *players.borrow_mut());
+	}
+	/// Checks whether the player is allowed to finish digging
+	/// the block at the given position: the block needs to be in
+	/// reach and diggable with the wielded tool, and enough time
+	/// must have passed since the digging has been started.
+	fn check_dig(&mut self, id :PlayerIdPair, p :Vector3<isize>) -> Result<(), &'static str> {
+		// Allow for some network jitter between the start
+		// and the end messages of the digging.
+		const DIG_TIME_TOLERANCE :f32 = 0.1;
+
+		let blk = if let Some(blk) = self.map.get_blk(p) {
+			blk
+		} else {
+			return Err("unloaded chunk");
+		};
+		if !self.params.p.get_pointability_for_blk(&blk) {
+			return Err("block not pointable");
+		}
+		if !self.claims_allow(id, p, ClaimFlag::Build) {
+			return Err("protected");
+		}
+		let mut players = self.players.borrow_mut();
+		let player = players.get_mut(&id).unwrap();
+		let digging = player.digging.take();
+		if !interaction::in_reach(player.pos(), p) {
+			return Err("out of reach");
+		}
+		let tool = player.inventory.get_selected();
+		let dig_time = interaction::dig_time(&self.params.p, blk, tool)
+			.ok_or("not diggable with wielded tool")?;
+		match digging {
+			Some((dig_pos, dig_start)) if dig_pos == p => {
+				let elapsed = (self.clock.now() - dig_start).as_secs_f32();
+				if elapsed + DIG_TIME_TOLERANCE < dig_time {
+					return Err("dug too fast");
+				}
+			},
+			_ => return Err("digging not started"),
+		}
+		Ok(())
+	}
+	pub fn handle_dig(&mut self, id :PlayerIdPair, p :Vector3<isize>) {
+		let mut remove = true;
+		if let Err(reason) = self.check_dig(id, p) {
+			let nick = &self.players.borrow()[&id].nick;
+			println!("Rejected dig of {} at {:?}: {}", nick, p, reason);
+			remove = false;
+		} else if let Some(chest_meta) = self.map.get_blk_meta(p) {
+			if let Some(inv) = chest_meta.and_then(|entry| entry.inventory()) {
+				if !inv.is_empty() {
+					remove = false;
+				}
+			}
+			if !self.may_access_chest(id, p) {
+				let nick = &self.players.borrow()[&id].nick;
+				println!("Rejected dig of {} at {:?}: protected", nick, p);
+				remove = false;
+			}
+		} else {
+			// TODO log something about an attempted action in an unloaded chunk
+			remove = false;
+		}
+		let mut drops = None;
+		let mut dug_block = None;
+		if remove {
+			{
+				// We can unwrap here as above we set remove to false if
+				// the result is None
+				let mut hdl = self.map.get_blk_mut(p).unwrap();
+				dug_block = Some(hdl.get());
+				drops = Some(self.params.p.get_block_params(hdl.get()).unwrap().drops);
+				let air_bl = self.params.p.block_roles.air;
+				hdl.set(air_bl);
+			}
+			let mut hdl = self.map.get_blk_meta_mut(p).unwrap();
+			hdl.clear();
+			self.on_block_changed(p);
+		} else {
+			// Send the unchanged block to the client
+			if let Some(mut hdl) = self.map.get_blk_mut(p) {
+				hdl.fake_change();
+			}
+		}
+		let mut leftover = Stack::Empty;
+		let remove_player = {
+			let mut players = self.players.borrow_mut();
+			let player = &mut players.get_mut(&id).unwrap();
+			if remove {
+				// If we remove the block, put the dropped item
+				// into the inventory. Send the new inventory to
+				// the client in any case to override any
+				// possibly mistaken local prediction.
+				if let Some(blk) = dug_block {
+					add_tool_wear(&self.params, &mut player.inventory, blk);
+				}
+				leftover = player.inventory.put(drops.unwrap());
+			}
+			let msg = ServerToClientMsg::SetInventory(player.inventory.clone());
+			player.conn.send(msg).is_err()
+		};
+		if remove_player {
+			close_connections(&[id], &mut *self.players.borrow_mut());
+		}
+		// Whatever didn't fit into the inventory gets dropped
+		self.drop_stack(p.map(|v| v as f32 + 0.5), leftover);
+	}
+	/// Spawns an item entity for the stack at the given position
+	///
+	/// The entity falls down until it lies on a solid block.
+	fn drop_stack(&mut self, pos :Vector3<f32>, stack :Stack) {
+		if stack.is_empty() {
+			return;
+		}
+		let mut blk_pos = pos.map(|v| v.floor() as isize);
+		for _ in 0 .. CHUNKSIZE {
+			let below = blk_pos - Vector3::z();
+			let below_solid = self.map.get_blk(below)
+				.and_then(|blk| self.params.p.get_block_params(blk))
+				.map(|bp| bp.solid)
+				.unwrap_or(true);
+			if below_solid {
+				break;
+			}
+			blk_pos = below;
+		}
+		let entity = ItemEntity {
+			pos : Vector3::new(pos.x, pos.y, blk_pos.z as f32),
+			stack,
+			spawned_at : self.clock.unix_secs(),
+		};
+		if let Some(entity) = self.map.add_item_entity(entity) {
+			println!("Lost item stack {:?} dropped at {:?}: chunk not loaded or full",
+				entity.stack, entity.pos);
+		}
+	}
+	/// Lets players pick up item entities close to them,
+	/// and removes item entities that have exceeded their lifetime
+	fn handle_item_entities(&mut self) {
+		// How close the body of the player needs to be to pick up an item
+		const PICKUP_RANGE :f32 = 1.5;
+		// Seconds before a freshly dropped item can be picked up,
+		// so that dropping items doesn't pick them up right away
+		const PICKUP_DELAY :u64 = 2;
+		let now = self.clock.unix_secs();
+		let lifetime = self.config.item_entity_lifetime;
+		let player_infos = self.players.borrow().iter()
+			.map(|(id, player)| (*id, player.pos(), player.inventory.clone()))
+			.collect::<Vec<_>>();
+		for (id, pos, mut inv) in player_infos {
+			let body_pos = pos - Vector3::new(0.0, 0.0, 0.9);
+			let chunk_pos = btchn(pos.map(|v| v.floor() as isize));
+			let mut inv_changed = false;
+			for x in -1 ..= 1 {
+				for y in -1 ..= 1 {
+					for z in -1 ..= 1 {
+						let cp = chunk_pos + Vector3::new(x, y, z) * CHUNKSIZE;
+						self.map.update_item_entities(cp, |ent| {
+							if now >= ent.spawned_at + lifetime {
+								return false;
+							}
+							if now < ent.spawned_at + PICKUP_DELAY ||
+									(ent.pos - body_pos).norm() > PICKUP_RANGE {
+								return true;
+							}
+							let leftover = inv.put(ent.stack);
+							inv_changed |= leftover != ent.stack;
+							ent.stack = leftover;
+							!leftover.is_empty()
+						});
+					}
+				}
+			}
+			if inv_changed {
+				if let Some(player) = self.players.borrow_mut().get_mut(&id) {
+					player.inventory = inv.clone();
+				}
+				self.send_msg_to(id, ServerToClientMsg::SetInventory(inv));
+			}
+		}
+	}
+	/// Queues updates of the block at the given position
+	/// and its neighbours after it has changed
+	fn on_block_changed(&mut self, pos :Vector3<isize>) {
+		self.liquid_updates.push_with_neighbours(pos);
+		self.falling_updates.push_with_neighbours(pos);
+	}
+	fn set_block(&mut self, pos :Vector3<isize>, blk :MapBlock) {
+		if let Some(mut hdl) = self.map.get_blk_mut(pos) {
+			hdl.set(blk);
+			self.on_block_changed(pos);
+		}
+	}
+	fn handle_block_updates(&mut self) {
+		let now = self.clock.now();
+		for pos in self.liquid_updates.take_due(now) {
+			let claims = &self.claims;
+			let may_flow = |from, to| claims.may_spread(from, to);
+			if let Some(blk) = liquid::update(&self.map, &self.params, pos, may_flow) {
+				self.set_block(pos, blk);
+			}
+		}
+		for pos in self.falling_updates.take_due(now) {
+			let claims = &self.claims;
+			let may_fall = |from, to| claims.may_spread(from, to);
+			for (pos, blk) in falling::update(&self.map, &self.params, pos, may_fall) {
+				self.set_block(pos, blk);
+			}
+		}
+	}
+	/// Runs the active block modifiers that are due
+	/// on the chunks near players
+	fn handle_abms(&mut self) {
+		// Radius of the area around players where modifiers are active
+		const ACTIVE_RADIUS_XY :isize = 2;
+		const ACTIVE_RADIUS_Z :isize = 1;
+		let params = self.params.clone();
+		let time = self.time_of_day;
+		let due = self.abm_timer.take_due(&params, self.clock.now()).into_iter()
+			.filter(|idx| params.abms[*idx].time_range
+				.map(|(from, to)| time_of_day::in_range(time, from, to))
+				.unwrap_or(true))
+			.collect::<Vec<_>>();
+		if due.is_empty() {
+			return;
+		}
+		let mut chunks = HashSet::new();
+		for (_id, player) in self.players.borrow().iter() {
+			let pos = player.pos.pos().map(|v| v.floor() as isize);
+			let (pmin, pmax) = chunk_positions_around(pos,
+				ACTIVE_RADIUS_XY, ACTIVE_RADIUS_Z);
+			let pmin = pmin / CHUNKSIZE;
+			let pmax = pmax / CHUNKSIZE;
+			for x in pmin.x ..= pmax.x {
+				for y in pmin.y ..= pmax.y {
+					for z in pmin.z ..= pmax.z {
+						chunks.insert(Vector3::new(x, y, z) * CHUNKSIZE);
+					}
+				}
+			}
+		}
+		self.abm_index.borrow_mut().retain(&chunks);
+		let chunks = chunks.into_iter().collect::<Vec<_>>();
+		for idx in due {
+			let abm = &params.abms[idx];
+			let matches = abm::find_matches(&self.map, &params,
+				&mut self.abm_index.borrow_mut(), idx, &chunks);
+			for pos in matches {
+				match &abm.action {
+					AbmAction::Replace(blk) => self.set_block(pos, *blk),
+					AbmAction::Schematic(schematic) => {
+						// Don't grow into claims from outside
+						let crosses_claims = self.claims
+							.intersecting(pos + schematic.aabb_min, pos + schematic.aabb_max)
+							.iter()
+							.any(|claim| !claim.contains(pos));
+						if crosses_claims {
+							continue;
+						}
+						map::spawn_schematic(&mut self.map, pos, schematic);
+						for (bpos, _) in schematic.items.iter() {
+							self.on_block_changed(pos + bpos);
+						}
+					},
+				}
+			}
+		}
+	}
+	/// Advances the time of day and regularly
+	/// sends it to the clients and stores it
+	fn handle_time_of_day(&mut self, float_delta :f32) {
+		// Clients advance the time of day on their own,
+		// so it only needs to be synced once in a while.
+		const SYNC_INTERVAL :Duration = Duration::from_secs(10);
+		self.time_of_day = time_of_day::advance(self.time_of_day,
+			float_delta, self.config.time_speed);
+		if self.clock.now() - self.last_time_sync >= SYNC_INTERVAL {
+			self.sync_time_of_day();
+		}
+	}
+	fn sync_time_of_day(&mut self) {
+		self.last_time_sync = self.clock.now();
+		let msg = ServerToClientMsg::SetTimeOfDay(self.time_of_day, self.config.time_speed);
+		self.send_msg_to_all(msg);
+		let serialized = map_storage::serialize_time_of_day(self.time_of_day);
+		self.map.set_global_kv("time_of_day", serialized);
+	}
+	/// Cooks the items in all loaded furnaces
+	fn handle_furnaces(&mut self) {
+		let now = self.clock.now();
+		let secs = now - self.last_furnace_step;
+		if secs < furnace::STEP_INTERVAL {
+			return;
+		}
+		self.last_furnace_step = now;
+		let secs = secs.as_secs_f32();
+		let params = self.params.clone();
+		let changed = self.map.update_metadata(|_pos, entry| {
+			if let MetadataEntry::Furnace(state) = entry {
+				furnace::step(&params, state, secs)
+			} else {
+				false
+			}
+		});
+		// Only the players viewing the furnaces need to know
+		for p in changed {
+			self.send_chest_meta_to_viewers(p);
+		}
+	}
+	/// Sends the chest or furnace to the players that have it open
+	fn send_chest_meta_to_viewers(&mut self, p :Vector3<isize>) {
+		let meta = self.map.get_blk_meta(p).flatten().cloned();
+		let mut players = self.players.borrow_mut();
+		let mut conns_to_close = Vec::new();
+		for (id, player) in players.iter_mut() {
+			if !player.open_chests.contains(&p) {
+				continue;
+			}
+			let msg = ServerToClientMsg::SetChestMeta(p, meta.clone());
+			if player.conn.send(msg).is_err() {
+				conns_to_close.push(*id);
+			}
+		}
+		close_connections(&conns_to_close, &mut *players);
+	}
+	/// Moves the player to the given position
+	fn set_player_pos(&mut self, id :PlayerIdPair, pos :PlayerPosition) {
+		let now = self.clock.now();
+		if let Some(player) = self.players.borrow_mut().get_mut(&id) {
+			player.pos = pos;
+			player.movement.moved_by_server(pos.pos(), now);
+			player.fall_start_z = None;
+			// Ignore fall damage from position updates
+			// the client has sent before it got teleported
+			player.fall_grace_until = now + Duration::from_secs(1);
+		}
+		self.send_msg_to(id, ServerToClientMsg::SetPos(pos));
+	}
+	/// Moves the player to the position once
+	/// the map around it has been generated
+	fn teleport_player(&mut self, id :PlayerIdPair, pos :PlayerPosition) {
+		gen_chunks_around(&mut self.map,
+			pos.pos().map(|v| v.floor() as isize),
+			self.config.mapgen_radius_xy,
+			self.config.mapgen_radius_z);
+		self.pending_teleports.insert(id, pos);
+		self.handle_pending_teleports();
+	}
+	/// Completes the teleports whose destination has been generated
+	fn handle_pending_teleports(&mut self) {
+		let players = self.players.clone();
+		self.pending_teleports.retain(|id, _pos| players.borrow().contains_key(id));
+		let ready = self.pending_teleports.iter()
+			.filter(|(_id, pos)| self.map.get_blk(pos.pos().map(|v| v.floor() as isize)).is_some())
+			.map(|(id, pos)| (*id, *pos))
+			.collect::<Vec<_>>();
+		for (id, pos) in ready {
+			self.pending_teleports.remove(&id);
+			self.set_player_pos(id, pos);
+		}
+	}
+	fn block_params_at(&self, pos :Vector3<f32>) -> Option<&BlockParams> {
+		self.map.get_blk(pos.map(|v| v.floor() as isize))
+			.and_then(|blk| self.params.p.get_block_params(blk))
+	}
+	/// Whether there is a liquid source or flowing liquid at the position
+	fn liquid_at(&self, pos :Vector3<f32>) -> bool {
+		self.map.get_blk(pos.map(|v| v.floor() as isize))
+			.and_then(|blk| self.params.get_liquid(blk))
+			.is_some()
+	}
+	/// Handles fall damage, drowning and damaging blocks
+	fn handle_health(&mut self) {
+		// Falls up to this distance don't cause damage
+		const SAFE_FALL_DISTANCE :f32 = 4.0;
+		let falling_players = self.players.borrow().iter()
+			.filter_map(|(id, player)| {
+				let flying = player.slow_states.modes.contains(&PlayerMode::Fly);
+				player.fall_start_z.map(|start| (*id, start, player.pos(), flying))
+			})
+			.collect::<Vec<_>>();
+		for (id, fall_start_z, pos, flying) in falling_players {
+			let feet_pos = pos - Vector3::new(0.0, 0.0, PLAYER_EYE_HEIGHT);
+			let in_water = self.liquid_at(feet_pos);
+			let climbing = self.block_params_at(feet_pos)
+				.map(|bp| bp.climbable)
+				.unwrap_or(false);
+			let on_ground = self.block_params_at(feet_pos - Vector3::new(0.0, 0.0, 0.1))
+				.map(|bp| bp.solid)
+				.unwrap_or(false);
+			if !(flying || in_water || climbing || on_ground) {
+				continue;
+			}
+			// Earlier deaths may have closed the connections of other players
+			if let Some(player) = self.players.borrow_mut().get_mut(&id) {
+				player.fall_start_z = None;
+			} else {
+				continue;
+			}
+			let distance = fall_start_z - pos.z;
+			if on_ground && !flying && distance > SAFE_FALL_DISTANCE {
+				let damage = (distance - SAFE_FALL_DISTANCE).ceil() as u16;
+				self.damage_player(id, damage, "fell from a high place");
+			}
+		}
+
+		// Environmental damage is applied each second
+		let now = self.clock.now();
+		if now - self.last_env_damage_time < Duration::from_secs(1) {
+			return;
+		}
+		self.last_env_damage_time = now;
+		let players = self.players.borrow().iter()
+			.map(|(id, player)| (*id, player.pos()))
+			.collect::<Vec<_>>();
+		for (id, pos) in players {
+			let head_in_water = self.liquid_at(pos);
+			let drowning = {
+				let mut players = self.players.borrow_mut();
+				let player = if let Some(player) = players.get_mut(&id) {
+					player
+				} else {
+					continue;
+				};
+				if !head_in_water {
+					player.breath = MAX_BREATH;
+					false
+				} else if player.breath > 0 {
+					player.breath -= 1;
+					false
+				} else {
+					true
+				}
+			};
+			if drowning {
+				self.damage_player(id, 1, "drowned");
+			}
+			let feet_pos = pos - Vector3::new(0.0, 0.0, PLAYER_EYE_HEIGHT - 0.5);
+			let block_damage = [pos, feet_pos].iter()
+				.filter_map(|p| self.block_params_at(*p))
+				.map(|bp| bp.damage_per_second)
+				.max()
+				.unwrap_or(0);
+			self.damage_player(id, block_damage, "was hurt by a block");
+		}
+	}
+	fn damage_player(&mut self, id :PlayerIdPair, damage :u16, cause :&str) {
+		if damage == 0 {
+			return;
+		}
+		let hp = {
+			let mut players = self.players.borrow_mut();
+			let player = if let Some(player) = players.get_mut(&id) {
+				player
+			} else {
+				return;
+			};
+			player.hp = player.hp.saturating_sub(damage);
+			player.hp
+		};
+		if hp == 0 {
+			self.kill_player(id, cause);
+		} else {
+			self.send_msg_to(id, ServerToClientMsg::SetHp(hp));
+		}
+	}
+	/// Drops the inventory of the player at the place of
+	/// death and respawns the player at their bed or at spawn
+	fn kill_player(&mut self, id :PlayerIdPair, cause :&str) {
+		let (nick, pos, stacks, bed) = {
+			let mut players = self.players.borrow_mut();
+			let player = if let Some(player) = players.get_mut(&id) {
+				player
+			} else {
+				return;
+			};
+			let stacks = player.inventory.stacks_mut().iter_mut()
+				.map(|st| std::mem::replace(st, Stack::Empty))
+				.collect::<Vec<_>>();
+			player.hp = MAX_HP;
+			player.breath = MAX_BREATH;
+			(player.nick.clone(), player.pos(), stacks, player.slow_states.bed)
+		};
+		for stack in stacks {
+			self.drop_stack(pos, stack);
+		}
+		let inv = self.players.borrow().get(&id).map(|player| player.inventory.clone());
+		if let Some(inv) = inv {
+			self.send_msg_to(id, ServerToClientMsg::SetInventory(inv));
+		}
+		self.send_msg_to(id, ServerToClientMsg::SetHp(MAX_HP));
+
+		// The bed might have been removed in the meantime.
+		// If its chunk isn't loaded, assume it's still there.
+		let bed = bed.filter(|p| match self.map.get_blk(*p) {
+			Some(blk) => self.params.p.get_block_params(blk)
+				.map(|bp| bp.bed)
+				.unwrap_or(false),
+			None => true,
+		});
+		let respawn_pos = if let Some(bed) = bed {
+			let pos = bed.map(|v| v as f32) +
+				Vector3::new(0.5, 0.5, 1.0 + PLAYER_EYE_HEIGHT);
+			PlayerPosition::from_pos(pos)
+		} else {
+			self.spawn_pos()
+		};
+		self.teleport_player(id, respawn_pos);
+		self.handle_chat_msg(ChatKind::System, format!("{} {}.", nick, cause));
+	}
+	fn handle_drop_selected(&mut self, id :PlayerIdPair) {
+		let (stack, pos) = {
+			let mut players = self.players.borrow_mut();
+			let player = players.get_mut(&id).unwrap();
+			let stack = if let Some(sel_idx) = player.inventory.selection() {
+				std::mem::replace(&mut player.inventory.stacks_mut()[sel_idx], Stack::Empty)
+			} else {
+				Stack::Empty
+			};
+			// Drop the stack in front of the player
+			let yaw = (-player.pos.yaw()).to_radians();
+			let pos = player.pos() + Vector3::new(yaw.cos(), yaw.sin(), 0.0) * 1.5;
+			let pos = if self.map.get_blk(pos.map(|v| v.floor() as isize))
+					.and_then(|blk| self.params.p.get_block_params(blk))
+					.map(|bp| bp.solid)
+					.unwrap_or(true) {
+				player.pos()
+			} else {
+				pos
+			};
+			(stack, pos)
+		};
+		let inv = self.players.borrow()[&id].inventory.clone();
+		self.send_msg_to(id, ServerToClientMsg::SetInventory(inv));
+		self.drop_stack(pos, stack);
+	}
+	/// Checks whether the player is allowed to place the
+	/// given block at the given position: the selection must
+	/// match, the position must be in reach, the block at
+	/// the position must be air or buildable_to, and the
+	/// placed block must not overlap with any player.
+	fn check_place(&self, id :PlayerIdPair, p :Vector3<isize>,
+			sel_idx :usize, b :MapBlock) -> Result<(), &'static str> {
+		if !self.claims_allow(id, p, ClaimFlag::Build) {
+			return Err("protected");
+		}
+		let players = self.players.borrow();
+		let player = &players[&id];
+		let sel = player.inventory.get_sel_idx_and_content();
+		if Some((sel_idx, b)) != sel {
+			return Err("selected inventory mismatch");
+		}
+		let params = self.params.p.get_block_params(b)
+			.ok_or("unknown block")?;
+		if !params.placeable {
+			return Err("block not placeable");
+		}
+		let blk = self.map.get_blk(p)
+			.ok_or("unloaded chunk")?;
+		let buildable_to = blk == self.params.p.block_roles.air ||
+			self.params.p.get_block_params(blk)
+				.map(|p| p.buildable_to)
+				.unwrap_or(false);
+		if !buildable_to {
+			return Err("position occupied");
+		}
+		if !interaction::in_reach(player.pos(), p) {
+			return Err("out of reach");
+		}
+		if params.solid {
+			let overlaps_player = players.values()
+				.any(|pl| interaction::player_overlaps_block(pl.pos(), p));
+			if overlaps_player {
+				return Err("overlaps with player");
+			}
+		}
+		Ok(())
+	}
+	/// Overrides the client's prediction of a failed placement
+	fn reject_place(&mut self, id :PlayerIdPair, p :Vector3<isize>, reason :&str) {
+		let nick = self.players.borrow()[&id].nick.clone();
+		println!("Rejected placement of {} at {:?}: {}", nick, p, reason);
+		// Send the unchanged block to the client
+		if let Some(mut hdl) = self.map.get_blk_mut(p) {
+			hdl.fake_change();
+		}
+		let inv = self.players.borrow()[&id].inventory.clone();
+		self.send_msg_to(id, ServerToClientMsg::SetInventory(inv));
+	}
+	/// Overrides the client's prediction of an inventory
+	/// move that has been rejected
+	fn reject_inv_move(&mut self, id :PlayerIdPair, p :Vector3<isize>) {
+		// Send the unchanged chest to the client
+		if let Some(mut hdl) = self.map.get_blk_mut(p) {
+			hdl.fake_change();
+		}
+		let inv = self.players.borrow()[&id].inventory.clone();
+		self.send_msg_to(id, ServerToClientMsg::SetInventory(inv));
+	}
+	/// Rejects a move involving the output of the furnace at the position,
+	/// also sending the chest at the other end of the move if there is one
+	fn reject_furnace_move(&mut self, id :PlayerIdPair, p :Vector3<isize>,
+			other_pos :&InventoryPos) {
+		if let InventoryLocation::WorldMeta(other_p) = other_pos.location {
+			if other_p != p {
+				self.send_chest_meta_to_viewers(other_p);
+			}
+		}
+		self.reject_inv_move(id, p);
+	}
+	pub fn handle_place(&mut self, id :PlayerIdPair, p :Vector3<isize>,
+			sel_idx :usize, b :MapBlock, facedir :Facedir) {
+		if let Err(reason) = self.check_place(id, p, sel_idx, b) {
+			self.reject_place(id, p, reason);
+			return;
+		}
+		self.players.borrow_mut().get_mut(&id).unwrap()
+			.inventory.take_selected();
+		// Don't send anything to the client, its
+		// prediction was alright.
+
+		let facedir = if self.params.p.get_block_params(b).unwrap().facedir {
+			facedir
+		} else {
+			Facedir::default()
+		};
+		// We can unwrap here as check_place made sure
+		// that the chunk is loaded
+		self.map.get_blk_mut(p).unwrap().set_with_facedir(b, facedir);
+		self.on_block_changed(p);
+		if self.params.p.get_block_params(b).unwrap().bed {
+			self.players.borrow_mut().get_mut(&id).unwrap()
+				.slow_states.bed = Some(p);
+			self.chat_msg_for(id, "Bed set as respawn position");
+		}
+		let mut hdl = self.map.get_blk_meta_mut(p).unwrap();
+		let bp = self.params.p.get_block_params(b).unwrap();
+		if bp.furnace {
+			hdl.set(MetadataEntry::Furnace(FurnaceState::default()));
+		} else if let (Some(stack_num), true) = (bp.inventory, bp.locked) {
+			let inv = SelectableInventory::empty_with_size(stack_num as usize);
+			hdl.set(MetadataEntry::LockedChest(LockedChestState {
+				inv,
+				owner : id,
+				members : Vec::new(),
+			}));
+		} else if let Some(stack_num) = bp.inventory {
+			let inv = SelectableInventory::empty_with_size(stack_num as usize);
+			hdl.set(MetadataEntry::Inventory(inv));
+		} else if bp.sign {
+			hdl.set(MetadataEntry::Text(String::new()));
+		} else if hdl.get().is_some() {
+			// Remove any metadata of the replaced block
+			hdl.clear();
+		}
+	}
+	pub fn handle_set_sign_text(&mut self, id :PlayerIdPair, p :Vector3<isize>, text :String) {
+		let reason = if text.chars().count() > SIGN_TEXT_MAX_LEN {
+			Some("text too long")
+		} else if !self.has_priv(id, privs::SIGN) {
+			Some("missing privilege")
+		} else if !interaction::in_reach(self.players.borrow()[&id].pos(), p) {
+			Some("out of reach")
+		} else if !self.claims_allow(id, p, ClaimFlag::Build) {
+			Some("protected")
+		} else if !matches!(self.map.get_blk_meta(p), Some(Some(MetadataEntry::Text(_)))) {
+			Some("not a sign")
+		} else {
+			None
+		};
+		if let Some(reason) = reason {
+			let nick = self.players.borrow()[&id].nick.clone();
+			println!("Rejected sign text of {} at {:?}: {}", nick, p, reason);
+			// Send the unchanged sign to the client
+			if let Some(mut hdl) = self.map.get_blk_mut(p) {
+				hdl.fake_change();
+			}
+			self.chat_msg_for(id, format!("Can't set the sign text: {}", reason));
+			return;
+		}
+		self.map.get_blk_meta_mut(p).unwrap()
+			.set(MetadataEntry::Text(text));
+	}
+	pub fn handle_inv_move_or_swap(&mut self, id :PlayerIdPair, from_pos :InventoryPos,
+			to_pos :InventoryPos, only_move_one :bool) {
+		for location in [from_pos.location, to_pos.location].iter() {
+			if let InventoryLocation::WorldMeta(p) = location {
+				let is_open = self.players.borrow()[&id].open_chests.contains(p);
+				let reason = if !is_open {
+					"chest not opened"
+				} else if !self.may_access_chest(id, *p) {
+					"protected"
+				} else {
+					continue;
+				};
+				let nick = self.players.borrow()[&id].nick.clone();
+				println!("Rejected move of {} in chest at {:?}: {}", nick, p, reason);
+				self.reject_inv_move(id, *p);
+				return;
+			}
+		}
+		if let Some(p) = self.furnace_output_at(&to_pos) {
+			let nick = self.players.borrow()[&id].nick.clone();
+			println!("Rejected move of {} into furnace output at {:?}", nick, p);
+			self.reject_furnace_move(id, p, &from_pos);
+			return;
+		}
+		if let Some(p) = self.furnace_output_at(&from_pos) {
+			// Taking from the output may not swap something into it
+			let stacks = (self.inv_stack(id, &from_pos), self.inv_stack(id, &to_pos));
+			let moves = match stacks {
+				(Some(from), Some(to)) => inventory::moves_without_swap(from, to),
+				_ => false,
+			};
+			if !moves {
+				let nick = self.players.borrow()[&id].nick.clone();
+				println!("Rejected swap of {} with furnace output at {:?}", nick, p);
+				self.reject_furnace_move(id, p, &to_pos);
+				return;
+			}
+		}
+		if let (InventoryLocation::WorldMeta(from_p), InventoryLocation::WorldMeta(to_p)) =
+				(from_pos.location, to_pos.location) {
+			if from_p != to_p {
+				self.handle_chest_to_chest_move(id, from_p, to_p,
+					from_pos.stack_pos, to_pos.stack_pos, only_move_one);
+				return;
+			}
+		}
+		// Create a temporary RefCell so that we can have code that
+		// seems to access self.map twice.
+		// Note though that moves between two different chests
+		// in the map are handled separately above, so there won't be
+		// an instance where the RefCell is borrowed twice at the same time.
+		let map_cell = RefCell::new(&mut self.map);
+
+		// Helper macro to save us some repetitive code
+		macro_rules! do_for_inv_ref {
+			($location:expr, $name:ident, $thing:expr) => {
+				if let InventoryLocation::WorldMeta(p) = $location {
 					// Move between two locations inside the chest
 					if let Some(mut hdl) = map_cell.borrow_mut().get_blk_meta_mut(p) {
-						if let Some(MetadataEntry::Inventory(inv)) = hdl.get().clone() {
-							let mut $name = inv.clone();
+						if let Some(mut entry) = hdl.get().cloned() {
+							let mut $name = if let Some(inv) = entry.inventory() {
+								inv.clone()
+							} else {
+								// TODO log something about metadata without inventory
+								return;
+							};
 							let invs = $thing;
-							hdl.set(MetadataEntry::Inventory(invs.0));
+							*entry.inventory_mut().unwrap() = invs.0;
+							hdl.set(entry);
 							invs.1
 						} else {
 							// TODO log something about no metadata present
@@ -922,11 +2595,6 @@ impl<S :NetworkServerSocket> Server<S> {
 				(inv, ())
 			});
 		} else {
-			if from_pos.location.is_world_meta() && to_pos.location.is_world_meta() {
-				// TODO log something about attempted move between two chests.
-				// For now, this is unsupported (and would panic anyways due to the RefCell).
-				return;
-			}
 			// Move between different inventories
 			let (from, to) = ((0, from_pos.stack_pos), (1, to_pos.stack_pos));
 			do_for_inv_ref!(from_pos.location, inv_from, {
@@ -942,27 +2610,145 @@ impl<S :NetworkServerSocket> Server<S> {
 		}
 	}
 
-	pub fn handle_craft(&mut self, id :PlayerIdPair) {
-		let mut players = self.players.borrow_mut();
-		let player = &mut players.get_mut(&id).unwrap();
+	/// Returns the position of the furnace if the
+	/// inventory position is the output slot of one
+	fn furnace_output_at(&self, pos :&InventoryPos) -> Option<Vector3<isize>> {
+		if let InventoryLocation::WorldMeta(p) = pos.location {
+			let is_furnace = matches!(self.map.get_blk_meta(p),
+				Some(Some(MetadataEntry::Furnace(_))));
+			if is_furnace && pos.stack_pos == FURNACE_OUTPUT_SLOT {
+				return Some(p);
+			}
+		}
+		None
+	}
+	/// Returns the stack at the inventory position,
+	/// if the position exists
+	fn inv_stack(&self, id :PlayerIdPair, pos :&InventoryPos) -> Option<Stack> {
+		let stacks = |inv :&SelectableInventory| inv.stacks().get(pos.stack_pos).copied();
+		match pos.location {
+			InventoryLocation::WorldMeta(p) => match self.map.get_blk_meta(p) {
+				Some(Some(entry)) => entry.inventory().and_then(stacks),
+				_ => None,
+			},
+			InventoryLocation::PlayerInv => stacks(&self.players.borrow()[&id].inventory),
+			InventoryLocation::CraftInv => stacks(&self.players.borrow()[&id].craft_inventory),
+		}
+	}
+	/// Moves between two different chests, changing
+	/// either both of them or none
+	fn handle_chest_to_chest_move(&mut self, id :PlayerIdPair,
+			from_p :Vector3<isize>, to_p :Vector3<isize>,
+			from_stack :usize, to_stack :usize, only_move_one :bool) {
+		let entries = (self.map.get_blk_meta(from_p), self.map.get_blk_meta(to_p));
+		let (mut from_entry, mut to_entry) = match entries {
+			(Some(Some(from_entry)), Some(Some(to_entry)))
+					if from_entry.inventory().is_some() && to_entry.inventory().is_some() => {
+				(from_entry.clone(), to_entry.clone())
+			},
+			_ => {
+				let nick = self.players.borrow()[&id].nick.clone();
+				println!("Rejected move of {} from chest at {:?} to chest at {:?}: no chest present",
+					nick, from_p, to_p);
+				// Send the actual contents, or the absence of a chest
+				self.send_chest_meta_to_viewers(from_p);
+				self.send_chest_meta_to_viewers(to_p);
+				return;
+			},
+		};
+		{
+			let mut invs = [
+				from_entry.inventory_mut().unwrap() as &mut dyn InvRef,
+				to_entry.inventory_mut().unwrap() as &mut dyn InvRef,
+			];
+			inventory::merge_or_move(&mut invs, (0, from_stack), (1, to_stack), only_move_one);
+		}
+		// Both chunks are loaded as we got the entries above
+		self.map.get_blk_meta_mut(from_p).unwrap().set(from_entry);
+		self.map.get_blk_meta_mut(to_p).unwrap().set(to_entry);
+	}
+
+	fn handle_open_chest(&mut self, id :PlayerIdPair, p :Vector3<isize>) {
+		let has_inventory = match self.map.get_blk_meta(p) {
+			Some(Some(entry)) => entry.inventory().is_some(),
+			_ => false,
+		};
+		let reason = if !has_inventory {
+			Some("no chest present")
+		} else if !interaction::in_reach(self.players.borrow()[&id].pos(), p) {
+			Some("out of reach")
+		} else if !self.may_access_chest(id, p) {
+			Some("protected")
+		} else {
+			None
+		};
+		if let Some(reason) = reason {
+			let nick = self.players.borrow()[&id].nick.clone();
+			println!("Rejected opening of chest by {} at {:?}: {}", nick, p, reason);
+			self.send_msg_to(id, ServerToClientMsg::SetChestMeta(p, None));
+			return;
+		}
+		self.players.borrow_mut().get_mut(&id).unwrap()
+			.open_chests.insert(p);
+		let meta = self.map.get_blk_meta(p).unwrap().cloned();
+		self.send_msg_to(id, ServerToClientMsg::SetChestMeta(p, meta));
+	}
 
-		let recipe = get_matching_recipe(&player.craft_inventory, &self.params.p);
-		let output = recipe.map(|r| r.output);
+	pub fn handle_craft(&mut self, id :PlayerIdPair, max :bool) {
+		let (leftover, pos) = {
+			let mut players = self.players.borrow_mut();
+			let player = &mut players.get_mut(&id).unwrap();
 
-		if let Some(output) = output {
-			player.inventory.put(output);
-			// Reduce inputs of input inv.
-			for st in player.craft_inventory.stacks_mut().iter_mut() {
-				st.take_n(1);
-			}
+			let leftover = crafting::craft_into(&mut player.craft_inventory,
+				&mut player.inventory, &self.params.p, max);
+			(leftover, player.pos())
+		};
+		self.drop_stack(pos, leftover);
+	}
+	/// Reads commands from the input, e.g. stdin,
+	/// and runs them with all privileges
+	pub fn enable_console(&mut self, input :impl BufRead + Send + 'static) {
+		self.console = Some(console::spawn_reader(input));
+	}
+	fn handle_console_input(&mut self) {
+		let lines = self.console.iter()
+			.flat_map(|console| console.try_iter())
+			.collect::<Vec<_>>();
+		for line in lines {
+			self.handle_command(console_id(), line);
 		}
 	}
+	/// Runs the server until it's shut down with /shutdown
+	///
+	/// Between ticks, the server sleeps, but it wakes
+	/// up early to handle the messages of clients.
 	pub fn run_loop(&mut self) {
-		loop {
-			self.tick();
+		while !self.shutdown_requested {
+			self.step();
+			let timeout = self.ticks.time_until_due(self.clock.now());
+			if timeout > Duration::from_secs(0) {
+				self.srv_socket.wait_for_msgs(timeout);
+			}
+		}
+		self.shutdown();
+	}
+	/// Runs a tick if one is due, then handles
+	/// the messages that have arrived
+	pub fn step(&mut self) {
+		if self.ticks.take_due(self.clock.now()) {
+			self.tick(self.ticks.interval().as_secs_f32());
 		}
+		self.handle_msgs();
 	}
-	fn tick(&mut self) {
+	fn shutdown(&mut self) {
+		self.store_player_positions_now().unwrap();
+		self.store_player_inventories().unwrap();
+		self.send_msg_to_all(ServerToClientMsg::Disconnect("Server shutting down".to_owned()));
+		self.map.flush();
+		println!("Server shut down");
+	}
+	/// Advances the game by the given number of seconds
+	fn tick(&mut self, float_delta :f32) {
 		let positions = self.players.borrow().iter()
 			.map(|(_, player)| {
 				(btchn(player.pos.pos().map(|v| v as isize)), player.last_chunk_pos)
@@ -976,10 +2762,21 @@ impl<S :NetworkServerSocket> Server<S> {
 				self.config.mapgen_radius_xy,
 				self.config.mapgen_radius_z);
 		}
+		self.handle_pending_teleports();
 		self.send_chunks_to_players();
 		self.send_positions_to_players();
+		self.handle_item_entities();
+		self.handle_health();
+		self.handle_block_updates();
+		self.handle_abms();
+		self.handle_furnaces();
 		self.map.tick();
-		let _float_delta = self.update_fps();
+		self.store_map_changes();
+		self.handle_time_of_day(float_delta);
+	}
+	/// Handles new connections and the messages
+	/// from clients and the console
+	fn handle_msgs(&mut self) {
 		while let Some(conn) = self.srv_socket.try_open_conn() {
 			if self.is_singleplayer {
 				let id = PlayerIdPair::singleplayer();
@@ -989,6 +2786,8 @@ impl<S :NetworkServerSocket> Server<S> {
 			}
 		}
 		self.handle_auth_msgs();
+		self.handle_console_input();
+		self.handle_spawn_search();
 		self.handle_players_waiting_for_kv();
 		self.store_player_kvs().unwrap();
 
@@ -1017,75 +2816,50 @@ impl<S :NetworkServerSocket> Server<S> {
 						close_connections(&[id], &mut *self.players.borrow_mut());
 					}
 				},
-				PlaceBlock(p, sel_idx, b) => {
-					// Block to make the borrow_mut work
-					{
-						let players = &mut self.players.borrow_mut();
-						let player = players.get_mut(&id).unwrap();
-						let sel = player.inventory.get_sel_idx_and_content();
-						if Some((sel_idx, b)) != sel {
-							// TODO log something about selected inventory mismatch
-							// between client and server
-
-							// TODO Maybe send msg to the client
-							// that the block placing failed??
-							continue;
-						}
-						player.inventory.take_selected();
-						// Don't send anything to the client, its
-						// prediction was alright.
-					};
-					let mut block_set = false;
-					if let Some(mut hdl) = self.map.get_blk_mut(p) {
-						// TODO check if air
-						hdl.set(b);
-						block_set = true;
-					} else {
-						// TODO log something about an attempted action in an unloaded chunk
-					}
-					let has_inv = self.params.p.get_block_params(b).unwrap().inventory;
-					if let (Some(stack_num), true) = (has_inv, block_set) {
-						if let Some(mut hdl) = self.map.get_blk_meta_mut(p) {
-							let inv = SelectableInventory::empty_with_size(stack_num as usize);
-							hdl.set(MetadataEntry::Inventory(inv));
-						} else {
-							// TODO log error about an attempted action in an unloaded chunk
-							// It needs to be an ERROR because we should already have
-							// set the block above successfully so if we can't set it now
-							// again, there is some problem...
-						}
-					}
+				PlaceBlock(p, sel_idx, b, facedir) => {
+					self.handle_place(id, p, sel_idx, b, facedir);
 				},
 				PlaceTree(p, sel_idx, b) => {
-					// Block to make the borrow_mut work
-					{
-						let players = &mut self.players.borrow_mut();
-						let player = players.get_mut(&id).unwrap();
-						let sel = player.inventory.get_sel_idx_and_content();
-						if Some((sel_idx, b)) != sel {
-							// TODO log something about selected inventory mismatch
-							// between client and server
-
-							// TODO Maybe send msg to the client
-							// that the block placing failed??
-							continue;
-						}
-						player.inventory.take_selected();
-						// Don't send anything to the client, its
-						// prediction was alright.
-					};
+					if let Err(reason) = self.check_place(id, p, sel_idx, b) {
+						self.reject_place(id, p, reason);
+						continue;
+					}
 					let on_place_plants_tree = self.params.p.get_block_params(b).unwrap().on_place_plants_tree;
 					if !on_place_plants_tree {
-						// TODO log something about on_place_plants_tree not being set
+						self.reject_place(id, p, "block doesn't plant trees");
+						continue;
+					}
+					let schematic = &self.params.p.schematics.tree_schematic;
+					let (min, max) = (p + schematic.aabb_min, p + schematic.aabb_max);
+					let allowed = self.claims.allows_area(id, min, max, ClaimFlag::Build)
+						|| self.has_priv(id, privs::PROTECTION);
+					if !allowed {
+						self.reject_place(id, p, "tree would grow into protected area");
 						continue;
 					}
+					self.players.borrow_mut().get_mut(&id).unwrap()
+						.inventory.take_selected();
+					// Don't send anything to the client, its
+					// prediction was alright.
 					map::spawn_tree(&mut self.map, p, &self.params);
 				},
+				StartDig(p) => {
+					let now = self.clock.now();
+					let mut players = self.players.borrow_mut();
+					let player = players.get_mut(&id).unwrap();
+					player.digging = Some((p, now));
+				},
 				Dig(p) => {
 					self.handle_dig(id, p);
 				},
 				SetPos(_p) => unreachable!(),
 				SetMode(mode, enabled) => {
+					if enabled && !self.check_priv(id, privs::priv_for_mode(mode)) {
+						// Override the client's prediction
+						let modes = self.players.borrow()[&id].slow_states.modes.clone();
+						self.send_msg_to(id, ServerToClientMsg::SetModes(modes));
+						continue;
+					}
 					let mut players = self.players.borrow_mut();
 					let player = &mut players.get_mut(&id).unwrap();
 					if enabled {
@@ -1098,8 +2872,25 @@ impl<S :NetworkServerSocket> Server<S> {
 				InventorySwap(from_pos, to_pos, only_move_one) => {
 					self.handle_inv_move_or_swap(id, from_pos, to_pos, only_move_one);
 				},
+				OpenChest(p) => {
+					self.handle_open_chest(id, p);
+				},
+				CompleteCommand(line) => {
+					self.handle_complete_command(id, line);
+				},
+				CloseChest(p) => {
+					let mut players = self.players.borrow_mut();
+					let player = players.get_mut(&id).unwrap();
+					player.open_chests.remove(&p);
+				},
 				Craft => {
-					self.handle_craft(id);
+					self.handle_craft(id, false);
+				},
+				CraftMax => {
+					self.handle_craft(id, true);
+				},
+				DropSelected => {
+					self.handle_drop_selected(id);
 				},
 				InventorySelect(selection) => {
 					let mut players = self.players.borrow_mut();
@@ -1111,22 +2902,361 @@ impl<S :NetworkServerSocket> Server<S> {
 				Chat(m) => {
 					if m.starts_with('/') {
 						self.handle_command(id, m);
-					} else {
+					} else if self.check_may_chat(id) {
 						let m = {
 							let nick = &self.players.borrow()[&id].nick;
 							format!("<{}> {}", nick, m)
 						};
-						self.handle_chat_msg(m);
+						self.handle_chat_msg(ChatKind::Public, m);
 					}
 				},
+				SetSignText(p, text) => {
+					self.handle_set_sign_text(id, p, text);
+				},
 			}
 		}
 	}
 }
 
+fn server_commands<S :NetworkServerSocket>() -> CommandRegistry<Server<S>> {
+	let mut reg = CommandRegistry::new();
+	reg.register(Command {
+		name : "help",
+		description : "Lists the commands, or describes the given command.",
+		args : vec![opt("command", ArgKind::Word)],
+		privilege : None,
+		needs_player : false,
+		handler : Server::handle_help_command,
+	});
+	reg.register(Command {
+		name : "info",
+		description : "Prints the name and version of the server.",
+		args : Vec::new(),
+		privilege : None,
+		needs_player : false,
+		handler : Server::handle_info_command,
+	});
+	reg.register(Command {
+		name : "spawn",
+		description : "Teleports you to spawn.",
+		args : Vec::new(),
+		privilege : Some(privs::TELEPORT),
+		needs_player : true,
+		handler : Server::handle_spawn_command,
+	});
+	reg.register(Command {
+		name : "setspawn",
+		description : "Sets the spawn position to your position.",
+		args : Vec::new(),
+		privilege : Some(privs::SERVER),
+		needs_player : true,
+		handler : Server::handle_setspawn_command,
+	});
+	reg.register(Command {
+		name : "tp",
+		description : "Teleports you to the coordinates.",
+		args : vec![
+			arg("x", ArgKind::Coordinate),
+			arg("y", ArgKind::Coordinate),
+			arg("z", ArgKind::Coordinate),
+		],
+		privilege : Some(privs::TELEPORT),
+		needs_player : true,
+		handler : Server::handle_tp_pos_command,
+	});
+	reg.register(Command {
+		name : "tp",
+		description : "Teleports you to the player.",
+		args : vec![arg("player", ArgKind::Player)],
+		privilege : Some(privs::TELEPORT),
+		needs_player : true,
+		handler : Server::handle_tp_command,
+	});
+	reg.register(Command {
+		name : "tp",
+		description : "Teleports the first player to the second. Needs the bring privilege as well.",
+		args : vec![arg("player", ArgKind::Player), arg("other player", ArgKind::Player)],
+		privilege : Some(privs::TELEPORT),
+		needs_player : false,
+		handler : Server::handle_tp_command,
+	});
+	reg.register(Command {
+		name : "sethome",
+		description : "Sets your home to your position.",
+		args : Vec::new(),
+		privilege : Some(privs::HOME),
+		needs_player : true,
+		handler : Server::handle_sethome_command,
+	});
+	reg.register(Command {
+		name : "home",
+		description : "Teleports you to your home.",
+		args : Vec::new(),
+		privilege : Some(privs::HOME),
+		needs_player : true,
+		handler : Server::handle_home_command,
+	});
+	reg.register(Command {
+		name : "where",
+		description : "Prints your position or that of the player. \
+			Locating others needs the teleport privilege.",
+		args : vec![opt("player", ArgKind::Player)],
+		privilege : None,
+		needs_player : false,
+		handler : Server::handle_where_command,
+	});
+	reg.register(Command {
+		name : "gime",
+		description : "Gives you the item.",
+		args : vec![arg("item", ArgKind::Item), opt("count", ArgKind::Number(u16::MAX as u32))],
+		privilege : Some(privs::GIVE),
+		needs_player : true,
+		handler : Server::handle_gime_command,
+	});
+	reg.register(Command {
+		name : "clear",
+		description : "Clears the selected stack or your whole inventory.",
+		args : vec![arg("what", ArgKind::Choice(&["selection", "sel", "inventory", "inv"]))],
+		privilege : Some(privs::GIVE),
+		needs_player : true,
+		handler : Server::handle_clear_command,
+	});
+	reg.register(Command {
+		name : "time",
+		description : "Prints the time of day, or sets it. Setting needs the settime privilege.",
+		args : vec![opt("HH:MM", ArgKind::Word)],
+		privilege : None,
+		needs_player : false,
+		handler : Server::handle_time_command,
+	});
+	reg.register(Command {
+		name : "privs",
+		description : "Lists the privileges of the player or of yourself.",
+		args : vec![opt("player", ArgKind::Player)],
+		privilege : None,
+		needs_player : false,
+		handler : Server::handle_privs_command,
+	});
+	reg.register(Command {
+		name : "grant",
+		description : "Grants a privilege, or all of them, to the player.",
+		args : vec![arg("player", ArgKind::Player), arg("privilege", ArgKind::Privilege)],
+		privilege : Some(privs::PRIVS),
+		needs_player : false,
+		handler : |srv, id, params| srv.handle_grant_revoke_command(id, params, true),
+	});
+	reg.register(Command {
+		name : "revoke",
+		description : "Revokes a privilege, or all of them, from the player.",
+		args : vec![arg("player", ArgKind::Player), arg("privilege", ArgKind::Privilege)],
+		privilege : Some(privs::PRIVS),
+		needs_player : false,
+		handler : |srv, id, params| srv.handle_grant_revoke_command(id, params, false),
+	});
+	reg.register(Command {
+		name : "msg",
+		description : "Sends a private message to the player.",
+		args : vec![arg("player", ArgKind::Player), arg("text", ArgKind::Text)],
+		privilege : None,
+		needs_player : false,
+		handler : Server::handle_msg_command,
+	});
+	for name in ["who", "list"].iter() {
+		reg.register(Command {
+			name,
+			description : "Lists the players who are online.",
+			args : Vec::new(),
+			privilege : None,
+			needs_player : false,
+			handler : Server::handle_who_command,
+		});
+	}
+	reg.register(Command {
+		name : "mute",
+		description : "Prevents the player from chatting, for the given duration like 10m, or until unmuted.",
+		args : vec![arg("player", ArgKind::Player), opt("duration", ArgKind::Duration)],
+		privilege : Some(privs::MUTE),
+		needs_player : false,
+		handler : |srv, id, params| srv.handle_mute_command(id, params, true),
+	});
+	reg.register(Command {
+		name : "unmute",
+		description : "Allows the player to chat again.",
+		args : vec![arg("player", ArgKind::Player)],
+		privilege : Some(privs::MUTE),
+		needs_player : false,
+		handler : |srv, id, params| srv.handle_mute_command(id, params, false),
+	});
+	reg.register(Command {
+		name : "say",
+		description : "Sends a message to all players.",
+		args : vec![arg("text", ArgKind::Text)],
+		privilege : Some(privs::SERVER),
+		needs_player : false,
+		handler : Server::handle_say_command,
+	});
+	reg.register(Command {
+		name : "kick",
+		description : "Disconnects the player.",
+		args : vec![arg("player", ArgKind::Player), opt("reason", ArgKind::Text)],
+		privilege : Some(privs::SERVER),
+		needs_player : false,
+		handler : Server::handle_kick_command,
+	});
+	reg.register(Command {
+		name : "shutdown",
+		description : "Saves everything and shuts down the server.",
+		args : Vec::new(),
+		privilege : Some(privs::SERVER),
+		needs_player : false,
+		handler : Server::handle_shutdown_command,
+	});
+	reg.register(Command {
+		name : "chest",
+		description : "Lists the owner and members of the locked chest you point at, \
+			or adds or removes a member.",
+		args : vec![opt("action", ArgKind::Choice(&["add", "remove"])), opt("player", ArgKind::Player)],
+		privilege : None,
+		needs_player : true,
+		handler : Server::handle_chest_command,
+	});
+	let claim_id = || arg("id", ArgKind::Number(u32::MAX));
+	let claim_forms :[(&[&str], &str, Vec<Arg>, Handler<Server<S>>); 6] = [
+		(&["pos1", "pos2"], "Sets a corner of a new claim to the block you point at, or to your position.",
+			Vec::new(), Server::handle_claim_corner_command),
+		(&["create"], "Claims the area between the two corners.",
+			Vec::new(), Server::handle_claim_create_command),
+		(&["list"], "Lists your claims and the claims at your position.",
+			Vec::new(), Server::handle_claim_list_command),
+		(&["remove"], "Removes the claim.",
+			vec![claim_id()], Server::handle_claim_remove_command),
+		(&["share", "unshare"], "Adds or removes a member of the claim.",
+			vec![claim_id(), arg("player", ArgKind::Player)], Server::handle_claim_share_command),
+		(&["flag"], "Allows everyone to build, or to access chests, inside the claim.",
+			vec![claim_id(), arg("flag", ArgKind::Choice(ClaimFlag::NAMES)),
+				arg("value", ArgKind::Choice(&["on", "off"]))],
+			Server::handle_claim_flag_command),
+	];
+	for (subcommands, description, args, handler) in claim_forms.iter() {
+		reg.register(Command {
+			name : "claim",
+			description : *description,
+			args : std::iter::once(arg("action", ArgKind::Choice(*subcommands)))
+				.chain(args.iter().cloned())
+				.collect(),
+			privilege : None,
+			needs_player : true,
+			handler : *handler,
+		});
+	}
+	reg
+}
+
 fn close_connections(conns_to_close :&[PlayerIdPair], connections :&mut HashMap<PlayerIdPair, impl Sized>) {
 	for id in conns_to_close.iter() {
 		println!("closing connection");
 		connections.remove(&id);
 	}
 }
+
+#[cfg(test)]
+#[test]
+fn test_server_ticks_with_manual_clock() {
+	use mimas_common::generic_net::{MpscServerSocket, NetworkClientConn};
+	use crate::clock::ManualClock;
+
+	let (server_socket, mut client_conn) = MpscServerSocket::new();
+	let config = Config {
+		spawn_pos : Some([0.0, 0.0, 20.0]),
+		.. Config::default()
+	};
+	let clock = ManualClock::default();
+	let mut server = Server::with_asset_dir(server_socket, true, config, clock.clone(),
+		&crate::game_params::test_asset_dir());
+	let interval = server.ticks.interval();
+	let start_time = server.time_of_day;
+	let time_speed = server.config.time_speed;
+	let after_ticks = |ticks :u32| (0 .. ticks).fold(start_time, |time, _| {
+		time_of_day::advance(time, interval.as_secs_f32(), time_speed)
+	});
+
+	// The first tick is due right away, the next only once the clock advanced
+	server.step();
+	assert_eq!(server.time_of_day, after_ticks(1));
+	server.step();
+	assert_eq!(server.time_of_day, after_ticks(1));
+	clock.advance(interval);
+	server.step();
+	assert_eq!(server.time_of_day, after_ticks(2));
+
+	// The time of day gets synced to the client after a while
+	let synced = |client_conn :&mut dyn NetworkClientConn| {
+		std::iter::from_fn(|| client_conn.try_recv().unwrap())
+			.any(|msg| matches!(msg, ServerToClientMsg::SetTimeOfDay(..)))
+	};
+	// The player joins once the map storage has answered,
+	// and joining sends the time of day as well
+	for _ in 0 .. 1000 {
+		if !server.players.borrow().is_empty() {
+			break;
+		}
+		thread::sleep(Duration::from_millis(1));
+		server.step();
+	}
+	assert!(synced(&mut client_conn));
+	clock.advance(Duration::from_secs(5));
+	server.step();
+	assert!(!synced(&mut client_conn));
+	clock.advance(Duration::from_secs(5));
+	server.step();
+	assert!(synced(&mut client_conn));
+}
+
+#[cfg(test)]
+#[test]
+fn test_damaging_blocks() {
+	use mimas_common::game_params::Id;
+	use mimas_common::generic_net::MpscServerSocket;
+	use std::sync::Arc;
+	use crate::clock::ManualClock;
+
+	let (server_socket, _client_conn) = MpscServerSocket::new();
+	let config = Config {
+		spawn_pos : Some([0.0, 0.0, 20.0]),
+		mapgen_radius_xy : 1,
+		mapgen_radius_z : 1,
+		.. Config::default()
+	};
+	let clock = ManualClock::default();
+	let mut server = Server::with_asset_dir(server_socket, true, config, clock.clone(),
+		&crate::game_params::test_asset_dir());
+	// No block in the default game params damages players, so make one
+	let mut params = crate::game_params::test_game_params();
+	let rose = params.p.search_block_name("flowers:rose").unwrap();
+	params.p.block_params[rose.id() as usize].damage_per_second = 3;
+	server.params = Arc::new(params);
+
+	// Wait until the player has joined and the chunk at them is loaded
+	let mut joined = None;
+	for _ in 0 .. 10_000 {
+		joined = server.players.borrow().iter()
+			.map(|(id, player)| (*id, player.pos().map(|v| v.floor() as isize)))
+			.next()
+			.filter(|(_id, pos)| server.map.get_blk(*pos).is_some());
+		if joined.is_some() {
+			break;
+		}
+		thread::sleep(Duration::from_millis(1));
+		clock.advance(server.ticks.interval());
+		server.step();
+	}
+	let (id, pos) = joined.unwrap();
+	server.map.get_blk_mut(pos).unwrap().set(rose);
+
+	// The damage is applied once per second
+	server.step();
+	assert_eq!(server.players.borrow()[&id].hp, MAX_HP);
+	clock.advance(Duration::from_secs(1));
+	server.step();
+	assert_eq!(server.players.borrow()[&id].hp, MAX_HP - 3);
+}